@@ -0,0 +1,2 @@
+ALTER TABLE oauth_client
+    ADD skip_consent boolean DEFAULT false NOT NULL;
@@ -0,0 +1,2 @@
+ALTER TABLE oauth_client
+    ADD require_2fa boolean DEFAULT false NOT NULL;
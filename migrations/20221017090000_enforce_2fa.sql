@@ -0,0 +1,9 @@
+ALTER TABLE "group"
+    ADD enforce_2fa boolean;
+
+CREATE TABLE user_2fa_grace
+(
+    username       varchar PRIMARY KEY,
+    first_login_at timestamptz DEFAULT now() NOT NULL,
+    login_count    integer     DEFAULT 0     NOT NULL
+);
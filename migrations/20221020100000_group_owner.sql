@@ -0,0 +1,10 @@
+CREATE TABLE group_owner
+(
+    group_id INTEGER NOT NULL,
+    CONSTRAINT fk_group_id
+        FOREIGN KEY (group_id)
+            REFERENCES "group" (id)
+            ON DELETE CASCADE,
+    username VARCHAR NOT NULL,
+    PRIMARY KEY (group_id, username)
+);
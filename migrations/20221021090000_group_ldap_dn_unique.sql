@@ -0,0 +1,37 @@
+-- Groups linked to the same LDAP group are merged into the oldest one, which takes over their
+-- permissions and owners. 2FA stays enforced if it was enforced on any of them. DNs are
+-- compared case-insensitively, like LDAP does.
+CREATE TEMPORARY TABLE group_merge AS
+SELECT id, min(id) OVER (PARTITION BY lower(ldap_dn)) AS keep_id
+FROM "group";
+
+DELETE FROM group_merge
+WHERE id = keep_id;
+
+UPDATE "group"
+SET enforce_2fa = TRUE
+FROM group_merge
+         JOIN "group" AS duplicate ON duplicate.id = group_merge.id
+WHERE "group".id = group_merge.keep_id
+  AND duplicate.enforce_2fa;
+
+UPDATE group_permission
+SET group_id = group_merge.keep_id
+FROM group_merge
+WHERE group_permission.group_id = group_merge.id;
+
+DELETE FROM group_permission
+WHERE id NOT IN (SELECT min(id) FROM group_permission GROUP BY client_id, group_id);
+
+INSERT INTO group_owner (group_id, username)
+SELECT group_merge.keep_id, group_owner.username
+FROM group_owner
+         JOIN group_merge ON group_owner.group_id = group_merge.id
+ON CONFLICT DO NOTHING;
+
+DELETE FROM "group"
+WHERE id IN (SELECT id FROM group_merge);
+
+DROP TABLE group_merge;
+
+CREATE UNIQUE INDEX group_ldap_dn_unique ON "group" (lower(ldap_dn));
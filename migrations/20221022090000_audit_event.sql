@@ -0,0 +1,15 @@
+CREATE TABLE audit_event
+(
+    id         BIGSERIAL PRIMARY KEY,
+    created_at timestamptz NOT NULL,
+    event_type varchar     NOT NULL,
+    actor      varchar,
+    subject    varchar,
+    ip_address varchar,
+    user_agent varchar,
+    payload    jsonb       NOT NULL
+);
+
+CREATE INDEX audit_event_event_type_idx ON audit_event (event_type);
+CREATE INDEX audit_event_actor_idx ON audit_event (actor);
+CREATE INDEX audit_event_subject_idx ON audit_event (subject);
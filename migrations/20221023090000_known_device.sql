@@ -0,0 +1,10 @@
+CREATE TABLE known_device
+(
+    username      varchar     NOT NULL,
+    device_id     varchar     NOT NULL,
+    ip_address    varchar,
+    user_agent    varchar,
+    first_seen_at timestamptz NOT NULL DEFAULT now(),
+    last_seen_at  timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (username, device_id)
+);
@@ -0,0 +1,24 @@
+CREATE TABLE service_account
+(
+    id          SERIAL PRIMARY KEY,
+    name        varchar     NOT NULL UNIQUE,
+    description varchar     NOT NULL DEFAULT '',
+    created_at  timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE TABLE api_key
+(
+    id                 uuid PRIMARY KEY     DEFAULT gen_random_uuid(),
+    service_account_id INTEGER     NOT NULL,
+    CONSTRAINT fk_service_account_id
+        FOREIGN KEY (service_account_id)
+            REFERENCES service_account (id)
+            ON DELETE CASCADE,
+    label              varchar     NOT NULL,
+    key_hash           varchar     NOT NULL UNIQUE,
+    scopes             varchar     NOT NULL,
+    created_at         timestamptz NOT NULL DEFAULT now(),
+    rotated_at         timestamptz,
+    last_used_at       timestamptz,
+    revoked_at         timestamptz
+);
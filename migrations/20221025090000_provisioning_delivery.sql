@@ -0,0 +1,19 @@
+CREATE TABLE provisioning_delivery
+(
+    id              BIGSERIAL PRIMARY KEY,
+    client_id       varchar     NOT NULL,
+    audit_event_id  bigint      NOT NULL,
+    CONSTRAINT fk_audit_event_id
+        FOREIGN KEY (audit_event_id)
+            REFERENCES audit_event (id)
+            ON DELETE CASCADE,
+    status          varchar     NOT NULL DEFAULT 'pending',
+    attempts        integer     NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL DEFAULT now(),
+    last_error      varchar,
+    created_at      timestamptz NOT NULL DEFAULT now(),
+    delivered_at    timestamptz
+);
+
+CREATE INDEX provisioning_delivery_pending_idx ON provisioning_delivery (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX provisioning_delivery_client_id_idx ON provisioning_delivery (client_id);
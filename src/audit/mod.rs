@@ -0,0 +1,299 @@
+use chrono::{SecondsFormat, Utc};
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::pool::PoolConnection;
+use sqlx::types::Json;
+use sqlx::Postgres;
+
+use crate::db::DBAuditEvent;
+use crate::error::Error;
+use crate::provisioning::Provisioning;
+use crate::sessions::Session;
+use sink::AuditSinks;
+
+pub(crate) mod sink;
+
+/// Version of the format events are exported in, raised on incompatible changes only. Fields
+/// may be added to events and payloads without raising it.
+pub(crate) const AUDIT_SCHEMA_VERSION: u32 = 1;
+
+/// Security-relevant events. The names are stored with every event and show up in exports,
+/// so they must never change. The subject of user events is the username, the subject of
+/// group events the group DN; the payload fields of each type are listed below.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AuditEventType {
+    /// `second_factor_required`: whether the login still needs a second factor.
+    LoginSucceeded,
+    /// No fields; the subject is the login as entered.
+    LoginFailed,
+    /// `method`: `totp` or `webauthn`; `credential_id` for TOTP.
+    SecondFactorSucceeded,
+    /// `method`: `totp` or `webauthn`.
+    SecondFactorFailed,
+    /// No fields.
+    PasswordChanged,
+    /// `reason`: `wrong_password` or `password_policy`.
+    PasswordChangeFailed,
+    /// `credential_id`.
+    WebauthnCredentialAdded,
+    /// `credential_id`.
+    TotpCredentialAdded,
+    /// `credential_id`.
+    CredentialDeleted,
+    /// `display_name`, `first_name`, `last_name`: the new names.
+    NameChanged,
+    /// `previous_email`, `email`.
+    EmailChanged,
+    /// `group_id`, `name`, `ldap_group_created`: whether the LDAP group was created as well.
+    GroupCreated,
+    /// `group_id`; `name`, `previous_name` and `previous_ldap_dn` if edited,
+    /// `ldap_group_recreated` if the missing LDAP group was created again.
+    GroupUpdated,
+    /// `group_id`, `name`, `ldap_group_deleted`.
+    GroupDeleted,
+    /// `group_id`, `added`, `removed`: lists of member usernames or DNs.
+    GroupMembersChanged,
+    /// `group_id`, `owner`.
+    GroupOwnerAdded,
+    /// `group_id`, `owner`.
+    GroupOwnerRemoved,
+    /// `group_id`, `enforce_2fa`: `true`, `false` or `null` for the default.
+    GroupTwoFactorPolicyChanged,
+    /// `client_id`, `scopes`, `automatic`: whether consent was given without asking.
+    ConsentAccepted,
+    /// `client_id`, `scopes`.
+    ConsentRejected,
+    /// `client_id`: the client whose consent was revoked, `null` if all consent and login
+    /// sessions were revoked; `reason`: the type of the event that caused the revocation, if
+    /// it was not requested.
+    SessionsRevoked,
+    /// `name`, `email`.
+    UserCreated,
+    /// No fields.
+    UserDeleted,
+    /// No fields.
+    UserDisabled,
+    /// No fields.
+    UserEnabled,
+    /// `service_account_id`, `description`; the subject is the name of the service account.
+    ServiceAccountCreated,
+    /// `service_account_id`.
+    ServiceAccountDeleted,
+    /// `service_account_id`, `api_key_id`, `label`, `scopes`.
+    ApiKeyCreated,
+    /// `service_account_id`, `api_key_id`.
+    ApiKeyRotated,
+    /// `service_account_id`, `api_key_id`.
+    ApiKeyRevoked,
+}
+
+impl AuditEventType {
+    pub(crate) const ALL: [AuditEventType; 30] = [
+        AuditEventType::LoginSucceeded,
+        AuditEventType::LoginFailed,
+        AuditEventType::SecondFactorSucceeded,
+        AuditEventType::SecondFactorFailed,
+        AuditEventType::PasswordChanged,
+        AuditEventType::PasswordChangeFailed,
+        AuditEventType::WebauthnCredentialAdded,
+        AuditEventType::TotpCredentialAdded,
+        AuditEventType::CredentialDeleted,
+        AuditEventType::NameChanged,
+        AuditEventType::EmailChanged,
+        AuditEventType::GroupCreated,
+        AuditEventType::GroupUpdated,
+        AuditEventType::GroupDeleted,
+        AuditEventType::GroupMembersChanged,
+        AuditEventType::GroupOwnerAdded,
+        AuditEventType::GroupOwnerRemoved,
+        AuditEventType::GroupTwoFactorPolicyChanged,
+        AuditEventType::ConsentAccepted,
+        AuditEventType::ConsentRejected,
+        AuditEventType::SessionsRevoked,
+        AuditEventType::UserCreated,
+        AuditEventType::UserDeleted,
+        AuditEventType::UserDisabled,
+        AuditEventType::UserEnabled,
+        AuditEventType::ServiceAccountCreated,
+        AuditEventType::ServiceAccountDeleted,
+        AuditEventType::ApiKeyCreated,
+        AuditEventType::ApiKeyRotated,
+        AuditEventType::ApiKeyRevoked,
+    ];
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            AuditEventType::LoginSucceeded => "login.succeeded",
+            AuditEventType::LoginFailed => "login.failed",
+            AuditEventType::SecondFactorSucceeded => "login.second_factor.succeeded",
+            AuditEventType::SecondFactorFailed => "login.second_factor.failed",
+            AuditEventType::PasswordChanged => "password.changed",
+            AuditEventType::PasswordChangeFailed => "password.change_failed",
+            AuditEventType::WebauthnCredentialAdded => "credential.webauthn.added",
+            AuditEventType::TotpCredentialAdded => "credential.totp.added",
+            AuditEventType::CredentialDeleted => "credential.deleted",
+            AuditEventType::NameChanged => "personal_data.name.changed",
+            AuditEventType::EmailChanged => "personal_data.email.changed",
+            AuditEventType::GroupCreated => "group.created",
+            AuditEventType::GroupUpdated => "group.updated",
+            AuditEventType::GroupDeleted => "group.deleted",
+            AuditEventType::GroupMembersChanged => "group.members.changed",
+            AuditEventType::GroupOwnerAdded => "group.owner.added",
+            AuditEventType::GroupOwnerRemoved => "group.owner.removed",
+            AuditEventType::GroupTwoFactorPolicyChanged => "group.2fa_policy.changed",
+            AuditEventType::ConsentAccepted => "consent.accepted",
+            AuditEventType::ConsentRejected => "consent.rejected",
+            AuditEventType::SessionsRevoked => "sessions.revoked",
+            AuditEventType::UserCreated => "user.created",
+            AuditEventType::UserDeleted => "user.deleted",
+            AuditEventType::UserDisabled => "user.disabled",
+            AuditEventType::UserEnabled => "user.enabled",
+            AuditEventType::ServiceAccountCreated => "service_account.created",
+            AuditEventType::ServiceAccountDeleted => "service_account.deleted",
+            AuditEventType::ApiKeyCreated => "service_account.api_key.created",
+            AuditEventType::ApiKeyRotated => "service_account.api_key.rotated",
+            AuditEventType::ApiKeyRevoked => "service_account.api_key.revoked",
+        }
+    }
+}
+
+/// An event as handed to the audit sinks.
+#[derive(Serialize)]
+pub(crate) struct ExportedAuditEvent {
+    schema_version: u32,
+    id: i64,
+    /// RFC 3339 in UTC.
+    timestamp: String,
+    #[serde(rename = "type")]
+    event_type: &'static str,
+    actor: Option<String>,
+    subject: Option<String>,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    payload: Value,
+}
+
+/// Who triggers events in a request and from where.
+pub(crate) struct AuditContext {
+    actor: Option<String>,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    sinks: AuditSinks,
+    provisioning: Provisioning,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuditContext {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<AuditContext, Self::Error> {
+        let actor = request
+            .guard::<Session>()
+            .await
+            .succeeded()
+            .map(|session| session.username);
+        Outcome::Success(AuditContext {
+            actor,
+            ip_address: request.client_ip().map(|ip| ip.to_string()),
+            user_agent: request.headers().get_one("User-Agent").map(str::to_owned),
+            sinks: request
+                .rocket()
+                .state::<AuditSinks>()
+                .cloned()
+                .unwrap_or_default(),
+            provisioning: request
+                .rocket()
+                .state::<Provisioning>()
+                .cloned()
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl AuditContext {
+    /// Records events as triggered by `actor`, for requests authenticated without a session.
+    pub(crate) fn with_actor(mut self, actor: &str) -> Self {
+        self.actor = Some(actor.to_owned());
+        self
+    }
+
+    /// Records an event concerning `subject`, a username or group DN, and queues it for the
+    /// audit sinks and the provisioned clients.
+    pub(crate) async fn record(
+        &self,
+        event_type: AuditEventType,
+        subject: &str,
+        payload: Value,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<(), Error> {
+        let event = self.event(event_type, subject, payload);
+        let id = DBAuditEvent::create_one(event.clone(), connection).await?;
+        self.provisioning
+            .enqueue(event_type, id, connection)
+            .await?;
+        self.sinks.publish(ExportedAuditEvent {
+            schema_version: AUDIT_SCHEMA_VERSION,
+            id,
+            timestamp: event
+                .created_at
+                .to_rfc3339_opts(SecondsFormat::Micros, true),
+            event_type: event_type.as_str(),
+            actor: event.actor,
+            subject: event.subject,
+            ip_address: event.ip_address,
+            user_agent: event.user_agent,
+            payload: event.payload.0,
+        });
+        Ok(())
+    }
+
+    /// Without a session or an explicit actor, as for failed logins, nobody is recorded as
+    /// having triggered the event.
+    fn event(&self, event_type: AuditEventType, subject: &str, payload: Value) -> DBAuditEvent {
+        DBAuditEvent {
+            id: None,
+            created_at: Utc::now(),
+            event_type: event_type.as_str().to_owned(),
+            actor: self.actor.clone(),
+            subject: Some(subject.to_owned()),
+            ip_address: self.ip_address.clone(),
+            user_agent: self.user_agent.clone(),
+            payload: Json(payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn context(actor: Option<&str>) -> AuditContext {
+        AuditContext {
+            actor: actor.map(str::to_owned),
+            ip_address: Some("192.0.2.1".to_owned()),
+            user_agent: None,
+            sinks: AuditSinks::default(),
+            provisioning: Provisioning::default(),
+        }
+    }
+
+    #[test]
+    fn failed_logins_have_no_actor() {
+        let event = context(None).event(AuditEventType::LoginFailed, "jdoe", json!({}));
+        assert_eq!(event.event_type, "login.failed");
+        assert_eq!(event.actor, None);
+        assert_eq!(event.subject.as_deref(), Some("jdoe"));
+        assert_eq!(event.ip_address.as_deref(), Some("192.0.2.1"));
+    }
+
+    #[test]
+    fn events_are_attributed_to_the_actor() {
+        let event = context(Some("admin")).event(AuditEventType::UserDeleted, "jdoe", json!({}));
+        assert_eq!(event.actor.as_deref(), Some("admin"));
+        assert_eq!(event.subject.as_deref(), Some("jdoe"));
+    }
+}
@@ -0,0 +1,246 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use rocket::fairing;
+use rocket::tokio::io::AsyncWriteExt;
+use rocket::tokio::net::{TcpStream, UdpSocket};
+use rocket::tokio::sync::mpsc;
+use rocket::{Build, Rocket};
+use sha2::Sha256;
+
+use crate::audit::ExportedAuditEvent;
+use crate::config::{AuditConfig, AuditSinkConfig, AuditSinkKind, SyslogTransport};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Queues of the configured sinks. Every sink delivers its events in order from a worker of
+/// its own, so a sink that is down neither blocks requests nor the other sinks.
+#[derive(Clone, Default)]
+pub(crate) struct AuditSinks {
+    queues: Arc<Vec<(String, mpsc::Sender<Arc<ExportedAuditEvent>>)>>,
+}
+
+impl AuditSinks {
+    pub(crate) fn publish(&self, event: ExportedAuditEvent) {
+        let event = Arc::new(event);
+        for (name, queue) in self.queues.iter() {
+            if queue.try_send(event.clone()).is_err() {
+                error!("Audit sink {} is full, dropping event {}", name, event.id);
+            }
+        }
+    }
+}
+
+enum Sink {
+    JsonLines {
+        path: String,
+    },
+    Syslog {
+        address: String,
+        transport: SyslogTransport,
+        facility: u8,
+        hostname: String,
+        tcp: Option<TcpStream>,
+    },
+    Webhook {
+        client: reqwest::Client,
+        url: String,
+        secret: String,
+    },
+}
+
+impl Sink {
+    fn new(kind: AuditSinkKind) -> Result<(String, Sink), reqwest::Error> {
+        Ok(match kind {
+            AuditSinkKind::JsonLines { path } => {
+                (format!("json_lines:{}", path), Sink::JsonLines { path })
+            }
+            AuditSinkKind::Syslog {
+                address,
+                transport,
+                facility,
+                hostname,
+            } => (
+                format!("syslog:{}", address),
+                Sink::Syslog {
+                    address,
+                    transport,
+                    facility,
+                    hostname: hostname.unwrap_or_else(|| "-".to_owned()),
+                    tcp: None,
+                },
+            ),
+            AuditSinkKind::Webhook {
+                url,
+                secret,
+                timeout,
+            } => (
+                format!("webhook:{}", url),
+                Sink::Webhook {
+                    client: reqwest::Client::builder()
+                        .timeout(Duration::from_secs(timeout))
+                        .build()?,
+                    url,
+                    secret,
+                },
+            ),
+        })
+    }
+
+    async fn deliver(
+        &mut self,
+        event: &ExportedAuditEvent,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let json = serde_json::to_string(event)?;
+        match self {
+            Sink::JsonLines { path } => {
+                let mut file = rocket::tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path.as_str())
+                    .await?;
+                file.write_all(format!("{}\n", json).as_bytes()).await?;
+                file.flush().await?;
+            }
+            Sink::Syslog {
+                address,
+                transport,
+                facility,
+                hostname,
+                tcp,
+            } => {
+                let message = syslog_message(*facility, hostname, event, &json);
+                match transport {
+                    SyslogTransport::Udp => {
+                        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                        socket.send_to(message.as_bytes(), address.as_str()).await?;
+                    }
+                    SyslogTransport::Tcp => {
+                        if tcp.is_none() {
+                            *tcp = Some(TcpStream::connect(address.as_str()).await?);
+                        }
+                        let framed = format!("{} {}", message.len(), message);
+                        if let Err(e) = tcp.as_mut().unwrap().write_all(framed.as_bytes()).await {
+                            // Reconnects on the next attempt.
+                            *tcp = None;
+                            return Err(e.into());
+                        }
+                    }
+                }
+            }
+            Sink::Webhook {
+                client,
+                url,
+                secret,
+            } => {
+                let timestamp = chrono::Utc::now().timestamp().to_string();
+                let signature = webhook_signature(secret, &timestamp, &json);
+                client
+                    .post(url.as_str())
+                    .header("Content-Type", "application/json")
+                    .header("X-Legitima-Timestamp", timestamp)
+                    .header("X-Legitima-Signature", signature)
+                    .body(json)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `X-Legitima-Signature` header of a webhook: `sha256=` and the hex HMAC-SHA256 of
+/// `{timestamp}.{body}` keyed with `secret`.
+pub(crate) fn webhook_signature(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Formats `json` as RFC 5424 message. Failures are logged with severity warning, everything
+/// else as notice; the event type is the MSGID.
+fn syslog_message(facility: u8, hostname: &str, event: &ExportedAuditEvent, json: &str) -> String {
+    let severity = if event.event_type.ends_with("failed") {
+        4
+    } else {
+        5
+    };
+    format!(
+        "<{}>1 {} {} legitima {} {} - \u{feff}{}",
+        u16::from(facility) * 8 + severity,
+        event.timestamp,
+        hostname,
+        std::process::id(),
+        event.event_type,
+        json
+    )
+}
+
+async fn run(
+    name: String,
+    mut sink: Sink,
+    max_attempts: u32,
+    mut queue: mpsc::Receiver<Arc<ExportedAuditEvent>>,
+) {
+    while let Some(event) = queue.recv().await {
+        let mut attempt = 1;
+        loop {
+            match sink.deliver(&event).await {
+                Ok(()) => break,
+                Err(e) if max_attempts != 0 && attempt >= max_attempts => {
+                    error!(
+                        "Dropping event {} after {} failed attempts to deliver it to audit sink {}: {}",
+                        event.id, attempt, name, e
+                    );
+                    break;
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to deliver event {} to audit sink {}: {}",
+                        event.id, name, e
+                    );
+                    // Backs off exponentially up to about four minutes.
+                    rocket::tokio::time::sleep(Duration::from_secs(1 << attempt.min(8))).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+fn start(
+    config: AuditSinkConfig,
+) -> Result<(String, mpsc::Sender<Arc<ExportedAuditEvent>>), reqwest::Error> {
+    let (name, sink) = Sink::new(config.kind)?;
+    let (sender, receiver) = mpsc::channel(config.queue_size.max(1));
+    rocket::tokio::spawn(run(name.clone(), sink, config.max_attempts, receiver));
+    Ok((name, sender))
+}
+
+pub(crate) async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    let config = match rocket.figment().focus("audit").extract::<AuditConfig>() {
+        Ok(config) => config,
+        Err(e) => {
+            rocket::config::pretty_print_error(e);
+            return Err(rocket);
+        }
+    };
+    let mut queues = Vec::new();
+    for sink_config in config.sinks {
+        match start(sink_config) {
+            Ok(queue) => queues.push(queue),
+            Err(e) => {
+                error!("Failed to set up audit sink: {}", e);
+                return Err(rocket);
+            }
+        }
+    }
+    Ok(rocket.manage(AuditSinks {
+        queues: Arc::new(queues),
+    }))
+}
@@ -8,6 +8,34 @@ pub(crate) struct HydraConfig {
     admin_endpoint_url: String,
     pub(crate) consent_remember_me: bool,
     pub(crate) consent_remember_me_for: i64,
+    #[serde(default)]
+    pub(crate) login_remember_me: bool,
+    /// Seconds Hydra remembers a login for. Hydra remembers it forever if this is 0.
+    #[serde(default = "default_login_remember_me_for")]
+    pub(crate) login_remember_me_for: i64,
+    #[serde(default)]
+    pub(crate) acr: AcrConfig,
+}
+
+fn default_login_remember_me_for() -> i64 {
+    86400
+}
+
+/// Authentication context class references reported to Hydra; clients may request
+/// `multi_factor` via `acr_values` to enforce a second factor.
+#[derive(Deserialize)]
+pub(crate) struct AcrConfig {
+    pub(crate) single_factor: String,
+    pub(crate) multi_factor: String,
+}
+
+impl Default for AcrConfig {
+    fn default() -> Self {
+        AcrConfig {
+            single_factor: "urn:legitima:acr:1fa".to_owned(),
+            multi_factor: "urn:legitima:acr:2fa".to_owned(),
+        }
+    }
 }
 
 impl HydraConfig {
@@ -50,13 +78,365 @@ impl WebauthnConfig for WebauthnStaticConfig {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub(crate) struct AppConfig {
     pub(crate) name: String,
     pub(crate) ldap_user_base_dn: String,
     pub(crate) ldap_groups_base_dn: String,
     pub(crate) ldap_admin_group_dn: String,
     pub(crate) ldap_root_dn: String,
+    #[serde(default)]
+    pub(crate) ldap_profile: LdapProfile,
+    #[serde(default)]
+    ldap_user: LdapUserConfig,
+    /// Group object class used on OpenLDAP; Active Directory always uses `group`.
+    #[serde(default)]
+    pub(crate) ldap_group_schema: LdapGroupSchema,
+    /// Resolves group memberships through the `memberOf` overlay instead of searching groups.
+    #[serde(default)]
+    pub(crate) ldap_member_of: bool,
+    /// Seconds directory lookups are served from memory, 0 disables the cache.
+    #[serde(default = "default_ldap_cache_ttl")]
+    pub(crate) ldap_cache_ttl: u64,
+    /// Entries per page requested with the Simple Paged Results control.
+    #[serde(default = "default_ldap_page_size")]
+    pub(crate) ldap_page_size: i32,
+    /// Seconds between checks for drift between legitima groups and LDAP, 0 disables them.
+    #[serde(default = "default_reconcile_interval")]
+    pub(crate) reconcile_interval: u64,
+    #[serde(default)]
+    pub(crate) enforce_2fa: bool,
+    #[serde(default)]
+    pub(crate) enforce_2fa_grace_logins: i32,
+    #[serde(default)]
+    pub(crate) enforce_2fa_grace_days: i64,
+}
+
+/// Directory flavour, which decides the object classes, how group membership is resolved and
+/// how passwords are changed.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LdapProfile {
+    #[default]
+    OpenLdap,
+    ActiveDirectory,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LdapGroupSchema {
+    /// `groupOfNames` listing member DNs in `member`.
+    #[default]
+    GroupOfNames,
+    /// `groupOfUniqueNames` listing member DNs in `uniqueMember`.
+    GroupOfUniqueNames,
+    /// `posixGroup` listing usernames in `memberUid`; these groups cannot be nested.
+    PosixGroup,
+}
+
+/// How users are found in the directory. Without a `search_filter` logins bind directly as
+/// `{username_attr}={login},{ldap_user_base_dn}`. With one, e.g. `(|(uid={0})(mail={0}))`, the
+/// login is searched for below `ldap_user_base_dn` first and the bind uses the DN found.
+/// Unset values fall back to the defaults of the configured `ldap_profile`.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub(crate) struct LdapUserConfig {
+    search_filter: Option<String>,
+    username_attr: Option<String>,
+    display_name_attr: Option<String>,
+    first_name_attr: Option<String>,
+    last_name_attr: Option<String>,
+    email_attr: Option<String>,
+}
+
+fn default_ldap_cache_ttl() -> u64 {
+    30
+}
+
+fn default_ldap_page_size() -> i32 {
+    500
+}
+
+fn default_reconcile_interval() -> u64 {
+    3600
+}
+
+/// LDAP servers and the service account legitima binds as.
+#[derive(Deserialize)]
+pub(crate) struct LdapConfig {
+    /// Primary servers in order of preference, e.g. `ldaps://ldap1.example.com`. Writes go to
+    /// the first reachable one.
+    pub(crate) urls: Vec<String>,
+    /// Servers only used for reads; without any, reads go to the primaries as well.
+    #[serde(default)]
+    pub(crate) replica_urls: Vec<String>,
+    /// Upgrades `ldap://` connections with StartTLS.
+    #[serde(default)]
+    pub(crate) starttls: bool,
+    /// PEM file of an additional CA to trust for LDAPS and StartTLS.
+    #[serde(default)]
+    pub(crate) ca_certificate: Option<String>,
+    #[serde(default)]
+    pub(crate) bind_dn: Option<String>,
+    #[serde(default)]
+    pub(crate) bind_password: String,
+    /// Seconds to wait for a server to accept a connection or answer a health check.
+    #[serde(default = "default_ldap_timeout")]
+    pub(crate) timeout: u64,
+    /// Seconds between health checks, which take failed servers back into rotation.
+    #[serde(default = "default_ldap_health_check_interval")]
+    pub(crate) health_check_interval: u64,
+}
+
+fn default_ldap_timeout() -> u64 {
+    5
+}
+
+fn default_ldap_health_check_interval() -> u64 {
+    30
+}
+
+/// Access to `/api/v1`. Besides users presenting an access token issued by Hydra, holders of
+/// one of the admin keys may use the admin endpoints.
+#[derive(Deserialize)]
+#[serde(default)]
+pub(crate) struct ApiConfig {
+    /// Hex encoded SHA-256 digests of the admin keys, e.g. from `echo -n $KEY | sha256sum`.
+    pub(crate) admin_key_hashes: Vec<String>,
+    /// Scope access tokens need to be granted to be accepted by the API, so tokens issued to
+    /// clients for other purposes cannot be replayed against it.
+    pub(crate) required_scope: String,
+    /// Clients whose access tokens are accepted; any client holding the scope if empty.
+    pub(crate) allowed_clients: Vec<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig {
+            admin_key_hashes: Vec::new(),
+            required_scope: "legitima:api".to_owned(),
+            allowed_clients: Vec::new(),
+        }
+    }
+}
+
+/// Where audit events are exported to besides the database, e.g.
+///
+/// ```toml
+/// [[default.audit.sinks]]
+/// type = "webhook"
+/// url = "https://siem.example.com/legitima"
+/// secret = "..."
+/// ```
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub(crate) struct AuditConfig {
+    pub(crate) sinks: Vec<AuditSinkConfig>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AuditSinkConfig {
+    #[serde(flatten)]
+    pub(crate) kind: AuditSinkKind,
+    /// Events held back while the sink is unreachable; further events are dropped.
+    #[serde(default = "default_audit_queue_size")]
+    pub(crate) queue_size: usize,
+    /// Delivery attempts per event before it is dropped, 0 retries forever.
+    #[serde(default = "default_audit_max_attempts")]
+    pub(crate) max_attempts: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum AuditSinkKind {
+    /// Appends one JSON object per line to `path`. The file is reopened for every event, so it
+    /// can be rotated.
+    JsonLines { path: String },
+    /// Sends RFC 5424 messages with the JSON event as message to `address`, e.g.
+    /// `127.0.0.1:514`. TCP uses octet counting framing (RFC 6587).
+    Syslog {
+        address: String,
+        #[serde(default)]
+        transport: SyslogTransport,
+        /// Facility number, defaults to authpriv.
+        #[serde(default = "default_syslog_facility")]
+        facility: u8,
+        #[serde(default)]
+        hostname: Option<String>,
+    },
+    /// POSTs the JSON event to `url`. The `X-Legitima-Signature` header carries
+    /// `sha256=` and the hex HMAC-SHA256 of `{X-Legitima-Timestamp}.{body}` keyed with `secret`.
+    Webhook {
+        url: String,
+        secret: String,
+        /// Seconds to wait for a response.
+        #[serde(default = "default_webhook_timeout")]
+        timeout: u64,
+    },
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SyslogTransport {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+fn default_audit_queue_size() -> usize {
+    10000
+}
+
+fn default_audit_max_attempts() -> u32 {
+    10
+}
+
+fn default_syslog_facility() -> u8 {
+    10
+}
+
+fn default_webhook_timeout() -> u64 {
+    10
+}
+
+/// Outgoing mail for security notifications. Without a `mail` section no mails are sent.
+#[derive(Deserialize)]
+pub(crate) struct MailConfig {
+    /// Sender, e.g. `legitima <noreply@example.com>`.
+    pub(crate) from: String,
+    #[serde(flatten)]
+    pub(crate) transport: MailTransportConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "transport", rename_all = "snake_case")]
+pub(crate) enum MailTransportConfig {
+    Smtp {
+        host: String,
+        /// Defaults to 587 for StartTLS, 465 for TLS and 25 without encryption.
+        #[serde(default)]
+        port: Option<u16>,
+        #[serde(default)]
+        tls: SmtpTls,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: String,
+    },
+    /// Writes every mail as `.eml` file into `directory` instead of sending it, for tests and
+    /// development.
+    File { directory: String },
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SmtpTls {
+    #[default]
+    Starttls,
+    Tls,
+    None,
+}
+
+/// Pushes changes of users and group members to the apps behind clients, so they do not only
+/// learn about them at the next login, e.g.
+///
+/// ```toml
+/// [[default.provisioning.targets]]
+/// client_id = "wiki"
+/// type = "scim"
+/// url = "https://wiki.example.com/scim/v2"
+/// token = "..."
+/// ```
+#[derive(Deserialize)]
+#[serde(default)]
+pub(crate) struct ProvisioningConfig {
+    pub(crate) targets: Vec<ProvisioningTargetConfig>,
+    /// Seconds between looking for due deliveries.
+    pub(crate) interval: u64,
+    /// Attempts per delivery before it is given up, 0 retries forever. Given up deliveries can
+    /// be retried in the admin area.
+    pub(crate) max_attempts: i32,
+}
+
+impl Default for ProvisioningConfig {
+    fn default() -> Self {
+        ProvisioningConfig {
+            targets: Vec::new(),
+            interval: 10,
+            max_attempts: 10,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ProvisioningTargetConfig {
+    /// The Hydra client whose app is provisioned.
+    pub(crate) client_id: String,
+    #[serde(flatten)]
+    pub(crate) kind: ProvisioningTargetKind,
+    /// Seconds to wait for a response.
+    #[serde(default = "default_webhook_timeout")]
+    pub(crate) timeout: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ProvisioningTargetKind {
+    /// Creates, updates and deletes users and groups at the SCIM 2.0 endpoint `url`,
+    /// authenticating with the bearer `token`. Resources are matched by `userName` and
+    /// `displayName`.
+    Scim { url: String, token: String },
+    /// POSTs every change to `url`, signed like the audit webhook with `secret`.
+    Webhook { url: String, secret: String },
+}
+
+impl AppConfig {
+    /// `groupOfNames` and `groupOfUniqueNames` must have at least one member.
+    pub(crate) fn ldap_groups_require_members(&self) -> bool {
+        self.ldap_profile == LdapProfile::OpenLdap
+            && self.ldap_group_schema != LdapGroupSchema::PosixGroup
+    }
+
+    pub(crate) fn ldap_user_search_filter(&self) -> Option<&str> {
+        match (&self.ldap_user.search_filter, self.ldap_profile) {
+            (Some(search_filter), _) => Some(search_filter),
+            (None, LdapProfile::OpenLdap) => None,
+            (None, LdapProfile::ActiveDirectory) => {
+                Some("(|(sAMAccountName={0})(userPrincipalName={0}))")
+            }
+        }
+    }
+
+    pub(crate) fn ldap_username_attr(&self) -> &str {
+        match (&self.ldap_user.username_attr, self.ldap_profile) {
+            (Some(attr), _) => attr,
+            (None, LdapProfile::OpenLdap) => "uid",
+            (None, LdapProfile::ActiveDirectory) => "sAMAccountName",
+        }
+    }
+
+    pub(crate) fn ldap_display_name_attr(&self) -> &str {
+        self.ldap_user
+            .display_name_attr
+            .as_deref()
+            .unwrap_or("displayName")
+    }
+
+    pub(crate) fn ldap_first_name_attr(&self) -> &str {
+        match (&self.ldap_user.first_name_attr, self.ldap_profile) {
+            (Some(attr), _) => attr,
+            (None, LdapProfile::OpenLdap) => "cn",
+            (None, LdapProfile::ActiveDirectory) => "givenName",
+        }
+    }
+
+    pub(crate) fn ldap_last_name_attr(&self) -> &str {
+        self.ldap_user.last_name_attr.as_deref().unwrap_or("sn")
+    }
+
+    pub(crate) fn ldap_email_attr(&self) -> &str {
+        self.ldap_user.email_attr.as_deref().unwrap_or("mail")
+    }
 }
 
 pub(crate) fn ad_hoc_config<'de, T>(sub_figment: &'static str) -> AdHoc
@@ -75,3 +455,21 @@ where
         Ok(rocket.manage(app_config))
     })
 }
+
+#[cfg(test)]
+impl AppConfig {
+    /// The settings every installation has, with `overrides` merged in.
+    pub(crate) fn for_tests(overrides: serde_json::Value) -> AppConfig {
+        let mut config = serde_json::json!({
+            "name": "legitima",
+            "ldap_user_base_dn": "ou=users,dc=example,dc=com",
+            "ldap_groups_base_dn": "ou=groups,dc=example,dc=com",
+            "ldap_admin_group_dn": "cn=admins,ou=groups,dc=example,dc=com",
+            "ldap_root_dn": "dc=example,dc=com",
+        });
+        for (key, value) in overrides.as_object().into_iter().flatten() {
+            config[key] = value.clone();
+        }
+        serde_json::from_value(config).unwrap()
+    }
+}
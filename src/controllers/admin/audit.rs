@@ -0,0 +1,124 @@
+use rocket::http::Status;
+use rocket::serde::Serialize;
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::Template;
+
+use crate::audit::AuditEventType;
+use crate::db::{DBAuditEvent, DB};
+use crate::directory::DEFAULT_PAGE_SIZE;
+use crate::error::Error;
+use crate::sessions::AdminUser;
+
+#[derive(Serialize)]
+pub(crate) struct ContextAuditEvent {
+    created_at: String,
+    event_type: String,
+    actor: Option<String>,
+    subject: Option<String>,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    payload: String,
+}
+
+/// One page of events, newest first. The total is not counted, so the page only tells whether
+/// there is a next one.
+#[derive(Serialize)]
+pub(crate) struct AuditEventPage {
+    events: Vec<ContextAuditEvent>,
+    page: usize,
+    has_next: bool,
+}
+
+impl AuditEventPage {
+    pub(crate) async fn load(
+        event_type: Option<&str>,
+        username: Option<&str>,
+        page: Option<usize>,
+        db: &mut Connection<DB>,
+    ) -> Result<AuditEventPage, Error> {
+        let page = page.unwrap_or(1).max(1);
+        let mut events = DBAuditEvent::list(
+            event_type,
+            username,
+            DEFAULT_PAGE_SIZE as i64 + 1,
+            ((page - 1) * DEFAULT_PAGE_SIZE) as i64,
+            &mut *db,
+        )
+        .await?;
+        let has_next = events.len() > DEFAULT_PAGE_SIZE;
+        events.truncate(DEFAULT_PAGE_SIZE);
+        Ok(AuditEventPage {
+            events: events
+                .into_iter()
+                .map(|event| ContextAuditEvent {
+                    created_at: event.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    event_type: event.event_type,
+                    actor: event.actor,
+                    subject: event.subject,
+                    ip_address: event.ip_address,
+                    user_agent: event.user_agent,
+                    payload: event.payload.0.to_string(),
+                })
+                .collect(),
+            page,
+            has_next,
+        })
+    }
+
+    /// Blanks where events came from unless `username` triggered them, so users do not learn
+    /// the addresses and browsers of the admins acting on their account.
+    pub(crate) fn hide_foreign_origins(&mut self, username: &str) {
+        for event in &mut self.events {
+            if event.actor.as_deref() != Some(username) {
+                event.ip_address = None;
+                event.user_agent = None;
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AuditLogContext {
+    event_types: Vec<&'static str>,
+    event_type: String,
+    user: String,
+    #[serde(flatten)]
+    events: AuditEventPage,
+}
+
+#[get("/audit", rank = 2)]
+pub(crate) async fn audit_log() -> Status {
+    Status::Forbidden
+}
+
+#[get("/audit?<event_type>&<user>&<page>")]
+pub(crate) async fn auth_audit_log(
+    _user: AdminUser,
+    event_type: Option<String>,
+    user: Option<String>,
+    page: Option<usize>,
+    mut db: Connection<DB>,
+) -> Result<Template, Error> {
+    let event_type = event_type.unwrap_or_default();
+    let user = user.unwrap_or_default();
+    let events = AuditEventPage::load(
+        Some(&event_type[..]).filter(|event_type| !event_type.is_empty()),
+        Some(&user[..]).filter(|user| !user.is_empty()),
+        page,
+        &mut db,
+    )
+    .await?;
+
+    Ok(Template::render(
+        "admin/audit",
+        AuditLogContext {
+            event_types: AuditEventType::ALL
+                .iter()
+                .map(|event_type| event_type.as_str())
+                .collect(),
+            event_type,
+            user,
+            events,
+        },
+    ))
+}
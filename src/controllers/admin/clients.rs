@@ -0,0 +1,189 @@
+use ory_hydra_client::apis::configuration::Configuration;
+use ory_hydra_client::models::OAuth2Client;
+use reqwest::header::{HeaderMap, LINK};
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::Template;
+
+use crate::config::HydraConfig;
+use crate::db::{DBOAuthClient, DB};
+use crate::error::Error;
+use crate::sessions::AdminUser;
+
+const CLIENT_PAGE_SIZE: i64 = 500;
+
+#[derive(Serialize)]
+struct ClientsContext {
+    clients: Vec<ContextClient>,
+}
+
+#[derive(Serialize)]
+struct ContextClient {
+    client_id: String,
+    client_name: String,
+    skip_consent: bool,
+    require_2fa: bool,
+}
+
+#[get("/clients", rank = 2)]
+pub(crate) async fn list_clients() -> Status {
+    Status::Forbidden
+}
+
+#[get("/clients")]
+pub(crate) async fn auth_list_clients(
+    _user: AdminUser,
+    hydra_config: &State<HydraConfig>,
+    mut db: Connection<DB>,
+) -> Result<Template, Error> {
+    let hydra_configuration: &Configuration = &hydra_config.inner().as_hydra_configuration();
+    let hydra_clients = list_hydra_clients(hydra_configuration).await?;
+    let db_clients = DBOAuthClient::list_all(&mut *db).await?;
+
+    Ok(Template::render(
+        "admin/clients",
+        ClientsContext {
+            clients: hydra_clients
+                .into_iter()
+                .filter_map(|hydra_client| {
+                    let client_id = hydra_client.client_id?;
+                    let db_client = db_clients.iter().find(|c| c.client_id == client_id);
+                    Some(ContextClient {
+                        client_name: hydra_client
+                            .client_name
+                            .filter(|name| !name.is_empty())
+                            .unwrap_or_else(|| client_id.clone()),
+                        skip_consent: db_client.map_or(false, |c| c.skip_consent),
+                        require_2fa: db_client.map_or(false, |c| c.require_2fa),
+                        client_id,
+                    })
+                })
+                .collect(),
+        },
+    ))
+}
+
+/// Lists all clients registered in Hydra. Hydra returns them in pages and links the next page
+/// in the `Link` header, which the generated client drops, so the pages are requested here.
+async fn list_hydra_clients(
+    hydra_configuration: &Configuration,
+) -> Result<Vec<OAuth2Client>, Error> {
+    let mut clients = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let mut request = hydra_configuration
+            .client
+            .get(format!("{}/admin/clients", hydra_configuration.base_path))
+            .query(&[("page_size", CLIENT_PAGE_SIZE)]);
+        if let Some(page_token) = &page_token {
+            request = request.query(&[("page_token", page_token)]);
+        }
+        let response = request.send().await.map_err(|_| Error::Hydra {
+            status: Status::ServiceUnavailable,
+        })?;
+        if !response.status().is_success() {
+            return Err(Error::Hydra {
+                status: match response.status().is_server_error() {
+                    true => Status::ServiceUnavailable,
+                    false => Status::BadRequest,
+                },
+            });
+        }
+        page_token = next_page_token(response.headers());
+        let body = response.text().await.map_err(|_| Error::Hydra {
+            status: Status::ServiceUnavailable,
+        })?;
+        let page: Vec<OAuth2Client> = serde_json::from_str(&body)?;
+        let last_page = page.is_empty() || page_token.is_none();
+        clients.extend(page);
+        if last_page {
+            return Ok(clients);
+        }
+    }
+}
+
+/// The `page_token` of the `rel="next"` link in the `Link` headers, if there is a next page.
+fn next_page_token(headers: &HeaderMap) -> Option<String> {
+    let target = headers
+        .get_all(LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find(|link| link.contains("rel=\"next\""))?
+        .split(';')
+        .next()?
+        .trim()
+        .strip_prefix('<')?
+        .strip_suffix('>')?
+        .to_owned();
+    // The target is usually relative to the admin endpoint.
+    url::Url::parse("http://hydra/")
+        .ok()?
+        .join(&target)
+        .ok()?
+        .query_pairs()
+        .find(|(key, _)| key == "page_token")
+        .map(|(_, value)| value.into_owned())
+}
+
+#[derive(FromForm)]
+pub(crate) struct ClientSettingsForm {
+    skip_consent: bool,
+    require_2fa: bool,
+}
+
+#[post("/clients/<client_id>", data = "<form>")]
+pub(crate) async fn auth_edit_client_form(
+    _user: AdminUser,
+    client_id: String,
+    form: Form<ClientSettingsForm>,
+    mut db: Connection<DB>,
+) -> Result<Redirect, Error> {
+    let form = form.into_inner();
+    let mut db_client = DBOAuthClient::find_by_client_id(&client_id, &mut *db)
+        .await?
+        .unwrap_or(DBOAuthClient {
+            client_id,
+            login_allowed: true,
+            skip_consent: false,
+            require_2fa: false,
+        });
+    db_client.skip_consent = form.skip_consent;
+    db_client.require_2fa = form.require_2fa;
+    DBOAuthClient::upsert_one(db_client, &mut *db).await?;
+    Ok(Redirect::to(uri!("/admin", auth_list_clients)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn next_page_token_follows_next_link() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            LINK,
+            HeaderValue::from_static(
+                "</admin/clients?page_size=500&page_token=MQ>; rel=\"first\",\
+                 </admin/clients?page_size=500&page_token=Mw>; rel=\"next\"",
+            ),
+        );
+        assert_eq!(next_page_token(&headers), Some("Mw".to_owned()));
+    }
+
+    #[test]
+    fn next_page_token_without_next_link() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(next_page_token(&headers), None);
+        headers.insert(
+            LINK,
+            HeaderValue::from_static("</admin/clients?page_size=500&page_token=MQ>; rel=\"first\""),
+        );
+        assert_eq!(next_page_token(&headers), None);
+    }
+}
@@ -1,20 +1,21 @@
-use ldap3::Mod;
 use rocket::form::validate::Contains;
 use rocket::form::{Contextual, Form};
 use rocket::http::{Cookie, CookieJar, Status};
 use rocket::response::Redirect;
-use rocket::serde::Serialize;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
 use rocket::{Either, State};
 use rocket_db_pools::Connection;
 use rocket_dyn_templates::Template;
+use serde_json::json;
 use std::collections::{HashMap, HashSet};
 
+use crate::audit::{AuditContext, AuditEventType};
 use crate::config::AppConfig;
-use crate::db::{DBGroup, DB};
+use crate::db::{DBGroup, DBGroupOwner, DB};
+use crate::directory::{Directory, Group, LdapDirectory, DEFAULT_PAGE_SIZE};
 use crate::error::Error;
-use crate::ldap::{add_dn, change_attrs, get_all_groups, get_all_users, get_group_members};
 use crate::sessions::AdminUser;
-use crate::DBLdapConn;
 
 #[derive(Serialize)]
 struct GroupsContext {
@@ -27,7 +28,57 @@ struct ContextGroup {
     name: String,
     ldap_dn: String,
     members: Option<Vec<String>>,
-    user_member_mapping: Option<Vec<(String, bool)>>,
+    ldap_missing: bool,
+    enforce_2fa: Option<bool>,
+}
+
+/// Member checkboxes of the group edit pages, listing one page of users.
+#[derive(Serialize)]
+pub(crate) struct MemberPicker {
+    user_member_mapping: Vec<(String, bool)>,
+    version: String,
+    query: String,
+    page: usize,
+    pages: usize,
+}
+
+impl MemberPicker {
+    pub(crate) async fn load(
+        directory: &dyn Directory,
+        group: &Group,
+        query: Option<String>,
+        page: Option<usize>,
+    ) -> Result<MemberPicker, Error> {
+        let query = query.unwrap_or_default();
+        let users_page = directory
+            .search_users(&query, page.unwrap_or(1), DEFAULT_PAGE_SIZE)
+            .await?;
+        Ok(MemberPicker {
+            user_member_mapping: users_page
+                .items
+                .into_iter()
+                .map(|user| {
+                    let is_member = group.members.contains(&user.dn);
+                    (user.dn, is_member)
+                })
+                .collect(),
+            version: group.version(),
+            query,
+            page: users_page.page,
+            pages: users_page.pages,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct EditGroupContext {
+    #[serde(flatten)]
+    group: ContextGroup,
+    #[serde(flatten)]
+    member_picker: MemberPicker,
+    owners: Vec<String>,
+    ldap_owners: Vec<String>,
+    message: Option<String>,
 }
 
 #[get("/groups", rank = 2)]
@@ -38,89 +89,492 @@ pub(crate) async fn list_groups(cookies: &CookieJar<'_>) -> Status {
 #[get("/groups")]
 pub(crate) async fn auth_list_groups(
     _user: AdminUser,
-    app_config: &State<AppConfig>,
-    ldap_conn: DBLdapConn,
+    directory: LdapDirectory<'_>,
     mut db: Connection<DB>,
 ) -> Result<Template, Error> {
-    let app_config = app_config.inner();
-    let groups = DBGroup::list_all(&mut *db).await?;
-    let ldap_groups = get_all_groups(app_config, &ldap_conn).await?;
+    let mut groups = Vec::new();
+    // Only the groups known to legitima are looked up instead of listing the whole directory.
+    for db_group in DBGroup::list_all(&mut *db).await? {
+        let (members, ldap_missing) = match directory.get_group(&db_group.ldap_dn).await {
+            Ok(ldap_group) => (ldap_group.members, false),
+            Err(Error::Http(status)) if status == Status::NotFound => (Vec::new(), true),
+            Err(error) => return Err(error),
+        };
+        groups.push(ContextGroup {
+            id: db_group.id.unwrap(),
+            name: db_group.name,
+            members: Some(members),
+            ldap_missing,
+            ldap_dn: db_group.ldap_dn,
+            enforce_2fa: db_group.enforce_2fa,
+        });
+    }
+
+    Ok(Template::render("admin/groups", GroupsContext { groups }))
+}
+
+async fn render_edit_group(
+    group_id: i32,
+    q: Option<String>,
+    page: Option<usize>,
+    message: Option<String>,
+    directory: &dyn Directory,
+    db: &mut Connection<DB>,
+) -> Result<Template, Error> {
+    let db_group = DBGroup::find_by_id(group_id, &mut *db).await?;
+    // A group linked to a wrong DN has to stay editable so the link can be fixed.
+    let (ldap_group, ldap_missing) = match directory.get_group(&db_group.ldap_dn).await {
+        Ok(ldap_group) => (ldap_group, false),
+        Err(Error::Http(status)) if status == Status::NotFound => (
+            Group {
+                dn: db_group.ldap_dn.clone(),
+                members: Vec::new(),
+                owners: Vec::new(),
+            },
+            true,
+        ),
+        Err(error) => return Err(error),
+    };
+    let member_picker = MemberPicker::load(directory, &ldap_group, q, page).await?;
+    let owners = DBGroupOwner::list_usernames_by_group_id(group_id, &mut *db).await?;
 
     Ok(Template::render(
-        "admin/groups",
-        GroupsContext {
-            groups: groups
-                .into_iter()
-                .map(|db_group| ContextGroup {
-                    id: db_group.id.unwrap(),
-                    name: db_group.name,
-                    members: Some(
-                        ldap_groups
-                            .get(&db_group.ldap_dn)
-                            .unwrap_or(&Vec::new())
-                            .clone(),
-                    ),
-                    ldap_dn: db_group.ldap_dn,
-                    user_member_mapping: None,
-                })
-                .collect(),
+        "admin/groups_edit",
+        EditGroupContext {
+            group: ContextGroup {
+                id: group_id,
+                name: db_group.name,
+                ldap_dn: db_group.ldap_dn,
+                members: None,
+                ldap_missing,
+                enforce_2fa: db_group.enforce_2fa,
+            },
+            member_picker,
+            owners,
+            ldap_owners: ldap_group.owners,
+            message,
         },
     ))
 }
 
-#[get("/groups/<group_id>")]
+#[get("/groups/<group_id>?<q>&<page>")]
 pub(crate) async fn auth_edit_group(
     _user: AdminUser,
     group_id: i32,
-    app_config: &State<AppConfig>,
-    ldap_conn: DBLdapConn,
+    q: Option<String>,
+    page: Option<usize>,
+    directory: LdapDirectory<'_>,
     mut db: Connection<DB>,
 ) -> Result<Template, Error> {
-    let app_config = app_config.inner();
-    let db_group = DBGroup::find_by_id(group_id, &mut *db).await?;
-    let ldap_group_members = get_group_members(&ldap_conn, db_group.ldap_dn.clone()).await?;
-    let ldap_all_users = get_all_users(app_config, &ldap_conn).await?;
-    let ldap_user_mapping: Vec<(String, bool)> = ldap_all_users
-        .iter()
-        .map(|user| (user.clone(), ldap_group_members.contains(user)))
-        .collect();
+    render_edit_group(group_id, q, page, None, &directory, &mut db).await
+}
 
-    Ok(Template::render(
-        "admin/groups_edit",
-        ContextGroup {
-            id: group_id,
-            name: db_group.name,
-            ldap_dn: db_group.ldap_dn,
-            user_member_mapping: Some(ldap_user_mapping),
-            members: None,
+#[derive(FromForm)]
+pub(crate) struct GroupDataGeneral {
+    #[field(validate = len(1..))]
+    name: String,
+    #[field(validate = len(1..))]
+    ldap_dn: String,
+}
+
+#[post("/groups/<group_id>/general", data = "<form>")]
+pub(crate) async fn auth_edit_group_general_form(
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    group_id: i32,
+    form: Form<GroupDataGeneral>,
+    _user: AdminUser,
+    audit: AuditContext,
+) -> Result<Either<Redirect, Template>, Error> {
+    let form = form.into_inner();
+    let ldap_group_missing = match directory.get_group(&form.ldap_dn).await {
+        Ok(_) => false,
+        Err(Error::Http(status)) if status == Status::NotFound => true,
+        Err(error) => return Err(error),
+    };
+    if ldap_group_missing {
+        let message = format!("There is no LDAP group {}.", form.ldap_dn);
+        return Ok(Either::Right(
+            render_edit_group(group_id, None, None, Some(message), &directory, &mut db).await?,
+        ));
+    }
+    let previous = DBGroup::find_by_id(group_id, &mut *db).await?;
+    DBGroup::update_one(
+        DBGroup {
+            id: Some(group_id),
+            name: form.name.clone(),
+            ldap_dn: form.ldap_dn.clone(),
+            enforce_2fa: None,
         },
-    ))
+        &mut *db,
+    )
+    .await?;
+    audit
+        .record(
+            AuditEventType::GroupUpdated,
+            &form.ldap_dn,
+            json!({
+                "group_id": group_id,
+                "name": form.name,
+                "previous_name": previous.name,
+                "previous_ldap_dn": previous.ldap_dn,
+            }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Either::Left(Redirect::to(uri!(
+        "/admin",
+        auth_edit_group(group_id, _, _)
+    ))))
+}
+
+#[derive(FromForm)]
+pub(crate) struct GroupDataDelete {
+    delete_ldap_group: bool,
+}
+
+#[post("/groups/<group_id>/delete", data = "<form>")]
+pub(crate) async fn auth_delete_group_form(
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    group_id: i32,
+    form: Form<GroupDataDelete>,
+    _user: AdminUser,
+    audit: AuditContext,
+) -> Result<Either<Redirect, Template>, Error> {
+    let permission_count = DBGroup::count_permissions(group_id, &mut *db).await?;
+    if permission_count > 0 {
+        let message = format!(
+            "The group still grants access to {} client(s). Remove these permissions before deleting the group.",
+            permission_count
+        );
+        return Ok(Either::Right(
+            render_edit_group(group_id, None, None, Some(message), &directory, &mut db).await?,
+        ));
+    }
+    let db_group = DBGroup::find_by_id(group_id, &mut *db).await?;
+    let delete_ldap_group = form.into_inner().delete_ldap_group;
+    if delete_ldap_group {
+        match directory.delete_group(&db_group.ldap_dn).await {
+            Ok(()) => {}
+            Err(Error::Http(status)) if status == Status::NotFound => {}
+            Err(error) => return Err(error),
+        }
+    }
+    DBGroup::delete_one(group_id, &mut *db).await?;
+    audit
+        .record(
+            AuditEventType::GroupDeleted,
+            &db_group.ldap_dn,
+            json!({
+                "group_id": group_id,
+                "name": db_group.name,
+                "ldap_group_deleted": delete_ldap_group,
+            }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Either::Left(Redirect::to(uri!("/admin", auth_list_groups))))
 }
 
+/// The member picker only lists one page of users, so `shown` tells which users the checked
+/// `members` were chosen from. Memberships of users on other pages are kept. `version` is the
+/// member list the page was rendered with.
 #[derive(FromForm)]
 pub(crate) struct GroupDataMembers {
-    #[field(validate = len(1..))]
     members: Vec<String>,
+    shown: Vec<String>,
+    version: String,
+}
+
+/// Applies the changes made in the member picker, failing with 409 if the group has been
+/// changed since the picker was rendered and with 400 if a member to add is not a user listed
+/// in the picker. Returns the added and the removed members.
+///
+/// Groups that must not be empty get the root DN as a placeholder member instead of losing
+/// their last member, the same way they are created.
+pub(crate) async fn update_members(
+    directory: &dyn Directory,
+    app_config: &AppConfig,
+    ldap_dn: &str,
+    form: GroupDataMembers,
+) -> Result<(Vec<String>, Vec<String>), Error> {
+    let group = directory.get_group(ldap_dn).await?;
+    if group.version() != form.version {
+        return Err(Error::Http(Status::Conflict));
+    }
+    let added: Vec<String> = form
+        .members
+        .iter()
+        .filter(|member| !group.members.contains(*member))
+        .cloned()
+        .collect();
+    // Only users the picker offered may be added. Anything else, a group DN in particular, would
+    // hand its members the group's permissions through nested memberships.
+    if added.iter().any(|member| !form.shown.contains(member)) {
+        return Err(Error::Http(Status::BadRequest));
+    }
+    if !added.is_empty() {
+        let user_dns: HashSet<String> = directory
+            .list_users()
+            .await?
+            .into_iter()
+            .map(|user| user.dn)
+            .collect();
+        if added.iter().any(|member| !user_dns.contains(member)) {
+            return Err(Error::Http(Status::BadRequest));
+        }
+    }
+    let removed: Vec<String> = group
+        .members
+        .iter()
+        .filter(|member| form.shown.contains(*member) && !form.members.contains(*member))
+        .cloned()
+        .collect();
+    let mut placeholder = Vec::new();
+    if app_config.ldap_groups_require_members()
+        && added.is_empty()
+        && group.members.iter().all(|member| removed.contains(member))
+    {
+        placeholder.push(app_config.ldap_root_dn.clone());
+    }
+    directory
+        .update_group_members(
+            ldap_dn,
+            [&added[..], &placeholder[..]].concat(),
+            removed.clone(),
+        )
+        .await?;
+    Ok((added, removed))
+}
+
+/// Records a change of members unless nothing changed.
+pub(crate) async fn record_members_changed(
+    audit: &AuditContext,
+    group_id: i32,
+    ldap_dn: &str,
+    added: &[String],
+    removed: &[String],
+    db: &mut Connection<DB>,
+) -> Result<(), Error> {
+    if added.is_empty() && removed.is_empty() {
+        return Ok(());
+    }
+    audit
+        .record(
+            AuditEventType::GroupMembersChanged,
+            ldap_dn,
+            json!({ "group_id": group_id, "added": added, "removed": removed }),
+            &mut *db,
+        )
+        .await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MemberAction {
+    Add,
+    Remove,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MemberChangeBody {
+    action: MemberAction,
+    member: String,
+    /// The change is rejected with 409 unless the members still match this version.
+    version: String,
+}
+
+impl MemberChangeBody {
+    /// The change as made in a member picker listing only `member`, so it passes the same
+    /// checks.
+    fn into_members(self) -> GroupDataMembers {
+        GroupDataMembers {
+            members: match self.action {
+                MemberAction::Add => vec![self.member.clone()],
+                MemberAction::Remove => Vec::new(),
+            },
+            shown: vec![self.member],
+            version: self.version,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct MembersResponse {
+    members: Vec<String>,
+    version: String,
+}
+
+#[patch("/groups/<group_id>/members", format = "json", data = "<body>")]
+pub(crate) async fn auth_change_group_member(
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    group_id: i32,
+    body: Json<MemberChangeBody>,
+    _user: AdminUser,
+    audit: AuditContext,
+    app_config: &State<AppConfig>,
+) -> Result<Json<MembersResponse>, Error> {
+    let db_group_ldap_dn = DBGroup::find_ldap_dn_by_id(group_id, &mut *db).await?;
+    let (added, removed) = update_members(
+        &directory,
+        app_config.inner(),
+        &db_group_ldap_dn,
+        body.into_inner().into_members(),
+    )
+    .await?;
+    record_members_changed(
+        &audit,
+        group_id,
+        &db_group_ldap_dn,
+        &added,
+        &removed,
+        &mut db,
+    )
+    .await?;
+    let group = directory.get_group(&db_group_ldap_dn).await?;
+    Ok(Json(MembersResponse {
+        version: group.version(),
+        members: group.members,
+    }))
 }
 
 #[post("/groups/<group_id>/members", data = "<form>")]
 pub(crate) async fn auth_edit_group_memberform(
-    ldap_conn: DBLdapConn,
+    directory: LdapDirectory<'_>,
     mut db: Connection<DB>,
     group_id: i32,
     form: Form<GroupDataMembers>,
     _user: AdminUser,
+    audit: AuditContext,
+    app_config: &State<AppConfig>,
 ) -> Result<Redirect, Error> {
-    let form = form.into_inner();
-    let changes = Vec::from([Mod::Replace(
-        "member".to_owned(),
-        HashSet::from_iter(form.members),
-    )]);
     let db_group_ldap_dn = DBGroup::find_ldap_dn_by_id(group_id, &mut *db).await?;
-    change_attrs(&ldap_conn, db_group_ldap_dn, changes).await?;
+    let (added, removed) = update_members(
+        &directory,
+        app_config.inner(),
+        &db_group_ldap_dn,
+        form.into_inner(),
+    )
+    .await?;
+    record_members_changed(
+        &audit,
+        group_id,
+        &db_group_ldap_dn,
+        &added,
+        &removed,
+        &mut db,
+    )
+    .await?;
     Ok(Redirect::to(uri!("/admin", auth_list_groups)))
 }
 
+#[derive(FromForm)]
+pub(crate) struct GroupDataOwner {
+    #[field(validate = len(1..))]
+    username: String,
+}
+
+#[post("/groups/<group_id>/owners", data = "<form>")]
+pub(crate) async fn auth_add_group_owner(
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    group_id: i32,
+    form: Form<GroupDataOwner>,
+    _user: AdminUser,
+    audit: AuditContext,
+) -> Result<Redirect, Error> {
+    // Makes sure the owner exists and is stored with the canonical username.
+    let user = directory.get_user(&form.into_inner().username).await?;
+    DBGroupOwner::create_one(
+        DBGroupOwner {
+            group_id,
+            username: user.username.clone(),
+        },
+        &mut *db,
+    )
+    .await?;
+    let db_group_ldap_dn = DBGroup::find_ldap_dn_by_id(group_id, &mut *db).await?;
+    audit
+        .record(
+            AuditEventType::GroupOwnerAdded,
+            &db_group_ldap_dn,
+            json!({ "group_id": group_id, "owner": user.username }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Redirect::to(uri!(
+        "/admin",
+        auth_edit_group(group_id, _, _)
+    )))
+}
+
+#[get("/groups/<group_id>/owners/<username>/delete")]
+pub(crate) async fn auth_delete_group_owner(
+    mut db: Connection<DB>,
+    group_id: i32,
+    username: &str,
+    _user: AdminUser,
+    audit: AuditContext,
+) -> Result<Redirect, Error> {
+    DBGroupOwner::delete_one(group_id, username, &mut *db).await?;
+    let db_group_ldap_dn = DBGroup::find_ldap_dn_by_id(group_id, &mut *db).await?;
+    audit
+        .record(
+            AuditEventType::GroupOwnerRemoved,
+            &db_group_ldap_dn,
+            json!({ "group_id": group_id, "owner": username }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Redirect::to(uri!(
+        "/admin",
+        auth_edit_group(group_id, _, _)
+    )))
+}
+
+#[derive(FromFormField)]
+pub(crate) enum TwoFactorPolicy {
+    Inherit,
+    Required,
+    Exempt,
+}
+
+#[derive(FromForm)]
+pub(crate) struct GroupDataTwoFactorPolicy {
+    enforce_2fa: TwoFactorPolicy,
+}
+
+#[post("/groups/<group_id>/2fa_policy", data = "<form>")]
+pub(crate) async fn auth_edit_group_2fa_policy_form(
+    mut db: Connection<DB>,
+    group_id: i32,
+    form: Form<GroupDataTwoFactorPolicy>,
+    _user: AdminUser,
+    audit: AuditContext,
+) -> Result<Redirect, Error> {
+    let enforce_2fa = match form.into_inner().enforce_2fa {
+        TwoFactorPolicy::Inherit => None,
+        TwoFactorPolicy::Required => Some(true),
+        TwoFactorPolicy::Exempt => Some(false),
+    };
+    DBGroup::update_enforce_2fa(group_id, enforce_2fa, &mut *db).await?;
+    let db_group_ldap_dn = DBGroup::find_ldap_dn_by_id(group_id, &mut *db).await?;
+    audit
+        .record(
+            AuditEventType::GroupTwoFactorPolicyChanged,
+            &db_group_ldap_dn,
+            json!({ "group_id": group_id, "enforce_2fa": enforce_2fa }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Redirect::to(uri!(
+        "/admin",
+        auth_edit_group(group_id, _, _)
+    )))
+}
+
 #[get("/groups/add_ldap_legitima")]
 pub(crate) async fn auth_add_ldap_legitima(_user: AdminUser) -> Result<Template, Error> {
     Ok(Template::render(
@@ -140,43 +594,41 @@ pub(crate) struct AddLdapLegitimaGroupForm {
 #[post("/groups/add_ldap_legitima", data = "<form>")]
 pub(crate) async fn auth_add_ldap_legitima_form(
     app_config: &State<AppConfig>,
-    ldap_conn: DBLdapConn,
+    directory: LdapDirectory<'_>,
     mut db: Connection<DB>,
     form: Form<Contextual<'_, AddLdapLegitimaGroupForm>>,
     _user: AdminUser,
+    audit: AuditContext,
 ) -> Result<Either<Redirect, Template>, Error> {
     let app_config = app_config.inner();
     Ok(match form.value {
         Some(ref submission) => {
-            let group_dn = format!(
-                "cn={},{}",
-                submission.ldap_cn, app_config.ldap_groups_base_dn
-            );
-            add_dn(
-                &ldap_conn,
-                group_dn.clone(),
-                vec![
-                    (
-                        "objectClass".to_owned(),
-                        HashSet::from(["groupOfNames".to_owned(), "top".to_owned()]),
-                    ),
-                    ("cn".to_owned(), HashSet::from([submission.ldap_cn.clone()])),
-                    (
-                        "member".to_owned(),
-                        HashSet::from([app_config.ldap_root_dn.clone()]),
-                    ),
-                ],
-            )
-            .await?;
-            DBGroup::create_one(
+            // groupOfNames requires at least one member, so the group starts out with the root DN.
+            let ldap_group = directory
+                .create_group(&submission.ldap_cn, vec![app_config.ldap_root_dn.clone()])
+                .await?;
+            let group_id = DBGroup::create_one(
                 DBGroup {
                     id: None,
                     name: submission.legitima_name.clone(),
-                    ldap_dn: group_dn,
+                    ldap_dn: ldap_group.dn.clone(),
+                    enforce_2fa: None,
                 },
                 &mut *db,
             )
             .await?;
+            audit
+                .record(
+                    AuditEventType::GroupCreated,
+                    &ldap_group.dn,
+                    json!({
+                        "group_id": group_id,
+                        "name": submission.legitima_name,
+                        "ldap_group_created": true,
+                    }),
+                    &mut *db,
+                )
+                .await?;
             Either::Left(Redirect::to(uri!("/admin", auth_list_groups)))
         }
         None => Either::Right(Template::render(
@@ -189,26 +641,42 @@ pub(crate) async fn auth_add_ldap_legitima_form(
 #[derive(Serialize)]
 pub(crate) struct AddLegitimaContext {
     ldap_dn_options: Vec<String>,
+    query: String,
+    page: usize,
+    pages: usize,
 }
 
-#[get("/groups/add_legitima")]
+#[get("/groups/add_legitima?<q>&<page>")]
 pub(crate) async fn auth_add_legitima(
     _user: AdminUser,
-    app_config: &State<AppConfig>,
-    ldap_conn: DBLdapConn,
+    q: Option<String>,
+    page: Option<usize>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
 ) -> Result<Template, Error> {
-    let app_config = app_config.inner();
-    let groups: Vec<String> = get_all_groups(app_config, &ldap_conn)
-        .await
-        .unwrap_or_default()
-        .keys()
-        .cloned()
+    let query = q.unwrap_or_default();
+    let groups_page = directory
+        .search_groups(&query, page.unwrap_or(1), DEFAULT_PAGE_SIZE)
+        .await?;
+    let linked_dns: Vec<String> = DBGroup::list_all(&mut *db)
+        .await?
+        .into_iter()
+        .map(|db_group| db_group.ldap_dn.to_lowercase())
         .collect();
 
     Ok(Template::render(
         "admin/groups_add_legitima",
         AddLegitimaContext {
-            ldap_dn_options: groups,
+            // Groups already linked cannot be added a second time.
+            ldap_dn_options: groups_page
+                .items
+                .into_iter()
+                .map(|group| group.dn)
+                .filter(|dn| !linked_dns.contains(&dn.to_lowercase()))
+                .collect(),
+            query,
+            page: groups_page.page,
+            pages: groups_page.pages,
         },
     ))
 }
@@ -226,20 +694,249 @@ pub(crate) async fn auth_add_legitima_form(
     mut db: Connection<DB>,
     form: Form<Contextual<'_, AddLegitimaGroupForm>>,
     _user: AdminUser,
+    audit: AuditContext,
 ) -> Result<Either<Redirect, Template>, Error> {
     Ok(match form.value {
         Some(ref submission) => {
-            DBGroup::create_one(
+            let group_id = DBGroup::create_one(
                 DBGroup {
                     id: None,
                     name: submission.legitima_name.clone(),
                     ldap_dn: submission.ldap_dn.clone(),
+                    enforce_2fa: None,
                 },
                 &mut *db,
             )
             .await?;
+            audit
+                .record(
+                    AuditEventType::GroupCreated,
+                    &submission.ldap_dn,
+                    json!({
+                        "group_id": group_id,
+                        "name": submission.legitima_name,
+                        "ldap_group_created": false,
+                    }),
+                    &mut *db,
+                )
+                .await?;
             Either::Left(Redirect::to(uri!("/admin", auth_list_groups)))
         }
         None => Either::Right(Template::render("admin/groups_add_legitima", &form.context)),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::directory::mock::MockDirectory;
+
+    fn form(group: &Group, members: &[String], shown: &[String]) -> GroupDataMembers {
+        GroupDataMembers {
+            members: members.to_vec(),
+            shown: shown.to_vec(),
+            version: group.version(),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn update_members_adds_and_removes_shown_users() {
+        let (alice, bob) = (
+            MockDirectory::user_dn("alice"),
+            MockDirectory::user_dn("bob"),
+        );
+        let directory = MockDirectory::default()
+            .with_user("alice", "secret")
+            .with_user("bob", "secret")
+            .with_group("staff", &[&alice]);
+        let dn = MockDirectory::group_dn("staff");
+        let config = AppConfig::for_tests(json!({}));
+        let group = directory.get_group(&dn).await.unwrap();
+
+        let shown = [alice.clone(), bob.clone()];
+        let changes = update_members(
+            &directory,
+            &config,
+            &dn,
+            form(&group, &[bob.clone()], &shown),
+        )
+        .await
+        .unwrap();
+        assert_eq!(changes, (vec![bob.clone()], vec![alice]));
+        assert_eq!(directory.get_group(&dn).await.unwrap().members, vec![bob]);
+    }
+
+    #[rocket::async_test]
+    async fn update_members_keeps_members_on_other_pages() {
+        let (alice, bob) = (
+            MockDirectory::user_dn("alice"),
+            MockDirectory::user_dn("bob"),
+        );
+        let directory = MockDirectory::default()
+            .with_user("alice", "secret")
+            .with_user("bob", "secret")
+            .with_group("staff", &[&alice]);
+        let dn = MockDirectory::group_dn("staff");
+        let config = AppConfig::for_tests(json!({}));
+        let group = directory.get_group(&dn).await.unwrap();
+
+        update_members(
+            &directory,
+            &config,
+            &dn,
+            form(&group, &[bob.clone()], &[bob.clone()]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            directory.get_group(&dn).await.unwrap().members,
+            vec![alice, bob]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn update_members_rejects_members_not_offered() {
+        let alice = MockDirectory::user_dn("alice");
+        let admins = MockDirectory::group_dn("admins");
+        let directory = MockDirectory::default()
+            .with_user("alice", "secret")
+            .with_user("bob", "secret")
+            .with_group("admins", &[&alice])
+            .with_group("staff", &[&alice]);
+        let dn = MockDirectory::group_dn("staff");
+        let config = AppConfig::for_tests(json!({}));
+        let group = directory.get_group(&dn).await.unwrap();
+
+        // A user that was not listed in the picker.
+        let bob = MockDirectory::user_dn("bob");
+        let result = update_members(
+            &directory,
+            &config,
+            &dn,
+            form(&group, &[bob], &[alice.clone()]),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Http(status)) if status == Status::BadRequest));
+
+        // A group DN smuggled into the listed users.
+        let shown = [alice.clone(), admins.clone()];
+        let result =
+            update_members(&directory, &config, &dn, form(&group, &[admins], &shown)).await;
+        assert!(matches!(result, Err(Error::Http(status)) if status == Status::BadRequest));
+
+        assert_eq!(directory.get_group(&dn).await.unwrap().members, vec![alice]);
+    }
+
+    #[rocket::async_test]
+    async fn update_members_rejects_outdated_versions() {
+        let alice = MockDirectory::user_dn("alice");
+        let directory = MockDirectory::default()
+            .with_user("alice", "secret")
+            .with_group("staff", &[]);
+        let dn = MockDirectory::group_dn("staff");
+        let config = AppConfig::for_tests(json!({}));
+        let mut group = directory.get_group(&dn).await.unwrap();
+        group.members.push(alice.clone());
+
+        let result = update_members(&directory, &config, &dn, form(&group, &[], &[alice])).await;
+        assert!(matches!(result, Err(Error::Http(status)) if status == Status::Conflict));
+    }
+
+    #[rocket::async_test]
+    async fn update_members_keeps_required_groups_populated() {
+        let alice = MockDirectory::user_dn("alice");
+        let directory = MockDirectory::default()
+            .requiring_members()
+            .with_user("alice", "secret")
+            .with_group("staff", &[&alice]);
+        let dn = MockDirectory::group_dn("staff");
+        let config = AppConfig::for_tests(json!({}));
+        let group = directory.get_group(&dn).await.unwrap();
+
+        let changes = update_members(
+            &directory,
+            &config,
+            &dn,
+            form(&group, &[], &[alice.clone()]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(changes, (Vec::new(), vec![alice]));
+        assert_eq!(
+            directory.get_group(&dn).await.unwrap().members,
+            vec![config.ldap_root_dn]
+        );
+    }
+
+    fn change(group: &Group, action: &str, member: &str) -> MemberChangeBody {
+        serde_json::from_value(json!({
+            "action": action,
+            "member": member,
+            "version": group.version(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn member_changes_require_a_version() {
+        let body = json!({ "action": "add", "member": MockDirectory::user_dn("alice") });
+        assert!(serde_json::from_value::<MemberChangeBody>(body).is_err());
+    }
+
+    #[rocket::async_test]
+    async fn member_changes_are_checked_like_the_picker() {
+        let alice = MockDirectory::user_dn("alice");
+        let admins = MockDirectory::group_dn("admins");
+        let directory = MockDirectory::default()
+            .requiring_members()
+            .with_user("alice", "secret")
+            .with_group("admins", &[&alice])
+            .with_group("staff", &[]);
+        let dn = MockDirectory::group_dn("staff");
+        let config = AppConfig::for_tests(json!({}));
+        let group = directory.get_group(&dn).await.unwrap();
+
+        let result = update_members(
+            &directory,
+            &config,
+            &dn,
+            change(&group, "add", &admins).into_members(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Http(status)) if status == Status::BadRequest));
+
+        let changes = update_members(
+            &directory,
+            &config,
+            &dn,
+            change(&group, "add", &alice).into_members(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(changes, (vec![alice.clone()], Vec::new()));
+
+        // The version the member was added with is outdated now.
+        let result = update_members(
+            &directory,
+            &config,
+            &dn,
+            change(&group, "remove", &alice).into_members(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Http(status)) if status == Status::Conflict));
+
+        let group = directory.get_group(&dn).await.unwrap();
+        update_members(
+            &directory,
+            &config,
+            &dn,
+            change(&group, "remove", &alice).into_members(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            directory.get_group(&dn).await.unwrap().members,
+            vec![config.ldap_root_dn]
+        );
+    }
+}
@@ -1 +1,7 @@
+pub(crate) mod audit;
+pub(crate) mod clients;
 pub(crate) mod groups;
+pub(crate) mod provisioning;
+pub(crate) mod reconcile;
+pub(crate) mod security;
+pub(crate) mod service_accounts;
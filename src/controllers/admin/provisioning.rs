@@ -0,0 +1,111 @@
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::Template;
+
+use crate::db::{DBProvisioningDelivery, DB};
+use crate::directory::DEFAULT_PAGE_SIZE;
+use crate::error::Error;
+use crate::provisioning::Provisioning;
+use crate::sessions::AdminUser;
+
+#[derive(Serialize)]
+struct ContextDelivery {
+    id: i64,
+    client_id: String,
+    event_type: String,
+    subject: Option<String>,
+    status: String,
+    attempts: i32,
+    created_at: String,
+    /// When a pending delivery is attempted next.
+    next_attempt_at: Option<String>,
+    delivered_at: Option<String>,
+    last_error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DeliveryLogContext {
+    client_ids: Vec<String>,
+    client_id: String,
+    status: String,
+    deliveries: Vec<ContextDelivery>,
+    page: usize,
+    has_next: bool,
+}
+
+fn format_time(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+#[get("/provisioning", rank = 2)]
+pub(crate) async fn delivery_log() -> Status {
+    Status::Forbidden
+}
+
+/// Deliveries of changes to the provisioned clients, newest first.
+#[get("/provisioning?<client_id>&<status>&<page>")]
+pub(crate) async fn auth_delivery_log(
+    _user: AdminUser,
+    client_id: Option<String>,
+    status: Option<String>,
+    page: Option<usize>,
+    provisioning: &State<Provisioning>,
+    mut db: Connection<DB>,
+) -> Result<Template, Error> {
+    let client_id = client_id.unwrap_or_default();
+    let status = status.unwrap_or_default();
+    let page = page.unwrap_or(1).max(1);
+    let mut deliveries = DBProvisioningDelivery::list(
+        Some(&client_id[..]).filter(|client_id| !client_id.is_empty()),
+        Some(&status[..]).filter(|status| !status.is_empty()),
+        DEFAULT_PAGE_SIZE as i64 + 1,
+        ((page - 1) * DEFAULT_PAGE_SIZE) as i64,
+        &mut *db,
+    )
+    .await?;
+    let has_next = deliveries.len() > DEFAULT_PAGE_SIZE;
+    deliveries.truncate(DEFAULT_PAGE_SIZE);
+
+    Ok(Template::render(
+        "admin/provisioning",
+        DeliveryLogContext {
+            client_ids: provisioning.client_ids(),
+            client_id,
+            status,
+            deliveries: deliveries
+                .into_iter()
+                .map(|delivery| ContextDelivery {
+                    next_attempt_at: Some(format_time(delivery.next_attempt_at))
+                        .filter(|_| delivery.status == "pending"),
+                    id: delivery.id,
+                    client_id: delivery.client_id,
+                    event_type: delivery.event_type,
+                    subject: delivery.subject,
+                    status: delivery.status,
+                    attempts: delivery.attempts,
+                    created_at: format_time(delivery.created_at),
+                    delivered_at: delivery.delivered_at.map(format_time),
+                    last_error: delivery.last_error,
+                })
+                .collect(),
+            page,
+            has_next,
+        },
+    ))
+}
+
+/// Queues a delivery that has been given up again.
+#[post("/provisioning/<delivery_id>/retry")]
+pub(crate) async fn auth_retry_delivery(
+    _user: AdminUser,
+    delivery_id: i64,
+    mut db: Connection<DB>,
+) -> Result<Redirect, Error> {
+    if !DBProvisioningDelivery::retry(delivery_id, &mut *db).await? {
+        return Err(Error::Http(Status::NotFound));
+    }
+    Ok(Redirect::to(uri!("/admin", auth_delivery_log(_, _, _))))
+}
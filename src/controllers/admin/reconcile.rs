@@ -0,0 +1,149 @@
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::Template;
+use serde_json::json;
+
+use crate::audit::{AuditContext, AuditEventType};
+use crate::config::AppConfig;
+use crate::db::{DBGroup, DB};
+use crate::directory::{rdn_value, Directory, LdapDirectory};
+use crate::error::Error;
+use crate::reconcile::detect_drift;
+use crate::sessions::AdminUser;
+
+#[derive(Serialize)]
+struct ReconcileContext {
+    dangling: Vec<DanglingGroup>,
+    orphaned: Vec<OrphanedGroup>,
+}
+
+#[derive(Serialize)]
+struct DanglingGroup {
+    id: i32,
+    name: String,
+    ldap_dn: String,
+}
+
+#[derive(Serialize)]
+struct OrphanedGroup {
+    ldap_dn: String,
+    name: String,
+}
+
+#[get("/groups/reconcile", rank = 2)]
+pub(crate) async fn reconcile() -> Status {
+    Status::Forbidden
+}
+
+#[get("/groups/reconcile")]
+pub(crate) async fn auth_reconcile(
+    _user: AdminUser,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Template, Error> {
+    let drift = detect_drift(&directory, &mut db).await?;
+
+    Ok(Template::render(
+        "admin/groups_reconcile",
+        ReconcileContext {
+            dangling: drift
+                .dangling
+                .into_iter()
+                .map(|db_group| DanglingGroup {
+                    id: db_group.id.unwrap(),
+                    name: db_group.name,
+                    ldap_dn: db_group.ldap_dn,
+                })
+                .collect(),
+            orphaned: drift
+                .orphaned
+                .into_iter()
+                .map(|ldap_dn| OrphanedGroup {
+                    name: rdn_value(&ldap_dn),
+                    ldap_dn,
+                })
+                .collect(),
+        },
+    ))
+}
+
+#[derive(FromForm)]
+pub(crate) struct ImportGroupForm {
+    #[field(validate = len(1..))]
+    ldap_dn: String,
+}
+
+/// Links an LDAP group to a new legitima group named after its common name.
+#[post("/groups/reconcile/import", data = "<form>")]
+pub(crate) async fn auth_import_group(
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    form: Form<ImportGroupForm>,
+    _user: AdminUser,
+    audit: AuditContext,
+) -> Result<Redirect, Error> {
+    let ldap_group = directory.get_group(&form.into_inner().ldap_dn).await?;
+    let name = rdn_value(&ldap_group.dn);
+    let group_id = DBGroup::create_one(
+        DBGroup {
+            id: None,
+            name: name.clone(),
+            ldap_dn: ldap_group.dn.clone(),
+            enforce_2fa: None,
+        },
+        &mut *db,
+    )
+    .await?;
+    audit
+        .record(
+            AuditEventType::GroupCreated,
+            &ldap_group.dn,
+            json!({ "group_id": group_id, "name": name, "ldap_group_created": false }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Redirect::to(uri!("/admin", auth_reconcile)))
+}
+
+/// Creates the missing LDAP group of a legitima group again, starting out with the root DN as
+/// its only member.
+#[post("/groups/<group_id>/recreate")]
+pub(crate) async fn auth_recreate_group(
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    group_id: i32,
+    _user: AdminUser,
+    audit: AuditContext,
+) -> Result<Redirect, Error> {
+    let mut db_group = DBGroup::find_by_id(group_id, &mut *db).await?;
+    let ldap_group = directory
+        .create_group(
+            &rdn_value(&db_group.ldap_dn),
+            vec![app_config.ldap_root_dn.clone()],
+        )
+        .await?;
+    // Groups are always created below the groups base DN, which may differ from the old DN.
+    let previous_ldap_dn = db_group.ldap_dn.clone();
+    if ldap_group.dn != db_group.ldap_dn {
+        db_group.ldap_dn = ldap_group.dn.clone();
+        DBGroup::update_one(db_group, &mut *db).await?;
+    }
+    audit
+        .record(
+            AuditEventType::GroupUpdated,
+            &ldap_group.dn,
+            json!({
+                "group_id": group_id,
+                "previous_ldap_dn": previous_ldap_dn,
+                "ldap_group_recreated": true,
+            }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Redirect::to(uri!("/admin", auth_reconcile)))
+}
@@ -0,0 +1,94 @@
+use rocket::http::Status;
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::Template;
+
+use crate::config::AppConfig;
+use crate::db::{DBGroup, DBTotpCredential, DBUser2FAGrace, DBUserCredential, DB};
+use crate::directory::{Directory, LdapDirectory, DEFAULT_PAGE_SIZE};
+use crate::error::Error;
+use crate::policy::{in_grace_period, is_2fa_enforced};
+use crate::sessions::AdminUser;
+
+#[derive(Serialize)]
+struct SecurityContext {
+    enforce_2fa: bool,
+    non_compliant_only: bool,
+    query: String,
+    page: usize,
+    pages: usize,
+    users: Vec<ContextUser>,
+}
+
+#[derive(Serialize)]
+struct ContextUser {
+    username: String,
+    enrolled: bool,
+    enforced: bool,
+    in_grace_period: bool,
+    grace_login_count: Option<i32>,
+    grace_first_login_at: Option<String>,
+}
+
+#[get("/security", rank = 2)]
+pub(crate) async fn security_overview() -> Status {
+    Status::Forbidden
+}
+
+#[get("/security?<non_compliant>&<q>&<page>")]
+pub(crate) async fn auth_security_overview(
+    _user: AdminUser,
+    non_compliant: Option<bool>,
+    q: Option<String>,
+    page: Option<usize>,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Template, Error> {
+    let app_config = app_config.inner();
+    let non_compliant_only = non_compliant.unwrap_or(false);
+    let query = q.unwrap_or_default();
+    let users_page = directory
+        .search_users(&query, page.unwrap_or(1), DEFAULT_PAGE_SIZE)
+        .await?;
+    let db_groups = DBGroup::list_all(&mut *db).await?;
+    let enrolled_usernames =
+        DBUserCredential::<DBTotpCredential>::find_usernames_with_permanent_credentials(&mut *db)
+            .await?;
+    let graces = DBUser2FAGrace::list_all(&mut *db).await?;
+
+    let mut users = Vec::new();
+    for user in users_page.items {
+        let username = user.username;
+        let user_group_dns = directory.get_user_groups(&username).await?;
+        let grace = graces.iter().find(|grace| grace.username == username);
+        let context_user = ContextUser {
+            enrolled: enrolled_usernames.contains(&username),
+            enforced: is_2fa_enforced(app_config, &db_groups, &user_group_dns),
+            in_grace_period: in_grace_period(app_config, grace),
+            grace_login_count: grace.map(|grace| grace.login_count),
+            grace_first_login_at: grace
+                .map(|grace| grace.first_login_at.format("%Y-%m-%d %H:%M").to_string()),
+            username,
+        };
+        if !non_compliant_only || (context_user.enforced && !context_user.enrolled) {
+            users.push(context_user);
+        }
+    }
+    users.sort_by(|a, b| {
+        (!a.enforced || a.enrolled, &a.username).cmp(&(!b.enforced || b.enrolled, &b.username))
+    });
+
+    Ok(Template::render(
+        "admin/security",
+        SecurityContext {
+            enforce_2fa: app_config.enforce_2fa,
+            non_compliant_only,
+            query,
+            page: users_page.page,
+            pages: users_page.pages,
+            users,
+        },
+    ))
+}
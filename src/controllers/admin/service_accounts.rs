@@ -0,0 +1,293 @@
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::serde::Serialize;
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::Template;
+use serde_json::json;
+
+use crate::audit::{AuditContext, AuditEventType};
+use crate::db::{DBApiKey, DBGroup, DBServiceAccount, DB};
+use crate::error::Error;
+use crate::service_accounts::{format_scopes, generate_api_key, parse_scopes, ApiScope};
+use crate::sessions::AdminUser;
+
+#[derive(Serialize)]
+struct ServiceAccountsContext {
+    service_accounts: Vec<ContextServiceAccount>,
+    global_scopes: Vec<String>,
+    groups: Vec<DBGroup>,
+    /// A key that has just been created or rotated. It is shown this one time only.
+    new_key: Option<NewApiKey>,
+}
+
+#[derive(Serialize)]
+struct ContextServiceAccount {
+    id: i32,
+    name: String,
+    description: String,
+    created_at: String,
+    keys: Vec<ContextApiKey>,
+}
+
+#[derive(Serialize)]
+struct ContextApiKey {
+    id: uuid::Uuid,
+    label: String,
+    scopes: Vec<String>,
+    created_at: String,
+    rotated_at: Option<String>,
+    last_used_at: Option<String>,
+    revoked: bool,
+}
+
+#[derive(Serialize)]
+struct NewApiKey {
+    service_account: String,
+    label: String,
+    key: String,
+}
+
+fn format_time(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M").to_string()
+}
+
+/// Describes `scope` for the overview, naming the group of group scopes.
+fn describe_scope(scope: ApiScope, groups: &[DBGroup]) -> String {
+    match scope {
+        ApiScope::GroupMembers(group_id) => match groups.iter().find(|g| g.id == Some(group_id)) {
+            Some(group) => format!("members of {}", group.name),
+            None => scope.to_string(),
+        },
+        _ => scope.to_string(),
+    }
+}
+
+impl ServiceAccountsContext {
+    async fn load(
+        new_key: Option<NewApiKey>,
+        db: &mut Connection<DB>,
+    ) -> Result<ServiceAccountsContext, Error> {
+        let groups = DBGroup::list_all(&mut *db).await?;
+        let api_keys = DBApiKey::list_all(&mut *db).await?;
+        let service_accounts = DBServiceAccount::list_all(&mut *db)
+            .await?
+            .into_iter()
+            .map(|service_account| ContextServiceAccount {
+                keys: api_keys
+                    .iter()
+                    .filter(|api_key| api_key.service_account_id == service_account.id)
+                    .cloned()
+                    .map(|api_key| ContextApiKey {
+                        id: api_key.id,
+                        label: api_key.label,
+                        scopes: parse_scopes(&api_key.scopes)
+                            .into_iter()
+                            .map(|scope| describe_scope(scope, &groups))
+                            .collect(),
+                        created_at: format_time(api_key.created_at),
+                        rotated_at: api_key.rotated_at.map(format_time),
+                        last_used_at: api_key.last_used_at.map(format_time),
+                        revoked: api_key.revoked_at.is_some(),
+                    })
+                    .collect(),
+                id: service_account.id,
+                name: service_account.name,
+                description: service_account.description,
+                created_at: format_time(service_account.created_at),
+            })
+            .collect();
+        Ok(ServiceAccountsContext {
+            service_accounts,
+            global_scopes: ApiScope::GLOBAL.iter().map(ApiScope::to_string).collect(),
+            groups,
+            new_key,
+        })
+    }
+}
+
+#[get("/service_accounts", rank = 2)]
+pub(crate) async fn list_service_accounts() -> Status {
+    Status::Forbidden
+}
+
+#[get("/service_accounts")]
+pub(crate) async fn auth_list_service_accounts(
+    _user: AdminUser,
+    mut db: Connection<DB>,
+) -> Result<Template, Error> {
+    Ok(Template::render(
+        "admin/service_accounts",
+        ServiceAccountsContext::load(None, &mut db).await?,
+    ))
+}
+
+#[derive(FromForm)]
+pub(crate) struct ServiceAccountForm {
+    #[field(validate = len(1..))]
+    name: String,
+    description: String,
+}
+
+#[post("/service_accounts", data = "<form>")]
+pub(crate) async fn auth_add_service_account(
+    _user: AdminUser,
+    form: Form<ServiceAccountForm>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Redirect, Error> {
+    let form = form.into_inner();
+    let service_account_id =
+        DBServiceAccount::create_one(&form.name, &form.description, &mut *db).await?;
+    audit
+        .record(
+            AuditEventType::ServiceAccountCreated,
+            &form.name,
+            json!({
+                "service_account_id": service_account_id,
+                "description": form.description,
+            }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Redirect::to(uri!("/admin", auth_list_service_accounts)))
+}
+
+#[post("/service_accounts/<service_account_id>/delete")]
+pub(crate) async fn auth_delete_service_account(
+    _user: AdminUser,
+    service_account_id: i32,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Redirect, Error> {
+    let service_account = DBServiceAccount::find_by_id(service_account_id, &mut *db).await?;
+    DBServiceAccount::delete_one(service_account_id, &mut *db).await?;
+    audit
+        .record(
+            AuditEventType::ServiceAccountDeleted,
+            &service_account.name,
+            json!({ "service_account_id": service_account_id }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Redirect::to(uri!("/admin", auth_list_service_accounts)))
+}
+
+/// `scopes` are the checked global scopes, `member_groups` the groups whose members the key
+/// may manage.
+#[derive(FromForm)]
+pub(crate) struct ApiKeyForm {
+    #[field(validate = len(1..))]
+    label: String,
+    scopes: Vec<String>,
+    member_groups: Vec<i32>,
+}
+
+#[post("/service_accounts/<service_account_id>/keys", data = "<form>")]
+pub(crate) async fn auth_add_api_key(
+    _user: AdminUser,
+    service_account_id: i32,
+    form: Form<ApiKeyForm>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Template, Error> {
+    let form = form.into_inner();
+    let mut scopes = form
+        .scopes
+        .iter()
+        .map(|scope| scope.parse::<ApiScope>())
+        .collect::<Result<Vec<ApiScope>, ()>>()
+        .map_err(|()| Error::Http(Status::UnprocessableEntity))?;
+    scopes.extend(form.member_groups.into_iter().map(ApiScope::GroupMembers));
+    if scopes.is_empty() {
+        return Err(Error::Http(Status::UnprocessableEntity));
+    }
+    let scopes = format_scopes(&scopes);
+    let service_account = DBServiceAccount::find_by_id(service_account_id, &mut *db).await?;
+    let (key, key_hash) = generate_api_key();
+    let api_key_id = DBApiKey::create_one(
+        service_account_id,
+        &form.label,
+        &key_hash,
+        &scopes,
+        &mut *db,
+    )
+    .await?;
+    audit
+        .record(
+            AuditEventType::ApiKeyCreated,
+            &service_account.name,
+            json!({
+                "service_account_id": service_account_id,
+                "api_key_id": api_key_id,
+                "label": form.label,
+                "scopes": scopes,
+            }),
+            &mut *db,
+        )
+        .await?;
+    let new_key = NewApiKey {
+        service_account: service_account.name,
+        label: form.label,
+        key,
+    };
+    Ok(Template::render(
+        "admin/service_accounts",
+        ServiceAccountsContext::load(Some(new_key), &mut db).await?,
+    ))
+}
+
+/// Replaces the key, keeping label and scopes. The previous key stops working immediately.
+#[post("/service_accounts/<service_account_id>/keys/<api_key_id>/rotate")]
+pub(crate) async fn auth_rotate_api_key(
+    _user: AdminUser,
+    service_account_id: i32,
+    api_key_id: uuid::Uuid,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Template, Error> {
+    let service_account = DBServiceAccount::find_by_id(service_account_id, &mut *db).await?;
+    let (key, key_hash) = generate_api_key();
+    let label = DBApiKey::rotate(api_key_id, service_account_id, &key_hash, &mut *db)
+        .await?
+        .ok_or(Error::Http(Status::NotFound))?;
+    audit
+        .record(
+            AuditEventType::ApiKeyRotated,
+            &service_account.name,
+            json!({ "service_account_id": service_account_id, "api_key_id": api_key_id }),
+            &mut *db,
+        )
+        .await?;
+    let new_key = NewApiKey {
+        service_account: service_account.name,
+        label,
+        key,
+    };
+    Ok(Template::render(
+        "admin/service_accounts",
+        ServiceAccountsContext::load(Some(new_key), &mut db).await?,
+    ))
+}
+
+#[post("/service_accounts/<service_account_id>/keys/<api_key_id>/revoke")]
+pub(crate) async fn auth_revoke_api_key(
+    _user: AdminUser,
+    service_account_id: i32,
+    api_key_id: uuid::Uuid,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Redirect, Error> {
+    let service_account = DBServiceAccount::find_by_id(service_account_id, &mut *db).await?;
+    if DBApiKey::revoke(api_key_id, service_account_id, &mut *db).await? {
+        audit
+            .record(
+                AuditEventType::ApiKeyRevoked,
+                &service_account.name,
+                json!({ "service_account_id": service_account_id, "api_key_id": api_key_id }),
+                &mut *db,
+            )
+            .await?;
+    }
+    Ok(Redirect::to(uri!("/admin", auth_list_service_accounts)))
+}
@@ -0,0 +1,266 @@
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_db_pools::Connection;
+use serde_json::json;
+
+use crate::audit::{AuditContext, AuditEventType};
+use crate::config::AppConfig;
+use crate::controllers::admin::groups::record_members_changed;
+use crate::controllers::api::ApiPrincipal;
+use crate::db::{DBGroup, DBGroupOwner, DB};
+use crate::directory::{Directory, LdapDirectory};
+use crate::error::Error;
+use crate::service_accounts::ApiScope;
+
+#[get("/groups")]
+pub(crate) async fn list_groups(
+    principal: ApiPrincipal,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Json<Vec<DBGroup>>, Error> {
+    principal
+        .require(ApiScope::GroupsRead, &directory, app_config, &mut *db)
+        .await?;
+    Ok(Json(DBGroup::list_all(&mut *db).await?))
+}
+
+/// Creates a legitima group. With `ldap_cn` a new LDAP group is created for it, with `ldap_dn`
+/// an existing one is linked.
+#[derive(Deserialize)]
+pub(crate) struct CreateGroupBody {
+    name: String,
+    ldap_dn: Option<String>,
+    ldap_cn: Option<String>,
+}
+
+#[post("/groups", format = "json", data = "<body>")]
+pub(crate) async fn create_group(
+    principal: ApiPrincipal,
+    body: Json<CreateGroupBody>,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Created<Json<DBGroup>>, Error> {
+    principal
+        .require(ApiScope::GroupsWrite, &directory, app_config, &mut *db)
+        .await?;
+    let body = body.into_inner();
+    if body.name.is_empty() {
+        return Err(Error::Http(Status::UnprocessableEntity));
+    }
+    let (ldap_dn, ldap_group_created) = match (body.ldap_dn, body.ldap_cn) {
+        (Some(ldap_dn), None) => (directory.get_group(&ldap_dn).await?.dn, false),
+        (None, Some(ldap_cn)) if !ldap_cn.is_empty() => {
+            // groupOfNames requires at least one member, so the group starts out with the root DN.
+            let ldap_group = directory
+                .create_group(&ldap_cn, vec![app_config.ldap_root_dn.clone()])
+                .await?;
+            (ldap_group.dn, true)
+        }
+        _ => return Err(Error::Http(Status::UnprocessableEntity)),
+    };
+    let group = DBGroup {
+        id: None,
+        name: body.name,
+        ldap_dn,
+        enforce_2fa: None,
+    };
+    let group_id = DBGroup::create_one(group.clone(), &mut *db).await?;
+    audit
+        .with_actor(principal.actor())
+        .record(
+            AuditEventType::GroupCreated,
+            &group.ldap_dn,
+            json!({
+                "group_id": group_id,
+                "name": group.name,
+                "ldap_group_created": ldap_group_created,
+            }),
+            &mut *db,
+        )
+        .await?;
+    Ok(
+        Created::new(format!("/api/v1/groups/{}", group_id)).body(Json(DBGroup {
+            id: Some(group_id),
+            ..group
+        })),
+    )
+}
+
+#[derive(Serialize)]
+pub(crate) struct ApiGroupDetails {
+    #[serde(flatten)]
+    group: DBGroup,
+    members: Vec<String>,
+    version: String,
+    /// Usernames of the owners set in legitima.
+    owners: Vec<String>,
+    /// DNs of the owners set in the LDAP group.
+    ldap_owners: Vec<String>,
+}
+
+#[get("/groups/<group_id>")]
+pub(crate) async fn get_group(
+    principal: ApiPrincipal,
+    group_id: i32,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Json<ApiGroupDetails>, Error> {
+    principal
+        .require(ApiScope::GroupsRead, &directory, app_config, &mut *db)
+        .await?;
+    let group = DBGroup::find_by_id(group_id, &mut *db).await?;
+    let ldap_group = directory.get_group(&group.ldap_dn).await?;
+    let owners = DBGroupOwner::list_usernames_by_group_id(group_id, &mut *db).await?;
+    Ok(Json(ApiGroupDetails {
+        group,
+        version: ldap_group.version(),
+        members: ldap_group.members,
+        owners,
+        ldap_owners: ldap_group.owners,
+    }))
+}
+
+/// Deletes a legitima group, refusing with 409 while it still grants access to clients.
+#[delete("/groups/<group_id>?<delete_ldap_group>")]
+pub(crate) async fn delete_group(
+    principal: ApiPrincipal,
+    group_id: i32,
+    delete_ldap_group: Option<bool>,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Status, Error> {
+    principal
+        .require(ApiScope::GroupsWrite, &directory, app_config, &mut *db)
+        .await?;
+    if DBGroup::count_permissions(group_id, &mut *db).await? > 0 {
+        return Err(Error::Http(Status::Conflict));
+    }
+    let group = DBGroup::find_by_id(group_id, &mut *db).await?;
+    let delete_ldap_group = delete_ldap_group.unwrap_or(false);
+    if delete_ldap_group {
+        match directory.delete_group(&group.ldap_dn).await {
+            Ok(()) => {}
+            Err(Error::Http(status)) if status == Status::NotFound => {}
+            Err(error) => return Err(error),
+        }
+    }
+    DBGroup::delete_one(group_id, &mut *db).await?;
+    audit
+        .with_actor(principal.actor())
+        .record(
+            AuditEventType::GroupDeleted,
+            &group.ldap_dn,
+            json!({
+                "group_id": group_id,
+                "name": group.name,
+                "ldap_group_deleted": delete_ldap_group,
+            }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Status::NoContent)
+}
+
+#[derive(Serialize)]
+pub(crate) struct ApiMembers {
+    members: Vec<String>,
+    version: String,
+}
+
+#[get("/groups/<group_id>/members")]
+pub(crate) async fn get_members(
+    principal: ApiPrincipal,
+    group_id: i32,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Json<ApiMembers>, Error> {
+    principal
+        .require(
+            ApiScope::GroupMembers(group_id),
+            &directory,
+            app_config,
+            &mut *db,
+        )
+        .await?;
+    let ldap_dn = DBGroup::find_ldap_dn_by_id(group_id, &mut *db).await?;
+    let group = directory.get_group(&ldap_dn).await?;
+    Ok(Json(ApiMembers {
+        version: group.version(),
+        members: group.members,
+    }))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MembersChangeBody {
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+    /// If given, the change is rejected with 409 unless the members still match this version.
+    version: Option<String>,
+}
+
+#[patch("/groups/<group_id>/members", format = "json", data = "<body>")]
+pub(crate) async fn change_members(
+    principal: ApiPrincipal,
+    group_id: i32,
+    body: Json<MembersChangeBody>,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Json<ApiMembers>, Error> {
+    principal
+        .require(
+            ApiScope::GroupMembers(group_id),
+            &directory,
+            app_config,
+            &mut *db,
+        )
+        .await?;
+    let body = body.into_inner();
+    let ldap_dn = DBGroup::find_ldap_dn_by_id(group_id, &mut *db).await?;
+    let group = directory.get_group(&ldap_dn).await?;
+    if let Some(version) = body.version {
+        if group.version() != version {
+            return Err(Error::Http(Status::Conflict));
+        }
+    }
+    let added: Vec<String> = body
+        .add
+        .into_iter()
+        .filter(|member| !group.members.contains(member))
+        .collect();
+    let removed: Vec<String> = body
+        .remove
+        .into_iter()
+        .filter(|member| group.members.contains(member))
+        .collect();
+    directory
+        .update_group_members(&ldap_dn, added.clone(), removed.clone())
+        .await?;
+    record_members_changed(
+        &audit.with_actor(principal.actor()),
+        group_id,
+        &ldap_dn,
+        &added,
+        &removed,
+        &mut db,
+    )
+    .await?;
+    let group = directory.get_group(&ldap_dn).await?;
+    Ok(Json(ApiMembers {
+        version: group.version(),
+        members: group.members,
+    }))
+}
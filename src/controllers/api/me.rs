@@ -0,0 +1,268 @@
+use ory_hydra_client::apis::configuration::Configuration;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_db_pools::Connection;
+use serde_json::json;
+use webauthn_rs::proto::Credential;
+
+use crate::audit::{AuditContext, AuditEventType};
+use crate::config::HydraConfig;
+use crate::controllers::api::ApiUser;
+use crate::db::{DBUserCredential, DB};
+use crate::directory::{Directory, LdapDirectory, User};
+use crate::error::Error;
+use crate::notifications::{Notification, Notifier};
+use crate::sessions::{revoke_sessions, SessionRevoker};
+
+#[get("/me")]
+pub(crate) async fn get_me(
+    user: ApiUser,
+    directory: LdapDirectory<'_>,
+) -> Result<Json<User>, Error> {
+    Ok(Json(directory.get_user(&user.get_username()).await?))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct NameBody {
+    display_name: String,
+    first_name: String,
+    last_name: String,
+}
+
+#[put("/me/name", format = "json", data = "<body>")]
+pub(crate) async fn put_name(
+    user: ApiUser,
+    body: Json<NameBody>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Json<User>, Error> {
+    let body = body.into_inner();
+    if body.display_name.is_empty() || body.first_name.is_empty() || body.last_name.is_empty() {
+        return Err(Error::Http(Status::UnprocessableEntity));
+    }
+    let username = user.get_username();
+    directory
+        .update_user_name(
+            &username,
+            &body.display_name,
+            &body.first_name,
+            &body.last_name,
+        )
+        .await?;
+    audit
+        .with_actor(&username)
+        .record(
+            AuditEventType::NameChanged,
+            &username,
+            json!({
+                "display_name": body.display_name,
+                "first_name": body.first_name,
+                "last_name": body.last_name,
+            }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Json(directory.get_user(&username).await?))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EmailBody {
+    email: String,
+}
+
+#[put("/me/email", format = "json", data = "<body>")]
+pub(crate) async fn put_email(
+    user: ApiUser,
+    body: Json<EmailBody>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+    notifier: Notifier<'_>,
+) -> Result<Json<User>, Error> {
+    let email = body.into_inner().email;
+    if email.is_empty() {
+        return Err(Error::Http(Status::UnprocessableEntity));
+    }
+    let username = user.get_username();
+    let previous_email = directory.get_user(&username).await?.email;
+    directory.update_user_email(&username, &email).await?;
+    audit
+        .with_actor(&username)
+        .record(
+            AuditEventType::EmailChanged,
+            &username,
+            json!({ "previous_email": previous_email, "email": email }),
+            &mut *db,
+        )
+        .await?;
+    notifier
+        .notify(
+            &username,
+            Notification::EmailChanged {
+                previous_email,
+                email,
+            },
+        )
+        .await;
+    Ok(Json(directory.get_user(&username).await?))
+}
+
+/// DNs of the LDAP groups the user is a member of.
+#[get("/me/groups")]
+pub(crate) async fn get_groups(
+    user: ApiUser,
+    directory: LdapDirectory<'_>,
+) -> Result<Json<Vec<String>>, Error> {
+    Ok(Json(directory.get_user_groups(&user.get_username()).await?))
+}
+
+#[derive(Serialize)]
+pub(crate) struct ApiCredential {
+    id: uuid::Uuid,
+    #[serde(rename = "type")]
+    credential_type: &'static str,
+    label: Option<String>,
+}
+
+/// The user's second factors. Secrets and public keys are never returned.
+#[get("/me/credentials")]
+pub(crate) async fn get_credentials(
+    user: ApiUser,
+    mut db: Connection<DB>,
+) -> Result<Json<Vec<ApiCredential>>, Error> {
+    let username = user.get_username();
+    let webauthn_credentials =
+        DBUserCredential::find_webauthn_credentials_by_username(&username, &mut *db).await?;
+    let totp_credentials =
+        DBUserCredential::find_totp_credentials_by_username(&username, &mut *db).await?;
+    let mut credentials: Vec<ApiCredential> = webauthn_credentials
+        .into_iter()
+        .map(|c| ApiCredential {
+            id: c.id.unwrap(),
+            credential_type: "webauthn",
+            label: c.label,
+        })
+        .collect();
+    credentials.extend(totp_credentials.into_iter().map(|c| ApiCredential {
+        id: c.id.unwrap(),
+        credential_type: "totp",
+        label: c.label,
+    }));
+    Ok(Json(credentials))
+}
+
+#[delete("/me/credentials/<credential_id>")]
+pub(crate) async fn delete_credential(
+    user: ApiUser,
+    credential_id: uuid::Uuid,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+    notifier: Notifier<'_>,
+    mut revoker: SessionRevoker<'_>,
+) -> Result<Status, Error> {
+    let username = user.get_username();
+    let label = DBUserCredential::<Credential>::find_label_by_id_and_username(
+        credential_id,
+        &username,
+        &mut *db,
+    )
+    .await?;
+    if !DBUserCredential::<Credential>::delete_credential(credential_id, &username, &mut *db)
+        .await?
+    {
+        return Err(Error::Http(Status::NotFound));
+    }
+    let audit = audit.with_actor(&username);
+    audit
+        .record(
+            AuditEventType::CredentialDeleted,
+            &username,
+            json!({ "credential_id": credential_id }),
+            &mut *db,
+        )
+        .await?;
+    revoker
+        .cut_off(
+            &username,
+            AuditEventType::CredentialDeleted,
+            &audit,
+            &mut *db,
+        )
+        .await?;
+    notifier
+        .notify(&username, Notification::SecondFactorRemoved { label })
+        .await;
+    Ok(Status::NoContent)
+}
+
+#[derive(Serialize)]
+pub(crate) struct ApiConsentSession {
+    client_id: Option<String>,
+    client_name: Option<String>,
+    scopes: Vec<String>,
+    granted_at: Option<String>,
+}
+
+/// Clients the user has given consent to that Hydra remembers.
+#[get("/me/sessions")]
+pub(crate) async fn get_sessions(
+    user: ApiUser,
+    hydra_config: &State<HydraConfig>,
+) -> Result<Json<Vec<ApiConsentSession>>, Error> {
+    let hydra_configuration: &Configuration = &hydra_config.inner().as_hydra_configuration();
+    let consent_sessions = ory_hydra_client::apis::o_auth2_api::list_o_auth2_consent_sessions(
+        hydra_configuration,
+        &user.get_username(),
+        None,
+        None,
+        None,
+    )
+    .await?;
+    Ok(Json(
+        consent_sessions
+            .into_iter()
+            .map(|session| {
+                let client = session
+                    .consent_request
+                    .and_then(|consent_request| consent_request.client);
+                ApiConsentSession {
+                    client_id: client.as_ref().and_then(|client| client.client_id.clone()),
+                    client_name: client.and_then(|client| client.client_name),
+                    scopes: session.grant_scope.unwrap_or_default(),
+                    granted_at: session.handled_at,
+                }
+            })
+            .collect(),
+    ))
+}
+
+/// Revokes the consent given to `client_id`, or without it every consent and login session
+/// and every legitima session, which logs the user out everywhere.
+#[delete("/me/sessions?<client_id>")]
+pub(crate) async fn delete_sessions(
+    user: ApiUser,
+    client_id: Option<&str>,
+    hydra_config: &State<HydraConfig>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+    mut revoker: SessionRevoker<'_>,
+) -> Result<Status, Error> {
+    let username = user.get_username();
+    match client_id {
+        Some(_) => revoke_sessions(hydra_config.inner(), &username, client_id).await?,
+        None => revoker.revoke_all(&username).await?,
+    }
+    audit
+        .with_actor(&username)
+        .record(
+            AuditEventType::SessionsRevoked,
+            &username,
+            json!({ "client_id": client_id }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,267 @@
+use ory_hydra_client::apis::configuration::Configuration;
+use ory_hydra_client::models::IntrospectedOAuth2Token;
+use rocket::http::{ContentType, Status};
+use rocket::outcome::try_outcome;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::{Request, State};
+use sha2::{Digest, Sha256};
+use sqlx::pool::PoolConnection;
+use sqlx::Postgres;
+
+use crate::config::{ApiConfig, AppConfig, HydraConfig};
+use crate::directory::Directory;
+use crate::error::Error;
+use crate::service_accounts::{ApiScope, ServiceAccount};
+use crate::sessions::owned_group_ids;
+
+pub(crate) mod groups;
+pub(crate) mod me;
+pub(crate) mod users;
+
+/// Who an API request is made by, from its `Authorization: Bearer` header.
+pub(crate) enum ApiPrincipal {
+    /// A user presenting an access token issued by Hydra.
+    User { username: String },
+    /// The holder of one of the configured admin keys.
+    AdminKey,
+    /// A service account presenting one of its keys.
+    ServiceAccount(ServiceAccount),
+}
+
+impl ApiPrincipal {
+    /// Name audit events are recorded with.
+    pub(crate) fn actor(&self) -> &str {
+        match self {
+            ApiPrincipal::User { username } => username,
+            ApiPrincipal::AdminKey => "api:admin_key",
+            ApiPrincipal::ServiceAccount(service_account) => service_account.actor(),
+        }
+    }
+
+    /// Admins and admin keys may do everything, service accounts what their key is scoped to.
+    /// Other users may only manage the members of the groups they own.
+    pub(crate) async fn allows(
+        &self,
+        scope: ApiScope,
+        directory: &dyn Directory,
+        app_config: &AppConfig,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<bool, Error> {
+        match self {
+            ApiPrincipal::User { username } => {
+                if directory
+                    .get_user_groups(username)
+                    .await?
+                    .contains(&app_config.ldap_admin_group_dn)
+                {
+                    return Ok(true);
+                }
+                match scope {
+                    ApiScope::GroupMembers(group_id) => {
+                        Ok(owned_group_ids(username, directory, connection)
+                            .await?
+                            .contains(&group_id))
+                    }
+                    _ => Ok(false),
+                }
+            }
+            ApiPrincipal::AdminKey => Ok(true),
+            ApiPrincipal::ServiceAccount(service_account) => Ok(service_account.allows(scope)),
+        }
+    }
+
+    /// Fails with 403 unless the principal [`allows`](Self::allows) `scope`.
+    pub(crate) async fn require(
+        &self,
+        scope: ApiScope,
+        directory: &dyn Directory,
+        app_config: &AppConfig,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<(), Error> {
+        match self
+            .allows(scope, directory, app_config, connection)
+            .await?
+        {
+            true => Ok(()),
+            false => Err(Error::Http(Status::Forbidden)),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiPrincipal {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<ApiPrincipal, Self::Error> {
+        match request.guard::<ServiceAccount>().await {
+            Outcome::Success(service_account) => {
+                return Outcome::Success(ApiPrincipal::ServiceAccount(service_account))
+            }
+            Outcome::Failure(failure) => return Outcome::Failure(failure),
+            Outcome::Forward(()) => {}
+        }
+        let token = match request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+        {
+            Some(token) => token.trim(),
+            None => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        let api_config = try_outcome!(request.guard::<&State<ApiConfig>>().await);
+        let key_hash = hex::encode(Sha256::digest(token.as_bytes()));
+        if api_config
+            .admin_key_hashes
+            .iter()
+            .any(|hash| hash.eq_ignore_ascii_case(&key_hash))
+        {
+            return Outcome::Success(ApiPrincipal::AdminKey);
+        }
+
+        let hydra_config = try_outcome!(request.guard::<&State<HydraConfig>>().await);
+        let hydra_configuration: &Configuration = &hydra_config.as_hydra_configuration();
+        let introspection = match ory_hydra_client::apis::o_auth2_api::introspect_o_auth2_token(
+            hydra_configuration,
+            token,
+            Some(&api_config.required_scope),
+        )
+        .await
+        {
+            Ok(introspection) => introspection,
+            Err(_) => return Outcome::Failure((Status::ServiceUnavailable, ())),
+        };
+        match token_username(introspection, api_config) {
+            Some(username) => Outcome::Success(ApiPrincipal::User { username }),
+            None => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// The user an access token was issued to. Hydra reports tokens without the required scope as
+/// inactive, the scope is checked again in case it ever stops doing so. Tokens from the client
+/// credentials grant carry the client ID as subject and belong to no user.
+fn token_username(
+    introspection: IntrospectedOAuth2Token,
+    api_config: &ApiConfig,
+) -> Option<String> {
+    if !introspection.active
+        || !introspection
+            .scope
+            .unwrap_or_default()
+            .split(' ')
+            .any(|scope| scope == api_config.required_scope)
+    {
+        return None;
+    }
+    let client_id = introspection.client_id?;
+    if !api_config.allowed_clients.is_empty() && !api_config.allowed_clients.contains(&client_id) {
+        return None;
+    }
+    introspection.sub.filter(|sub| *sub != client_id)
+}
+
+/// A user acting on their own account through the API.
+pub(crate) struct ApiUser(String);
+
+impl ApiUser {
+    pub(crate) fn get_username(self) -> String {
+        self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiUser {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<ApiUser, Self::Error> {
+        match try_outcome!(request.guard::<ApiPrincipal>().await) {
+            ApiPrincipal::User { username } => Outcome::Success(ApiUser(username)),
+            // Keys do not belong to a user who could have an own account.
+            ApiPrincipal::AdminKey | ApiPrincipal::ServiceAccount(_) => {
+                Outcome::Failure((Status::Forbidden, ()))
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct ApiError {
+    status: u16,
+    error: &'static str,
+}
+
+/// Answers every error below `/api` with JSON instead of an HTML page.
+#[catch(default)]
+pub(crate) fn api_error(status: Status, _request: &Request) -> Json<ApiError> {
+    Json(ApiError {
+        status: status.code,
+        error: status.reason().unwrap_or("Unknown Error"),
+    })
+}
+
+#[get("/openapi.json")]
+pub(crate) fn openapi() -> (ContentType, &'static str) {
+    (ContentType::JSON, include_str!("openapi.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn introspection(client_id: &str, sub: &str, scope: &str) -> IntrospectedOAuth2Token {
+        IntrospectedOAuth2Token {
+            client_id: Some(client_id.to_owned()),
+            sub: Some(sub.to_owned()),
+            scope: Some(scope.to_owned()),
+            ..IntrospectedOAuth2Token::new(true)
+        }
+    }
+
+    #[test]
+    fn user_tokens_need_the_scope() {
+        let api_config = ApiConfig::default();
+        assert_eq!(
+            token_username(
+                introspection("app", "jdoe", "openid legitima:api"),
+                &api_config
+            ),
+            Some("jdoe".to_owned())
+        );
+        assert_eq!(
+            token_username(introspection("app", "jdoe", "openid"), &api_config),
+            None
+        );
+        let inactive = IntrospectedOAuth2Token {
+            active: false,
+            ..introspection("app", "jdoe", "legitima:api")
+        };
+        assert_eq!(token_username(inactive, &api_config), None);
+    }
+
+    #[test]
+    fn client_credentials_tokens_are_rejected() {
+        assert_eq!(
+            token_username(
+                introspection("app", "app", "legitima:api"),
+                &ApiConfig::default()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn only_allowed_clients_are_accepted() {
+        let api_config = ApiConfig {
+            allowed_clients: vec!["app".to_owned()],
+            ..ApiConfig::default()
+        };
+        assert!(
+            token_username(introspection("app", "jdoe", "legitima:api"), &api_config).is_some()
+        );
+        assert!(
+            token_username(introspection("other", "jdoe", "legitima:api"), &api_config).is_none()
+        );
+    }
+}
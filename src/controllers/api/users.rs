@@ -0,0 +1,89 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket_db_pools::Connection;
+use serde_json::json;
+
+use crate::audit::{AuditContext, AuditEventType};
+use crate::config::AppConfig;
+use crate::controllers::api::ApiPrincipal;
+use crate::db::DB;
+use crate::directory::{Directory, LdapDirectory, Page, User, DEFAULT_PAGE_SIZE};
+use crate::error::Error;
+use crate::service_accounts::ApiScope;
+use crate::sessions::SessionRevoker;
+
+#[get("/users?<q>&<page>&<per_page>")]
+pub(crate) async fn list_users(
+    principal: ApiPrincipal,
+    q: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Json<Page<User>>, Error> {
+    principal
+        .require(ApiScope::UsersRead, &directory, app_config, &mut *db)
+        .await?;
+    let per_page = per_page.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, 1000);
+    Ok(Json(
+        directory
+            .search_users(&q.unwrap_or_default(), page.unwrap_or(1), per_page)
+            .await?,
+    ))
+}
+
+#[derive(Serialize)]
+pub(crate) struct ApiUserDetails {
+    #[serde(flatten)]
+    user: User,
+    /// DNs of the LDAP groups the user is a member of.
+    groups: Vec<String>,
+}
+
+#[get("/users/<username>")]
+pub(crate) async fn get_user(
+    principal: ApiPrincipal,
+    username: &str,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Json<ApiUserDetails>, Error> {
+    principal
+        .require(ApiScope::UsersRead, &directory, app_config, &mut *db)
+        .await?;
+    let user = directory.get_user(username).await?;
+    let groups = directory.get_user_groups(&user.username).await?;
+    Ok(Json(ApiUserDetails { user, groups }))
+}
+
+/// Revokes every consent and login session of the user in Hydra and deletes their legitima
+/// sessions.
+#[delete("/users/<username>/sessions")]
+pub(crate) async fn delete_sessions(
+    principal: ApiPrincipal,
+    username: &str,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+    mut revoker: SessionRevoker<'_>,
+) -> Result<Status, Error> {
+    principal
+        .require(ApiScope::UsersWrite, &directory, app_config, &mut *db)
+        .await?;
+    let user = directory.get_user(username).await?;
+    revoker.revoke_all(&user.username).await?;
+    audit
+        .with_actor(principal.actor())
+        .record(
+            AuditEventType::SessionsRevoked,
+            &user.username,
+            json!({ "client_id": null }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Status::NoContent)
+}
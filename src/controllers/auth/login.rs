@@ -1,8 +1,11 @@
+use crate::audit::{AuditContext, AuditEventType};
 use crate::config::{AppConfig, WebauthnStaticConfig};
-use crate::db::{DBTotpCredential, DBUserCredential, DBUserCredentialTypes, DB};
+use crate::db::{DBTotpCredential, DBUser2FAGrace, DBUserCredential, DBUserCredentialTypes, DB};
+use crate::directory::{Directory, LdapDirectory};
 use crate::error::Error;
+use crate::notifications::Notifier;
+use crate::policy::{has_second_factor_enrolled, in_grace_period, requires_2fa_enrolment};
 use crate::sessions::{create_session, Session, SessionStorage, User};
-use crate::DBLdapConn;
 use rocket::form::validate::Contains;
 use rocket::form::Form;
 use rocket::http::{CookieJar, Status};
@@ -12,6 +15,7 @@ use rocket::serde::Serialize;
 use rocket::{Either, State};
 use rocket_db_pools::Connection;
 use rocket_dyn_templates::{context, Template};
+use serde_json::json;
 use totp_rs::{Secret, TOTP};
 use webauthn_rs::proto::{PublicKeyCredential, RequestChallengeResponse};
 use webauthn_rs::{AuthenticationState, Webauthn};
@@ -43,26 +47,6 @@ pub(crate) fn login(app_config: &State<AppConfig>) -> Template {
     )
 }
 
-async fn check_user_pw(
-    ldap_conn: DBLdapConn,
-    ldap_user_base_dn: String,
-    username: String,
-    password: String,
-) -> Result<bool, Error> {
-    Ok(ldap_conn
-        .run(move |c| {
-            let bind = c.simple_bind(
-                &*format!("uid={},{}", username, ldap_user_base_dn),
-                &*password,
-            );
-            c.unbind(); // TODO: Handle error
-            bind
-        })
-        .await?
-        .success()
-        .is_ok())
-}
-
 #[derive(FromForm)]
 pub(crate) struct Login {
     username: String,
@@ -72,17 +56,16 @@ pub(crate) struct Login {
 #[post("/login", data = "<form>")]
 pub(crate) async fn submit(
     cookies: &CookieJar<'_>,
-    ldap_conn: DBLdapConn,
+    directory: LdapDirectory<'_>,
     mut db: Connection<DB>,
     session_storage: Connection<SessionStorage>,
     form: Form<Login>,
     app_config: &State<AppConfig>,
+    audit: AuditContext,
+    notifier: Notifier<'_>,
 ) -> Result<Either<Template, Redirect>, Error> {
     let app_config = app_config.inner();
     let form = form.into_inner();
-    let username = form.username.clone();
-    let password = form.password.clone();
-    let ldap_user_base_dn = app_config.ldap_user_base_dn.clone();
     if form.username.is_empty() || form.password.is_empty() {
         return Ok(Either::Left(Template::render(
             "login",
@@ -91,18 +74,25 @@ pub(crate) async fn submit(
                 message: Some("Username and password cannot be empty".to_owned()),
             },
         )));
-    } else if check_user_pw(ldap_conn, ldap_user_base_dn, username, password).await? {
-        return if !DBUserCredential::<DBTotpCredential>::find_permanent_credentials_by_username(
-            &*form.username,
-            &mut *db,
-        )
-        .await?
-        .is_empty()
-        {
+    }
+    let authenticated = directory
+        .authenticate(&form.username, &form.password)
+        .await?;
+    if let Some(username) = authenticated {
+        let audit = audit.with_actor(&username);
+        return if has_second_factor_enrolled(&username, &mut db).await? {
+            audit
+                .record(
+                    AuditEventType::LoginSucceeded,
+                    &username,
+                    json!({ "second_factor_required": true }),
+                    &mut *db,
+                )
+                .await?;
             create_session(
                 session_storage,
                 &Session::new(
-                    form.username,
+                    username,
                     false,
                     vec!["password".to_owned()],
                     vec!["2fa".to_owned()],
@@ -117,17 +107,48 @@ pub(crate) async fn submit(
                 Some(cookie) => cookie.value().to_owned(),
                 None => "/".to_owned(),
             };
+            let enrolment_required =
+                requires_2fa_enrolment(app_config, &directory, &username, &mut db).await?;
+            if enrolment_required {
+                DBUser2FAGrace::record_login(&username, &mut *db).await?;
+            }
+            audit
+                .record(
+                    AuditEventType::LoginSucceeded,
+                    &username,
+                    json!({ "second_factor_required": false }),
+                    &mut *db,
+                )
+                .await?;
+            notifier
+                .login_completed(&username, cookies, &mut *db)
+                .await?;
 
             create_session(
                 session_storage,
-                &Session::new(form.username, true, vec!["password".to_owned()], vec![]),
+                &Session::new(username, true, vec!["password".to_owned()], vec![]),
                 cookies,
             )
             .await?;
 
-            Ok(Either::Right(Redirect::to(redirect_url)))
+            if enrolment_required {
+                Ok(Either::Right(Redirect::to(uri!(
+                    "/auth",
+                    two_factor_enroll()
+                ))))
+            } else {
+                Ok(Either::Right(Redirect::to(redirect_url)))
+            }
         };
     }
+    audit
+        .record(
+            AuditEventType::LoginFailed,
+            &form.username,
+            json!({}),
+            &mut *db,
+        )
+        .await?;
     Ok(Either::Left(Template::render(
         "login",
         LoginContext {
@@ -164,6 +185,48 @@ pub(crate) async fn two_factor(
     ))
 }
 
+#[get("/2fa/enroll")]
+pub(crate) async fn two_factor_enroll(
+    user: User,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    cookies: &CookieJar<'_>,
+) -> Result<Template, Error> {
+    let app_config = app_config.inner();
+    let username = user.get_username();
+    let continue_url = match cookies.get("redirect_url") {
+        Some(cookie) => cookie.value().to_owned(),
+        None => "/".to_owned(),
+    };
+    let grace = DBUser2FAGrace::find_by_username(&username, &mut *db).await?;
+    let can_skip = requires_2fa_enrolment(app_config, &directory, &username, &mut db).await?
+        && in_grace_period(app_config, grace.as_ref());
+    let grace_logins_left = match (&grace, app_config.enforce_2fa_grace_logins) {
+        (Some(grace), grace_logins) if grace_logins > 0 => Some(grace_logins - grace.login_count),
+        _ => None,
+    };
+    let grace_until = match (&grace, app_config.enforce_2fa_grace_days) {
+        (Some(grace), grace_days) if grace_days > 0 => Some(
+            (grace.first_login_at + chrono::Duration::days(grace_days))
+                .format("%Y-%m-%d")
+                .to_string(),
+        ),
+        _ => None,
+    };
+
+    Ok(Template::render(
+        "2fa_enroll",
+        context! {
+            app_name: app_config.name.clone(),
+            continue_url,
+            can_skip,
+            grace_logins_left,
+            grace_until
+        },
+    ))
+}
+
 #[derive(FromForm, Debug)]
 pub(crate) struct TOTPAuthForm {
     otp: String,
@@ -177,6 +240,8 @@ pub(crate) async fn totp_2fa(
     mut db: Connection<DB>,
     session_storage: Connection<SessionStorage>,
     cookies: &CookieJar<'_>,
+    audit: AuditContext,
+    notifier: Notifier<'_>,
 ) -> Result<Either<Redirect, Template>, Error> {
     let form = form.into_inner();
     let app_config = app_config.inner();
@@ -205,11 +270,30 @@ pub(crate) async fn totp_2fa(
                 Some(cookie) => cookie.value().to_owned(),
                 None => "/".to_owned(),
             };
-            session.finish_step("2fa", session_storage).await?;
+            audit
+                .record(
+                    AuditEventType::SecondFactorSucceeded,
+                    &session.username,
+                    json!({ "method": "totp", "credential_id": credential.id }),
+                    &mut *db,
+                )
+                .await?;
+            notifier
+                .login_completed(&session.username, cookies, &mut *db)
+                .await?;
+            session.finish_step("2fa", "totp", session_storage).await?;
 
             return Ok(Either::Left(Redirect::to(redirect_url)));
         }
     }
+    audit
+        .record(
+            AuditEventType::SecondFactorFailed,
+            &session.username,
+            json!({ "method": "totp" }),
+            &mut *db,
+        )
+        .await?;
 
     let available_credential_types =
         DBUserCredential::<DBTotpCredential>::find_permanent_credentials_by_username(
@@ -283,6 +367,8 @@ pub(crate) async fn webauthn_2fa_login(
     mut db: Connection<DB>,
     session_storage: Connection<SessionStorage>,
     cookies: &CookieJar<'_>,
+    audit: AuditContext,
+    notifier: Notifier<'_>,
 ) -> Result<String, Error> {
     let webauthn_static_config = webauthn_static_config.inner().clone();
     let webauthn_client = Webauthn::new(webauthn_static_config);
@@ -310,9 +396,32 @@ pub(crate) async fn webauthn_2fa_login(
             // dbg!(credential.counter);
             // DBUserCredential::<Credential>::update_counter(cid, credential.counter, &mut *db)
             //     .await?;
-            session.finish_step("2fa", session_storage).await?;
+            audit
+                .record(
+                    AuditEventType::SecondFactorSucceeded,
+                    &session.username,
+                    json!({ "method": "webauthn" }),
+                    &mut *db,
+                )
+                .await?;
+            notifier
+                .login_completed(&session.username, cookies, &mut *db)
+                .await?;
+            session
+                .finish_step("2fa", "webauthn", session_storage)
+                .await?;
             Ok(redirect_url)
         }
-        Err(_) => Err(Error::Http(Status::InternalServerError)),
+        Err(_) => {
+            audit
+                .record(
+                    AuditEventType::SecondFactorFailed,
+                    &session.username,
+                    json!({ "method": "webauthn" }),
+                    &mut *db,
+                )
+                .await?;
+            Err(Error::Http(Status::InternalServerError))
+        }
     }
 }
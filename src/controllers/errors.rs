@@ -44,6 +44,18 @@ pub(crate) fn not_found(req: &Request) -> Template {
     )
 }
 
+#[catch(409)]
+pub(crate) fn conflict() -> Template {
+    Template::render(
+        "error",
+        ErrorContext {
+            error_title: "409 - Conflict".to_owned(),
+            error_message: "Someone else changed this in the meantime. Please go back, reload the page and try again."
+                .to_owned(),
+        },
+    )
+}
+
 #[catch(500)]
 pub(crate) fn internal_server_error() -> Template {
     Template::render(
@@ -55,3 +67,15 @@ pub(crate) fn internal_server_error() -> Template {
         },
     )
 }
+
+#[catch(503)]
+pub(crate) fn service_unavailable() -> Template {
+    Template::render(
+        "error",
+        ErrorContext {
+            error_title: "503 - Service Unavailable".to_owned(),
+            error_message: "Oh no! A service we depend on is currently unavailable. Please try again in a few minutes."
+                .to_owned(),
+        },
+    )
+}
@@ -1,6 +1,8 @@
 pub(crate) mod admin;
+pub(crate) mod api;
 pub(crate) mod auth;
 pub(crate) mod oidc;
+pub(crate) mod scim;
 pub(crate) mod selfservice;
 
 pub(crate) mod errors;
@@ -8,20 +8,23 @@ use ory_hydra_client::models::{
 use rocket::http::Status;
 use rocket::response::Redirect;
 use rocket::{get, Either, State};
+use rocket_db_pools::Connection;
 use rocket_dyn_templates::Template;
 use serde::Serialize;
 use serde_json::json;
 
+use crate::audit::{AuditContext, AuditEventType};
 use crate::config::{AppConfig, HydraConfig};
+use crate::db::{DBOAuthClient, DB};
+use crate::directory::{Directory, LdapDirectory, User};
 use crate::error::Error;
-use crate::DBLdapConn;
 
 #[derive(Serialize, Clone)]
 struct Scope {
     name: &'static str,
     short_description: &'static str,
     description: &'static str,
-    ldap_fields: Vec<(&'static str, &'static str)>,
+    claims: Vec<&'static str>,
     icon: &'static str,
 }
 
@@ -33,7 +36,7 @@ fn get_scopes() -> HashMap<&'static str, Scope> {
                 name: "email",
                 short_description: "View your email address",
                 description: "The service gets access to your email address",
-                ldap_fields: vec![("email", "mail")],
+                claims: vec!["email"],
                 icon: "openmoji/email.svg",
             },
         ),
@@ -43,12 +46,7 @@ fn get_scopes() -> HashMap<&'static str, Scope> {
                 name: "profile",
                 short_description: "Get your general profile information",
                 description: "The service gets access to general information of your profile",
-                ldap_fields: vec![
-                    ("name", "displayName"),
-                    ("given_name", "cn"),
-                    ("family_name", "sn"),
-                    ("preferred_username", "uid"),
-                ],
+                claims: vec!["name", "given_name", "family_name", "preferred_username"],
                 icon: "openmoji/person.svg",
             },
         ),
@@ -67,10 +65,12 @@ struct ConsentContext {
 
 #[get("/consent?<consent_challenge>")]
 pub(crate) async fn index(
-    ldap_conn: DBLdapConn,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
     consent_challenge: &str,
     hydra_config: &State<HydraConfig>,
     app_config: &State<AppConfig>,
+    audit: AuditContext,
 ) -> Result<Either<Template, Redirect>, Error> {
     let app_config = app_config.inner();
     let hydra_configuration: &Configuration = &hydra_config.inner().as_hydra_configuration();
@@ -80,22 +80,33 @@ pub(crate) async fn index(
     )
     .await?;
 
-    if let Some(skip) = consent_request.skip {
-        if skip {
-            return match accept_consent_request(
-                &ldap_conn,
-                hydra_config.inner(),
-                hydra_configuration,
-                consent_challenge,
-                consent_request,
-                app_config,
-            )
-            .await
-            {
-                Ok(redirect) => Ok(Either::Right(redirect)),
-                Err(error) => Err(error),
-            };
-        }
+    let trusted_client = match consent_request
+        .client
+        .as_ref()
+        .and_then(|client| client.client_id.as_ref())
+    {
+        Some(client_id) => DBOAuthClient::find_by_client_id(client_id, &mut *db)
+            .await?
+            .map_or(false, |db_client| db_client.skip_consent),
+        None => false,
+    };
+
+    if consent_request.skip.unwrap_or(false) || trusted_client {
+        return match accept_consent_request(
+            &directory,
+            hydra_config.inner(),
+            hydra_configuration,
+            consent_challenge,
+            consent_request,
+            true,
+            &audit,
+            &mut db,
+        )
+        .await
+        {
+            Ok(redirect) => Ok(Either::Right(redirect)),
+            Err(error) => Err(error),
+        };
     }
 
     let requested_scope_details: Vec<Scope> = consent_request
@@ -122,13 +133,13 @@ pub(crate) async fn index(
 
 #[get("/consent/approve?<consent_challenge>")]
 pub(crate) async fn approve(
-    ldap_conn: DBLdapConn,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
     consent_challenge: &str,
     hydra_config: &State<HydraConfig>,
-    app_config: &State<AppConfig>,
+    audit: AuditContext,
 ) -> Result<Redirect, Error> {
     let hydra_configuration: &Configuration = &hydra_config.inner().as_hydra_configuration();
-    let app_config = app_config.inner();
     let consent_request = ory_hydra_client::apis::o_auth2_api::get_o_auth2_consent_request(
         hydra_configuration,
         consent_challenge,
@@ -136,22 +147,31 @@ pub(crate) async fn approve(
     .await?;
 
     accept_consent_request(
-        &ldap_conn,
+        &directory,
         hydra_config.inner(),
         hydra_configuration,
         consent_challenge,
         consent_request,
-        app_config,
+        false,
+        &audit,
+        &mut db,
     )
     .await
 }
 
 #[get("/consent/reject?<consent_challenge>")]
 pub(crate) async fn reject(
+    mut db: Connection<DB>,
     consent_challenge: &str,
     hydra_config: &State<HydraConfig>,
+    audit: AuditContext,
 ) -> Result<Redirect, Error> {
     let hydra_configuration: &Configuration = &hydra_config.inner().as_hydra_configuration();
+    let consent_request = ory_hydra_client::apis::o_auth2_api::get_o_auth2_consent_request(
+        hydra_configuration,
+        consent_challenge,
+    )
+    .await?;
     let reject_consent_request =
         ory_hydra_client::apis::o_auth2_api::reject_o_auth2_consent_request(
             hydra_configuration,
@@ -165,38 +185,47 @@ pub(crate) async fn reject(
             }),
         )
         .await?;
+    if let Some(subject) = &consent_request.subject {
+        audit
+            .record(
+                AuditEventType::ConsentRejected,
+                subject,
+                json!({
+                    "client_id": client_id(&consent_request),
+                    "scopes": consent_request.requested_scope,
+                }),
+                &mut *db,
+            )
+            .await?;
+    }
     Ok(Redirect::to(reject_consent_request.redirect_to))
 }
+
+/// Accepts the consent request; `automatic` tells whether the user was asked at all.
+#[allow(clippy::too_many_arguments)]
 async fn accept_consent_request(
-    ldap_conn: &DBLdapConn,
+    directory: &dyn Directory,
     hydra_config: &HydraConfig,
     hydra_configuration: &Configuration,
     consent_challenge: &str,
     consent_request: OAuth2ConsentRequest,
-    app_config: &AppConfig,
+    automatic: bool,
+    audit: &AuditContext,
+    db: &mut Connection<DB>,
 ) -> Result<Redirect, Error> {
-    let ldap_user_base_dn = app_config.ldap_user_base_dn.clone();
-    let (ldap_search_rs, _) = ldap_conn
-        .run(move |c| {
-            c.search(
-                &*format!(
-                    "uid={},{}",
-                    consent_request.subject.unwrap(),
-                    ldap_user_base_dn
-                ),
-                ldap3::Scope::Base,
-                "objectClass=inetOrgPerson",
-                vec!["*"],
-            )
-        })
-        .await?
-        .success()?;
-
-    let ldap_user_data = if let Some(ldap_search_user) = ldap_search_rs.into_iter().next() {
-        ldap3::SearchEntry::construct(ldap_search_user)
-    } else {
-        return Err(Error::Http(Status::BadRequest));
+    let subject = consent_request
+        .subject
+        .as_ref()
+        .ok_or(Error::Http(Status::BadRequest))?;
+    let user = match directory.get_user(subject).await {
+        Ok(user) => user,
+        Err(Error::Http(status)) if status == Status::NotFound => {
+            return Err(Error::Http(Status::BadRequest))
+        }
+        Err(error) => return Err(error),
     };
+    let client_id = client_id(&consent_request);
+    let scopes = consent_request.requested_scope.clone();
 
     let accept_consent_request =
         ory_hydra_client::apis::o_auth2_api::accept_o_auth2_consent_request(
@@ -209,39 +238,58 @@ async fn accept_consent_request(
                 remember: Some(hydra_config.consent_remember_me),
                 remember_for: Some(hydra_config.consent_remember_me_for),
                 session: Some(Box::new(data_to_session(
-                    ldap_user_data,
+                    &user,
                     consent_request.requested_scope.unwrap(),
                 ))),
             }),
         )
         .await?;
+    audit
+        .record(
+            AuditEventType::ConsentAccepted,
+            &user.username,
+            json!({
+                "client_id": client_id,
+                "scopes": scopes,
+                "automatic": automatic,
+            }),
+            &mut *db,
+        )
+        .await?;
     Ok(Redirect::to(accept_consent_request.redirect_to))
 }
 
-fn data_to_session(
-    ldap_user: ldap3::SearchEntry,
-    scopes: Vec<String>,
-) -> AcceptOAuth2ConsentRequestSession {
+fn client_id(consent_request: &OAuth2ConsentRequest) -> Option<String> {
+    consent_request
+        .client
+        .as_ref()
+        .and_then(|client| client.client_id.clone())
+}
+
+fn data_to_session(user: &User, scopes: Vec<String>) -> AcceptOAuth2ConsentRequestSession {
     let mut consent_request_session = AcceptOAuth2ConsentRequestSession::new();
     let mut id_token_data = HashMap::new();
-    let default_vec: Vec<String> = Vec::new();
-    let default_str = String::new();
 
     for scope in &scopes {
         if let Some(scope_data) = get_scopes().get(scope as &str) {
-            for ldap_fieldpair in &scope_data.ldap_fields {
-                id_token_data.insert(
-                    ldap_fieldpair.0,
-                    &**ldap_user
-                        .attrs
-                        .get(ldap_fieldpair.1)
-                        .unwrap_or(&default_vec)
-                        .first()
-                        .unwrap_or(&default_str),
-                );
+            for claim in &scope_data.claims {
+                if let Some(value) = user_claim(user, claim) {
+                    id_token_data.insert(*claim, value);
+                }
             }
         }
     }
     consent_request_session.id_token = Some(json!(id_token_data));
     consent_request_session
 }
+
+fn user_claim<'u>(user: &'u User, claim: &str) -> Option<&'u str> {
+    match claim {
+        "email" => Some(&user.email),
+        "name" => Some(&user.name),
+        "given_name" => Some(&user.first_name),
+        "family_name" => Some(&user.last_name),
+        "preferred_username" => Some(&user.username),
+        _ => None,
+    }
+}
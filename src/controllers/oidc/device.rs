@@ -0,0 +1,139 @@
+use ory_hydra_client::apis::configuration::Configuration;
+use ory_hydra_client::apis::Error as HydraError;
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::{get, post, Either, State};
+use rocket_dyn_templates::{context, Template};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AppConfig, HydraConfig};
+use crate::error::Error;
+
+#[derive(Serialize)]
+struct DeviceContext {
+    app_name: String,
+    message: Option<String>,
+    device_challenge: String,
+    user_code: String,
+}
+
+/// User code entry page, configured as Hydra's `urls.device.verification`.
+#[get("/device?<device_challenge>&<user_code>")]
+pub(crate) fn index(
+    device_challenge: &str,
+    user_code: Option<&str>,
+    app_config: &State<AppConfig>,
+) -> Template {
+    let app_config = app_config.inner();
+    Template::render(
+        "device",
+        DeviceContext {
+            app_name: app_config.name.clone(),
+            message: None,
+            device_challenge: device_challenge.to_owned(),
+            user_code: user_code.unwrap_or_default().to_owned(),
+        },
+    )
+}
+
+#[derive(FromForm)]
+pub(crate) struct DeviceUserCode {
+    device_challenge: String,
+    user_code: String,
+}
+
+#[post("/device", data = "<form>")]
+pub(crate) async fn submit(
+    form: Form<DeviceUserCode>,
+    hydra_config: &State<HydraConfig>,
+    app_config: &State<AppConfig>,
+) -> Result<Either<Template, Redirect>, Error> {
+    let app_config = app_config.inner();
+    let form = form.into_inner();
+    let hydra_configuration: &Configuration = &hydra_config.inner().as_hydra_configuration();
+    let user_code = form.user_code.trim().to_owned();
+
+    let message = if user_code.is_empty() {
+        "The code cannot be empty"
+    } else {
+        match accept_user_code_request(hydra_configuration, &form.device_challenge, &user_code)
+            .await
+        {
+            Ok(redirect_to) => return Ok(Either::Right(Redirect::to(redirect_to))),
+            Err(Error::Hydra { status }) if status == Status::BadRequest => {
+                "The code is invalid or has expired. Please check the code shown on your device."
+            }
+            Err(error) => return Err(error),
+        }
+    };
+
+    Ok(Either::Left(Template::render(
+        "device",
+        DeviceContext {
+            app_name: app_config.name.clone(),
+            message: Some(message.to_owned()),
+            device_challenge: form.device_challenge,
+            user_code,
+        },
+    )))
+}
+
+/// Success page, configured as Hydra's `urls.device.success`.
+#[get("/device/done")]
+pub(crate) fn done(app_config: &State<AppConfig>) -> Template {
+    let app_config = app_config.inner();
+    Template::render(
+        "device_done",
+        context! {
+            app_name: app_config.name.clone()
+        },
+    )
+}
+
+#[derive(Serialize)]
+struct AcceptDeviceUserCodeRequest<'a> {
+    user_code: &'a str,
+}
+
+#[derive(Deserialize)]
+struct DeviceUserCodeRedirect {
+    redirect_to: String,
+}
+
+/// The generated Hydra client does not cover the device authorization endpoints yet, so the
+/// admin API is called directly with the client's HTTP client.
+async fn accept_user_code_request(
+    hydra_configuration: &Configuration,
+    device_challenge: &str,
+    user_code: &str,
+) -> Result<String, Error> {
+    let response = hydra_configuration
+        .client
+        .put(format!(
+            "{}/admin/oauth2/auth/requests/device/accept",
+            hydra_configuration.base_path
+        ))
+        .query(&[("device_challenge", device_challenge)])
+        .json(&AcceptDeviceUserCodeRequest { user_code })
+        .send()
+        .await
+        .map_err(HydraError::<()>::from)?;
+
+    let status = response.status();
+    if status.is_client_error() {
+        return Err(Error::Hydra {
+            status: Status::BadRequest,
+        });
+    } else if !status.is_success() {
+        return Err(Error::Hydra {
+            status: Status::ServiceUnavailable,
+        });
+    }
+
+    Ok(response
+        .json::<DeviceUserCodeRedirect>()
+        .await
+        .map_err(HydraError::<()>::from)?
+        .redirect_to)
+}
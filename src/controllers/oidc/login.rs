@@ -1,59 +1,213 @@
 use ory_hydra_client::apis::configuration::Configuration;
-use ory_hydra_client::models::AcceptOAuth2LoginRequest;
+use ory_hydra_client::models::{AcceptOAuth2LoginRequest, OAuth2LoginRequest};
 use rocket::http::{Cookie, CookieJar};
 use rocket::response::Redirect;
-use rocket::{get, Either, State};
-use rocket_dyn_templates::Template;
+use rocket::{get, State};
+use rocket_db_pools::Connection;
 
-use crate::config::HydraConfig;
+use crate::config::{AcrConfig, AppConfig, HydraConfig};
+use crate::db::{DBOAuthClient, DBUser2FAGrace, DB};
+use crate::directory::LdapDirectory;
 use crate::error::Error;
-use crate::sessions::User;
+use crate::policy::{has_second_factor_enrolled, in_grace_period, requires_2fa_enrolment};
+use crate::sessions::{delete_session, Session, SessionStorage};
 
 #[get("/login?<login_challenge>")]
 pub(crate) async fn auth_index(
-    user: User,
+    session: Session,
     login_challenge: &str,
     hydra_config: &State<HydraConfig>,
-) -> Result<Either<Template, Redirect>, Error> {
-    let hydra_configuration: &Configuration = &hydra_config.inner().as_hydra_configuration();
-    let _login_request = ory_hydra_client::apis::o_auth2_api::get_o_auth2_login_request(
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    session_storage: Connection<SessionStorage>,
+    cookies: &CookieJar<'_>,
+) -> Result<Redirect, Error> {
+    let hydra_config = hydra_config.inner();
+    let app_config = app_config.inner();
+    let hydra_configuration: &Configuration = &hydra_config.as_hydra_configuration();
+    let login_request = ory_hydra_client::apis::o_auth2_api::get_o_auth2_login_request(
         hydra_configuration,
         login_challenge,
     )
     .await?;
-    // let ids: Vec<String> = db_sql
-    //     .run(move |conn| {
-    //         db::oauth_client::table
-    //             .select(db::oauth_client::client_id)
-    //             .load(conn)
-    //     })
-    //     .await?;
-    // dbg!(ids);
-    let accept_login_request = ory_hydra_client::apis::o_auth2_api::accept_o_auth2_login_request(
+
+    cookies.add(Cookie::new(
+        "redirect_url",
+        uri!("/oidc", auth_index(login_challenge)).to_string(),
+    ));
+    if !session.fully_authenticated {
+        return Ok(Redirect::to(uri!("/auth/2fa")));
+    }
+
+    if !login_request.skip && requires_reauthentication(&login_request, &session) {
+        // The private cookie is only set right before the old session is dropped, so a session
+        // presented together with it was created after the client asked for re-authentication.
+        let reauthenticated = cookies
+            .get_private("login_reauth_challenge")
+            .map_or(false, |cookie| cookie.value() == login_challenge);
+        if reauthenticated {
+            cookies.remove_private(Cookie::named("login_reauth_challenge"));
+        } else {
+            cookies.add_private(Cookie::new(
+                "login_reauth_challenge",
+                login_challenge.to_owned(),
+            ));
+            delete_session(session_storage, session, cookies).await?;
+            return Ok(Redirect::to(uri!(
+                "/auth",
+                crate::controllers::auth::login::login()
+            )));
+        }
+    }
+
+    let client_requires_2fa = client_requires_2fa(&login_request, &mut db).await?;
+    if (client_requires_2fa || requests_multi_factor(&login_request, &hydra_config.acr))
+        && !session.has_second_factor()
+    {
+        if has_second_factor_enrolled(&session.username, &mut db).await? {
+            session.require_step("2fa", session_storage).await?;
+            return Ok(Redirect::to(uri!("/auth/2fa")));
+        }
+        // Accepting at the single-factor ACR would silently downgrade a request for the
+        // multi-factor ACR, so the user has to enrol first.
+        return Ok(Redirect::to(uri!(
+            "/auth",
+            crate::controllers::auth::login::two_factor_enroll()
+        )));
+    }
+
+    if !session.has_second_factor()
+        && requires_2fa_enrolment(app_config, &directory, &session.username, &mut db).await?
+        && !in_grace_period(
+            app_config,
+            DBUser2FAGrace::find_by_username(&session.username, &mut *db)
+                .await?
+                .as_ref(),
+        )
+    {
+        return Ok(Redirect::to(uri!(
+            "/auth",
+            crate::controllers::auth::login::two_factor_enroll()
+        )));
+    }
+
+    let subject = if login_request.skip {
+        login_request.subject
+    } else {
+        session.username.clone()
+    };
+    accept_login_request(
+        hydra_config,
         hydra_configuration,
         login_challenge,
-        Some(AcceptOAuth2LoginRequest {
-            acr: None,
-            amr: None,
-            context: None,
-            force_subject_identifier: None,
-            remember: None,
-            remember_for: None,
-            subject: user.get_username(),
-        }),
+        subject,
+        Some(&session),
     )
-    .await?;
-
-    Ok(Either::Right(Redirect::to(
-        accept_login_request.redirect_to,
-    )))
+    .await
 }
 
 #[get("/login?<login_challenge>", rank = 2)]
-pub(crate) async fn index(login_challenge: &str, cookies: &CookieJar<'_>) -> Redirect {
+pub(crate) async fn index(
+    login_challenge: &str,
+    hydra_config: &State<HydraConfig>,
+    mut db: Connection<DB>,
+    cookies: &CookieJar<'_>,
+) -> Result<Redirect, Error> {
+    let hydra_config = hydra_config.inner();
+    let hydra_configuration: &Configuration = &hydra_config.as_hydra_configuration();
+    let login_request = ory_hydra_client::apis::o_auth2_api::get_o_auth2_login_request(
+        hydra_configuration,
+        login_challenge,
+    )
+    .await?;
+
+    if login_request.skip
+        && !requests_multi_factor(&login_request, &hydra_config.acr)
+        && !client_requires_2fa(&login_request, &mut db).await?
+    {
+        return accept_login_request(
+            hydra_config,
+            hydra_configuration,
+            login_challenge,
+            login_request.subject,
+            None,
+        )
+        .await;
+    }
+
     cookies.add(Cookie::new(
         "redirect_url",
         uri!("/oidc", auth_index(login_challenge)).to_string(),
     ));
-    Redirect::to(uri!("/auth", crate::controllers::auth::login::login()))
+    Ok(Redirect::to(uri!(
+        "/auth",
+        crate::controllers::auth::login::login()
+    )))
+}
+
+/// Checks whether the client asked for `prompt=login` or passed a `max_age` the session's
+/// authentication time no longer satisfies.
+fn requires_reauthentication(login_request: &OAuth2LoginRequest, session: &Session) -> bool {
+    let request_url = match url::Url::parse(&login_request.request_url) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+    request_url.query_pairs().any(|(key, value)| match &*key {
+        "prompt" => value.split(' ').any(|prompt| prompt == "login"),
+        "max_age" => match (value.parse::<i64>(), session.authenticated_since()) {
+            (Ok(max_age), Some(since)) => since.num_seconds() > max_age,
+            (Ok(_), None) => true,
+            (Err(_), _) => false,
+        },
+        _ => false,
+    })
+}
+
+/// Checks whether the client asked for the multi-factor ACR via `acr_values`.
+fn requests_multi_factor(login_request: &OAuth2LoginRequest, acr_config: &AcrConfig) -> bool {
+    login_request
+        .oidc_context
+        .as_ref()
+        .and_then(|oidc_context| oidc_context.acr_values.as_ref())
+        .map_or(false, |acr_values| {
+            acr_values.contains(&acr_config.multi_factor)
+        })
+}
+
+async fn client_requires_2fa(
+    login_request: &OAuth2LoginRequest,
+    db: &mut Connection<DB>,
+) -> Result<bool, Error> {
+    Ok(match login_request.client.client_id.as_ref() {
+        Some(client_id) => DBOAuthClient::find_by_client_id(client_id, &mut *db)
+            .await?
+            .map_or(false, |db_client| db_client.require_2fa),
+        None => false,
+    })
+}
+
+async fn accept_login_request(
+    hydra_config: &HydraConfig,
+    hydra_configuration: &Configuration,
+    login_challenge: &str,
+    subject: String,
+    session: Option<&Session>,
+) -> Result<Redirect, Error> {
+    let accept_login_request = ory_hydra_client::apis::o_auth2_api::accept_o_auth2_login_request(
+        hydra_configuration,
+        login_challenge,
+        Some(AcceptOAuth2LoginRequest {
+            acr: session.map(|session| session.acr(&hydra_config.acr)),
+            amr: session.map(|session| session.amr()),
+            context: None,
+            force_subject_identifier: None,
+            remember: Some(hydra_config.login_remember_me),
+            remember_for: Some(hydra_config.login_remember_me_for),
+            subject,
+        }),
+    )
+    .await?;
+
+    Ok(Redirect::to(accept_login_request.redirect_to))
 }
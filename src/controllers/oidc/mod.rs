@@ -1,2 +1,3 @@
 pub(crate) mod consent;
+pub(crate) mod device;
 pub(crate) mod login;
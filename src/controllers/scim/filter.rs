@@ -0,0 +1,378 @@
+/// Deepest nesting of parentheses and `not` accepted, which keeps parsing and evaluating a
+/// filter from overflowing the stack.
+const MAX_NESTING: usize = 16;
+/// Most comparisons in one filter. Every `and` and `or` nests the parsed filter one level
+/// deeper, so this bounds the depth of long chains.
+const MAX_COMPARISONS: usize = 64;
+
+/// Comparison operators; `pr` has no value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operator {
+    Eq,
+    Ne,
+    Co,
+    Sw,
+    Ew,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Pr,
+}
+
+/// The part of the SCIM filter syntax (RFC 7644, section 3.4.2.2) provisioning clients use:
+/// comparisons of one attribute, combined with `and`, `or` and `not` and grouped with
+/// parentheses. Filters are evaluated in memory against the values a resource reports for an
+/// attribute path; all comparisons ignore case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Filter {
+    Compare {
+        /// Lowercase attribute path, e.g. `username` or `emails.value`.
+        attribute: String,
+        operator: Operator,
+        value: String,
+    },
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub(crate) fn parse(filter: &str) -> Result<Filter, ()> {
+        let tokens = tokenize(filter)?;
+        let mut parser = Parser {
+            tokens,
+            next: 0,
+            depth: 0,
+            comparisons: 0,
+        };
+        let filter = parser.or()?;
+        match parser.next < parser.tokens.len() {
+            true => Err(()),
+            false => Ok(filter),
+        }
+    }
+
+    /// Evaluates the filter; `values` returns the values of an attribute path, which must be
+    /// lowercase.
+    pub(crate) fn matches(&self, values: &dyn Fn(&str) -> Vec<String>) -> bool {
+        match self {
+            Filter::Compare {
+                attribute,
+                operator,
+                value,
+            } => {
+                let value = value.to_lowercase();
+                let matching = |actual: &String| {
+                    let actual = actual.to_lowercase();
+                    match operator {
+                        Operator::Eq | Operator::Ne => actual == value,
+                        Operator::Co => actual.contains(&value),
+                        Operator::Sw => actual.starts_with(&value),
+                        Operator::Ew => actual.ends_with(&value),
+                        Operator::Gt => actual > value,
+                        Operator::Ge => actual >= value,
+                        Operator::Lt => actual < value,
+                        Operator::Le => actual <= value,
+                        Operator::Pr => !actual.is_empty(),
+                    }
+                };
+                match operator {
+                    Operator::Ne => !values(attribute).iter().any(matching),
+                    _ => values(attribute).iter().any(matching),
+                }
+            }
+            Filter::And(left, right) => left.matches(values) && right.matches(values),
+            Filter::Or(left, right) => left.matches(values) || right.matches(values),
+            Filter::Not(filter) => !filter.matches(values),
+        }
+    }
+
+    /// The value a filter of the form `<attribute> eq "<value>"` looks for, which lets a lookup
+    /// by unique attribute skip listing every resource.
+    pub(crate) fn equality_value(&self, attribute: &str) -> Option<&str> {
+        match self {
+            Filter::Compare {
+                attribute: compared,
+                operator: Operator::Eq,
+                value,
+            } if compared == attribute => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Value(String),
+    Open,
+    Close,
+}
+
+fn tokenize(filter: &str) -> Result<Vec<Token>, ()> {
+    let mut tokens = Vec::new();
+    let mut chars = filter.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next().ok_or(())? {
+                        '"' => break,
+                        '\\' => value.push(chars.next().ok_or(())?),
+                        c => value.push(c),
+                    }
+                }
+                tokens.push(Token::Value(value));
+            }
+            c => {
+                let mut word = String::from(c);
+                // Value filters like `emails[type eq "work"]` are kept in one word and only
+                // their attribute is compared.
+                let mut brackets = usize::from(c == '[');
+                while let Some(&c) = chars.peek() {
+                    if brackets == 0 && (c.is_whitespace() || c == '(' || c == ')') {
+                        break;
+                    }
+                    match c {
+                        '[' => brackets += 1,
+                        ']' => brackets = brackets.checked_sub(1).ok_or(())?,
+                        _ => {}
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+    depth: usize,
+    comparisons: usize,
+}
+
+impl Parser {
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.tokens.get(self.next), Some(Token::Word(w)) if w.eq_ignore_ascii_case(word))
+    }
+
+    fn take(&mut self) -> Result<Token, ()> {
+        let token = self.tokens.get(self.next).cloned().ok_or(())?;
+        self.next += 1;
+        Ok(token)
+    }
+
+    /// Runs `parse` one nesting level deeper, failing beyond `MAX_NESTING`.
+    fn nested(&mut self, parse: fn(&mut Parser) -> Result<Filter, ()>) -> Result<Filter, ()> {
+        if self.depth == MAX_NESTING {
+            return Err(());
+        }
+        self.depth += 1;
+        let filter = parse(self);
+        self.depth -= 1;
+        filter
+    }
+
+    fn or(&mut self) -> Result<Filter, ()> {
+        let mut filter = self.and()?;
+        while self.peek_word("or") {
+            self.next += 1;
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter, ()> {
+        let mut filter = self.unary()?;
+        while self.peek_word("and") {
+            self.next += 1;
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Filter, ()> {
+        if self.peek_word("not") {
+            self.next += 1;
+            return Ok(Filter::Not(Box::new(self.nested(Parser::unary)?)));
+        }
+        match self.take()? {
+            Token::Open => {
+                let filter = self.nested(Parser::or)?;
+                match self.take()? {
+                    Token::Close => Ok(filter),
+                    _ => Err(()),
+                }
+            }
+            Token::Word(attribute) => self.comparison(attribute),
+            _ => Err(()),
+        }
+    }
+
+    fn comparison(&mut self, attribute: String) -> Result<Filter, ()> {
+        self.comparisons += 1;
+        if self.comparisons > MAX_COMPARISONS {
+            return Err(());
+        }
+        let operator = match self.take()? {
+            Token::Word(operator) => match operator.to_lowercase().as_str() {
+                "eq" => Operator::Eq,
+                "ne" => Operator::Ne,
+                "co" => Operator::Co,
+                "sw" => Operator::Sw,
+                "ew" => Operator::Ew,
+                "gt" => Operator::Gt,
+                "ge" => Operator::Ge,
+                "lt" => Operator::Lt,
+                "le" => Operator::Le,
+                "pr" => Operator::Pr,
+                _ => return Err(()),
+            },
+            _ => return Err(()),
+        };
+        let value = match operator {
+            Operator::Pr => String::new(),
+            _ => match self.take()? {
+                Token::Value(value) => value,
+                // true, false, null and numbers are not quoted.
+                Token::Word(value) => value,
+                _ => return Err(()),
+            },
+        };
+        Ok(Filter::Compare {
+            attribute: attribute_path(&attribute),
+            operator,
+            value,
+        })
+    }
+}
+
+/// Normalizes an attribute path: lowercase, without the schema URN of core attributes and
+/// without value filters, so `emails[type eq "work"].value` becomes `emails.value`.
+pub(crate) fn attribute_path(attribute: &str) -> String {
+    let attribute = attribute.to_lowercase();
+    let attribute = attribute
+        .strip_prefix("urn:ietf:params:scim:schemas:core:2.0:user:")
+        .or_else(|| attribute.strip_prefix("urn:ietf:params:scim:schemas:core:2.0:group:"))
+        .unwrap_or(&attribute);
+    let mut path = String::new();
+    let mut brackets = 0;
+    for c in attribute.chars() {
+        match c {
+            '[' => brackets += 1,
+            ']' => brackets -= 1,
+            c if brackets == 0 => path.push(c),
+            _ => {}
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(attribute: &str, operator: Operator, value: &str) -> Filter {
+        Filter::Compare {
+            attribute: attribute.to_owned(),
+            operator,
+            value: value.to_owned(),
+        }
+    }
+
+    #[test]
+    fn parses_precedence_and_grouping() {
+        assert_eq!(
+            Filter::parse(r#"userName eq "a" or userName eq "b" and active eq true"#),
+            Ok(Filter::Or(
+                Box::new(compare("username", Operator::Eq, "a")),
+                Box::new(Filter::And(
+                    Box::new(compare("username", Operator::Eq, "b")),
+                    Box::new(compare("active", Operator::Eq, "true")),
+                )),
+            ))
+        );
+        assert_eq!(
+            Filter::parse(r#"not (emails[type eq "work"].value pr)"#),
+            Ok(Filter::Not(Box::new(compare(
+                "emails.value",
+                Operator::Pr,
+                ""
+            ))))
+        );
+    }
+
+    #[test]
+    fn parses_escapes_and_schema_urns() {
+        assert_eq!(
+            Filter::parse(r#"urn:ietf:params:scim:schemas:core:2.0:User:userName EQ "a\"b""#),
+            Ok(compare("username", Operator::Eq, "a\"b"))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_filters() {
+        for filter in [
+            "",
+            "userName",
+            r#"userName eq"#,
+            r#"userName is "a""#,
+            r#"(userName eq "a""#,
+            r#"userName eq "a")"#,
+            r#"userName eq "a"#,
+            r#"userName eq "a" and"#,
+        ] {
+            assert_eq!(Filter::parse(filter), Err(()), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested =
+            |depth: usize| format!("{}userName pr{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Filter::parse(&nested(MAX_NESTING)).is_ok());
+        assert_eq!(Filter::parse(&nested(MAX_NESTING + 1)), Err(()));
+        assert_eq!(Filter::parse(&nested(100_000)), Err(()));
+        assert_eq!(Filter::parse(&"not ".repeat(100_000)), Err(()));
+    }
+
+    #[test]
+    fn limits_comparisons() {
+        let chain = |comparisons: usize| vec!["userName pr"; comparisons].join(" or ");
+        assert!(Filter::parse(&chain(MAX_COMPARISONS)).is_ok());
+        assert_eq!(Filter::parse(&chain(MAX_COMPARISONS + 1)), Err(()));
+    }
+
+    #[test]
+    fn matches_ignoring_case() {
+        let values = |attribute: &str| match attribute {
+            "username" => vec!["JDoe".to_owned()],
+            "emails.value" => vec!["jdoe@example.com".to_owned(), "j@example.org".to_owned()],
+            _ => Vec::new(),
+        };
+        let matches = |filter: &str| Filter::parse(filter).unwrap().matches(&values);
+        assert!(matches(r#"userName eq "jdoe""#));
+        assert!(matches(r#"emails.value ew ".org""#));
+        assert!(matches(r#"userName ne "other" and not (title pr)"#));
+        assert!(!matches(r#"emails.value ne "j@example.org""#));
+        assert!(!matches(r#"userName sw "x" or title pr"#));
+    }
+
+    #[test]
+    fn equality_value_only_for_plain_equality() {
+        let filter = Filter::parse(r#"userName eq "jdoe""#).unwrap();
+        assert_eq!(filter.equality_value("username"), Some("jdoe"));
+        assert_eq!(filter.equality_value("externalid"), None);
+        let filter = Filter::parse(r#"userName eq "jdoe" or userName eq "x""#).unwrap();
+        assert_eq!(filter.equality_value("username"), None);
+    }
+}
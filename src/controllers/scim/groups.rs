@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_db_pools::Connection;
+use serde_json::{json, Value};
+
+use crate::audit::{AuditContext, AuditEventType};
+use crate::config::AppConfig;
+use crate::controllers::admin::groups::record_members_changed;
+use crate::controllers::api::ApiPrincipal;
+use crate::controllers::scim::{
+    string_value, ListQuery, ListResponse, Meta, PatchOp, PatchRequest, Scim, SCHEMA_GROUP,
+};
+use crate::db::{DBGroup, DB};
+use crate::directory::{Directory, LdapDirectory, User};
+use crate::error::Error;
+use crate::service_accounts::ApiScope;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ScimGroup {
+    schemas: [&'static str; 1],
+    id: String,
+    display_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    members: Option<Vec<ScimMember>>,
+    meta: Meta,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ScimMember {
+    /// The username, which is the id of SCIM users.
+    value: String,
+    #[serde(default, skip_deserializing)]
+    display: String,
+}
+
+fn scim_group(group_id: i32, group: DBGroup, members: Option<&[(String, User)]>) -> ScimGroup {
+    ScimGroup {
+        schemas: [SCHEMA_GROUP],
+        id: group_id.to_string(),
+        display_name: group.name,
+        members: members.map(|members| {
+            members
+                .iter()
+                .map(|(_, user)| ScimMember {
+                    value: user.username.clone(),
+                    display: user.name.clone(),
+                })
+                .collect()
+        }),
+        meta: Meta {
+            resource_type: "Group",
+            location: format!("/scim/v2/Groups/{}", group_id),
+        },
+    }
+}
+
+/// Users by lowercase DN, which the member DNs of LDAP groups are looked up in.
+async fn users_by_dn(directory: &LdapDirectory<'_>) -> Result<HashMap<String, User>, Error> {
+    Ok(directory
+        .list_users()
+        .await?
+        .into_iter()
+        .map(|user| (user.dn.to_lowercase(), user))
+        .collect())
+}
+
+/// The members of an LDAP group that are users, with the DN as listed in the group. Other
+/// members, like the root DN groups are created with, are left out.
+fn member_users(members: &[String], users: &HashMap<String, User>) -> Vec<(String, User)> {
+    members
+        .iter()
+        .filter_map(|dn| Some((dn.clone(), users.get(&dn.to_lowercase())?.clone())))
+        .collect()
+}
+
+/// Members of the LDAP group `ldap_dn`; a group missing in LDAP has none.
+async fn group_members(
+    ldap_dn: &str,
+    directory: &LdapDirectory<'_>,
+    users: &HashMap<String, User>,
+) -> Result<Vec<(String, User)>, Error> {
+    match directory.get_group(ldap_dn).await {
+        Ok(group) => Ok(member_users(&group.members, users)),
+        Err(Error::Http(status)) if status == Status::NotFound => Ok(Vec::new()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Values of the attribute paths groups can be filtered by.
+fn group_attribute(
+    group_id: i32,
+    group: &DBGroup,
+    members: &[(String, User)],
+    path: &str,
+) -> Vec<String> {
+    match path {
+        "id" => vec![group_id.to_string()],
+        "displayname" => vec![group.name.clone()],
+        "members" | "members.value" => members
+            .iter()
+            .map(|(_, user)| user.username.clone())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[get("/Groups?<query..>")]
+pub(crate) async fn list_groups(
+    principal: ApiPrincipal,
+    query: ListQuery,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Scim<ListResponse<ScimGroup>>, Error> {
+    principal
+        .require(ApiScope::GroupsRead, &directory, app_config, &mut *db)
+        .await?;
+    let filter = query.filter()?;
+    let with_members = !query.excludes("members");
+    // Members are only looked up when they are returned or filtered by.
+    let (users, ldap_groups): (HashMap<String, User>, HashMap<String, Vec<String>>) =
+        match with_members || filter.is_some() {
+            true => {
+                let users = users_by_dn(&directory).await?;
+                let ldap_groups = directory
+                    .list_groups()
+                    .await?
+                    .into_iter()
+                    .map(|group| (group.dn.to_lowercase(), group.members))
+                    .collect();
+                (users, ldap_groups)
+            }
+            false => (HashMap::new(), HashMap::new()),
+        };
+    let mut groups = Vec::new();
+    for group in DBGroup::list_all(&mut *db).await? {
+        let group_id = match group.id {
+            Some(group_id) => group_id,
+            None => continue,
+        };
+        let members = ldap_groups
+            .get(&group.ldap_dn.to_lowercase())
+            .map(|members| member_users(members, &users))
+            .unwrap_or_default();
+        let matching = filter.as_ref().map_or(true, |filter| {
+            filter.matches(&|path| group_attribute(group_id, &group, &members, path))
+        });
+        if matching {
+            groups.push(scim_group(
+                group_id,
+                group,
+                Some(members.as_slice()).filter(|_| with_members),
+            ));
+        }
+    }
+    Ok(Scim(ListResponse::of(groups, &query)))
+}
+
+#[get("/Groups/<group_id>")]
+pub(crate) async fn get_group(
+    principal: ApiPrincipal,
+    group_id: i32,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Scim<ScimGroup>, Error> {
+    principal
+        .require(
+            ApiScope::GroupMembers(group_id),
+            &directory,
+            app_config,
+            &mut *db,
+        )
+        .await?;
+    let group = DBGroup::find_by_id(group_id, &mut *db).await?;
+    let users = users_by_dn(&directory).await?;
+    let members = group_members(&group.ldap_dn, &directory, &users).await?;
+    Ok(Scim(scim_group(group_id, group, Some(&members))))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ScimGroupBody {
+    display_name: String,
+    #[serde(default)]
+    members: Vec<ScimMember>,
+}
+
+/// Looks up the DNs of the users with the given usernames, failing with 400 for unknown users.
+fn member_dns(usernames: &[String], users: &HashMap<String, User>) -> Result<Vec<String>, Error> {
+    usernames
+        .iter()
+        .map(|username| {
+            users
+                .values()
+                .find(|user| user.username.eq_ignore_ascii_case(username))
+                .map(|user| user.dn.clone())
+                .ok_or(Error::Http(Status::BadRequest))
+        })
+        .collect()
+}
+
+/// Creates a legitima group together with a new LDAP group named after it.
+#[post("/Groups", data = "<body>")]
+pub(crate) async fn create_group(
+    principal: ApiPrincipal,
+    body: Json<ScimGroupBody>,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Created<Scim<ScimGroup>>, Error> {
+    principal
+        .require(ApiScope::GroupsWrite, &directory, app_config, &mut *db)
+        .await?;
+    let body = body.into_inner();
+    if body.display_name.is_empty() {
+        return Err(Error::Http(Status::BadRequest));
+    }
+    let users = users_by_dn(&directory).await?;
+    let usernames: Vec<String> = body.members.into_iter().map(|m| m.value).collect();
+    let added = member_dns(&usernames, &users)?;
+    // groupOfNames requires at least one member, so the group always contains the root DN.
+    let mut members = vec![app_config.ldap_root_dn.clone()];
+    members.extend(added.iter().cloned());
+    let ldap_group = directory.create_group(&body.display_name, members).await?;
+    let group = DBGroup {
+        id: None,
+        name: body.display_name,
+        ldap_dn: ldap_group.dn,
+        enforce_2fa: None,
+    };
+    let group_id = DBGroup::create_one(group.clone(), &mut *db).await?;
+    let audit = audit.with_actor(principal.actor());
+    audit
+        .record(
+            AuditEventType::GroupCreated,
+            &group.ldap_dn,
+            json!({
+                "group_id": group_id,
+                "name": group.name,
+                "ldap_group_created": true,
+            }),
+            &mut *db,
+        )
+        .await?;
+    record_members_changed(&audit, group_id, &group.ldap_dn, &added, &[], &mut db).await?;
+    let members = group_members(&group.ldap_dn, &directory, &users).await?;
+    Ok(
+        Created::new(format!("/scim/v2/Groups/{}", group_id)).body(Scim(scim_group(
+            group_id,
+            group,
+            Some(&members),
+        ))),
+    )
+}
+
+/// Changes to apply to a group: a new name and the usernames of all members.
+struct GroupChanges {
+    name: Option<String>,
+    usernames: Vec<String>,
+}
+
+/// Renames the group and updates its members, recording what actually changed. Renaming
+/// requires `groups:write`, while the members can also be changed with the scope of the group.
+#[allow(clippy::too_many_arguments)]
+async fn save_group_changes(
+    principal: &ApiPrincipal,
+    group_id: i32,
+    group: DBGroup,
+    current: &[(String, User)],
+    changes: GroupChanges,
+    users: &HashMap<String, User>,
+    app_config: &AppConfig,
+    directory: &LdapDirectory<'_>,
+    audit: &AuditContext,
+    db: &mut Connection<DB>,
+) -> Result<(), Error> {
+    if let Some(name) = changes.name.filter(|name| *name != group.name) {
+        principal
+            .require(ApiScope::GroupsWrite, directory, app_config, &mut *db)
+            .await?;
+        if name.is_empty() {
+            return Err(Error::Http(Status::BadRequest));
+        }
+        DBGroup::update_one(
+            DBGroup {
+                id: Some(group_id),
+                name: name.clone(),
+                ..group.clone()
+            },
+            &mut *db,
+        )
+        .await?;
+        audit
+            .record(
+                AuditEventType::GroupUpdated,
+                &group.ldap_dn,
+                json!({
+                    "group_id": group_id,
+                    "name": name,
+                    "previous_name": group.name,
+                    "previous_ldap_dn": group.ldap_dn,
+                }),
+                &mut *db,
+            )
+            .await?;
+    }
+    let wanted = member_dns(&changes.usernames, users)?;
+    let added: Vec<String> = wanted
+        .iter()
+        .filter(|dn| {
+            !current
+                .iter()
+                .any(|(member, _)| member.eq_ignore_ascii_case(dn))
+        })
+        .cloned()
+        .collect();
+    let removed: Vec<String> = current
+        .iter()
+        .map(|(member, _)| member.clone())
+        .filter(|member| !wanted.iter().any(|dn| dn.eq_ignore_ascii_case(member)))
+        .collect();
+    if !added.is_empty() || !removed.is_empty() {
+        directory
+            .update_group_members(&group.ldap_dn, added.clone(), removed.clone())
+            .await?;
+    }
+    record_members_changed(audit, group_id, &group.ldap_dn, &added, &removed, db).await
+}
+
+/// Replaces the name and the members of a group.
+#[put("/Groups/<group_id>", data = "<body>")]
+pub(crate) async fn replace_group(
+    principal: ApiPrincipal,
+    group_id: i32,
+    body: Json<ScimGroupBody>,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Scim<ScimGroup>, Error> {
+    principal
+        .require(
+            ApiScope::GroupMembers(group_id),
+            &directory,
+            app_config,
+            &mut *db,
+        )
+        .await?;
+    let body = body.into_inner();
+    let group = DBGroup::find_by_id(group_id, &mut *db).await?;
+    let users = users_by_dn(&directory).await?;
+    let current = group_members(&group.ldap_dn, &directory, &users).await?;
+    let changes = GroupChanges {
+        name: Some(body.display_name),
+        usernames: body.members.into_iter().map(|m| m.value).collect(),
+    };
+    save_group_changes(
+        &principal,
+        group_id,
+        group,
+        &current,
+        changes,
+        &users,
+        app_config,
+        &directory,
+        &audit.with_actor(principal.actor()),
+        &mut db,
+    )
+    .await?;
+    let group = DBGroup::find_by_id(group_id, &mut *db).await?;
+    let members = group_members(&group.ldap_dn, &directory, &users).await?;
+    Ok(Scim(scim_group(group_id, group, Some(&members))))
+}
+
+/// Reads the usernames of a `members` value, a list of `{"value": "<username>"}` objects.
+fn member_usernames(value: &Value) -> Result<Vec<String>, Error> {
+    let members: Vec<ScimMember> =
+        match value {
+            Value::Null => Vec::new(),
+            Value::Array(_) => serde_json::from_value(value.clone())
+                .map_err(|_| Error::Http(Status::BadRequest))?,
+            _ => vec![serde_json::from_value(value.clone())
+                .map_err(|_| Error::Http(Status::BadRequest))?],
+        };
+    Ok(members.into_iter().map(|m| m.value).collect())
+}
+
+/// Supports adding, removing and replacing members, including the `members[value eq "..."]`
+/// paths clients remove single members with, and renaming the group.
+#[patch("/Groups/<group_id>", data = "<body>")]
+pub(crate) async fn patch_group(
+    principal: ApiPrincipal,
+    group_id: i32,
+    body: Json<PatchRequest>,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Scim<ScimGroup>, Error> {
+    principal
+        .require(
+            ApiScope::GroupMembers(group_id),
+            &directory,
+            app_config,
+            &mut *db,
+        )
+        .await?;
+    let group = DBGroup::find_by_id(group_id, &mut *db).await?;
+    let users = users_by_dn(&directory).await?;
+    let current = group_members(&group.ldap_dn, &directory, &users).await?;
+    let mut changes = GroupChanges {
+        name: None,
+        usernames: current
+            .iter()
+            .map(|(_, user)| user.username.clone())
+            .collect(),
+    };
+    for operation in &body.operations {
+        let op = operation.op()?;
+        if let Some(value_filter) = operation.value_filter()? {
+            // Only removing the members matching the filter is meaningful.
+            if op != PatchOp::Remove {
+                return Err(Error::Http(Status::BadRequest));
+            }
+            changes.usernames.retain(|username| {
+                !value_filter.matches(&|path| match path {
+                    "value" => vec![username.clone()],
+                    _ => Vec::new(),
+                })
+            });
+            continue;
+        }
+        if op == PatchOp::Remove {
+            match operation.attributes()?.as_slice() {
+                [(path, value)] if path == "members" => {
+                    let removed = member_usernames(value)?;
+                    match removed.is_empty() {
+                        true => changes.usernames.clear(),
+                        false => changes.usernames.retain(|username| {
+                            !removed.iter().any(|r| r.eq_ignore_ascii_case(username))
+                        }),
+                    }
+                }
+                _ => return Err(Error::Http(Status::BadRequest)),
+            }
+            continue;
+        }
+        for (path, value) in operation.attributes()? {
+            match path.as_str() {
+                "displayname" => changes.name = Some(string_value(&value)?),
+                "members" => {
+                    let usernames = member_usernames(&value)?;
+                    if op == PatchOp::Replace {
+                        changes.usernames.clear();
+                    }
+                    for username in usernames {
+                        if !changes
+                            .usernames
+                            .iter()
+                            .any(|u| u.eq_ignore_ascii_case(&username))
+                        {
+                            changes.usernames.push(username);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    save_group_changes(
+        &principal,
+        group_id,
+        group,
+        &current,
+        changes,
+        &users,
+        app_config,
+        &directory,
+        &audit.with_actor(principal.actor()),
+        &mut db,
+    )
+    .await?;
+    let group = DBGroup::find_by_id(group_id, &mut *db).await?;
+    let members = group_members(&group.ldap_dn, &directory, &users).await?;
+    Ok(Scim(scim_group(group_id, group, Some(&members))))
+}
+
+/// Deletes a legitima group and its LDAP group, refusing with 409 while it still grants access
+/// to clients.
+#[delete("/Groups/<group_id>")]
+pub(crate) async fn delete_group(
+    principal: ApiPrincipal,
+    group_id: i32,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Status, Error> {
+    principal
+        .require(ApiScope::GroupsWrite, &directory, app_config, &mut *db)
+        .await?;
+    if DBGroup::count_permissions(group_id, &mut *db).await? > 0 {
+        return Err(Error::Http(Status::Conflict));
+    }
+    let group = DBGroup::find_by_id(group_id, &mut *db).await?;
+    match directory.delete_group(&group.ldap_dn).await {
+        Ok(()) => {}
+        Err(Error::Http(status)) if status == Status::NotFound => {}
+        Err(error) => return Err(error),
+    }
+    DBGroup::delete_one(group_id, &mut *db).await?;
+    audit
+        .with_actor(principal.actor())
+        .record(
+            AuditEventType::GroupDeleted,
+            &group.ldap_dn,
+            json!({
+                "group_id": group_id,
+                "name": group.name,
+                "ldap_group_deleted": true,
+            }),
+            &mut *db,
+        )
+        .await?;
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,227 @@
+use rocket::http::{ContentType, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::Request;
+use serde_json::{json, Value};
+
+use crate::error::Error;
+use filter::Filter;
+
+pub(crate) mod filter;
+pub(crate) mod groups;
+pub(crate) mod users;
+
+const SCHEMA_USER: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+const SCHEMA_GROUP: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+const SCHEMA_LIST_RESPONSE: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+const SCHEMA_ERROR: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+
+/// Resources per page unless the client asks for a different `count`.
+const DEFAULT_COUNT: usize = 100;
+const MAX_COUNT: usize = 1000;
+
+/// Serializes a resource as `application/scim+json`.
+pub(crate) struct Scim<T>(pub(crate) T);
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Scim<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let body = serde_json::to_string(&self.0).map_err(|_| Status::InternalServerError)?;
+        Response::build_from(body.respond_to(request)?)
+            .header(ContentType::new("application", "scim+json"))
+            .ok()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Meta {
+    resource_type: &'static str,
+    location: String,
+}
+
+/// Query of list requests. `startIndex` counts from 1.
+#[derive(FromForm)]
+pub(crate) struct ListQuery {
+    filter: Option<String>,
+    #[field(name = "startIndex")]
+    start_index: Option<usize>,
+    count: Option<usize>,
+    /// Only `members` can be excluded, which spares looking up the members of every group.
+    #[field(name = "excludedAttributes")]
+    excluded_attributes: Option<String>,
+}
+
+impl ListQuery {
+    fn filter(&self) -> Result<Option<Filter>, Error> {
+        self.filter
+            .as_deref()
+            .map(Filter::parse)
+            .transpose()
+            .map_err(|()| Error::Http(Status::BadRequest))
+    }
+
+    fn excludes(&self, attribute: &str) -> bool {
+        self.excluded_attributes
+            .as_deref()
+            .map_or(false, |excluded| {
+                excluded
+                    .split(',')
+                    .any(|excluded| excluded.trim().eq_ignore_ascii_case(attribute))
+            })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ListResponse<T> {
+    schemas: [&'static str; 1],
+    total_results: usize,
+    start_index: usize,
+    items_per_page: usize,
+    #[serde(rename = "Resources")]
+    resources: Vec<T>,
+}
+
+impl<T> ListResponse<T> {
+    /// Cuts the requested page out of all matching resources. Unless the filter looks up a
+    /// single resource by name, the callers list every user or group from LDAP and filter them
+    /// in memory on each request, so paging through a large directory costs one full listing
+    /// per page, softened only by the directory cache.
+    fn of(resources: Vec<T>, query: &ListQuery) -> Self {
+        let start_index = query.start_index.unwrap_or(1).max(1);
+        let count = query.count.unwrap_or(DEFAULT_COUNT).min(MAX_COUNT);
+        let total_results = resources.len();
+        let resources: Vec<T> = resources
+            .into_iter()
+            .skip(start_index - 1)
+            .take(count)
+            .collect();
+        ListResponse {
+            schemas: [SCHEMA_LIST_RESPONSE],
+            total_results,
+            start_index,
+            items_per_page: resources.len(),
+            resources,
+        }
+    }
+}
+
+/// Body of PATCH requests. Operation names are matched ignoring case, as some clients send
+/// `Replace` instead of `replace`.
+#[derive(Deserialize)]
+pub(crate) struct PatchRequest {
+    #[serde(rename = "Operations")]
+    operations: Vec<PatchOperation>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PatchOperation {
+    op: String,
+    path: Option<String>,
+    #[serde(default)]
+    value: Value,
+}
+
+#[derive(PartialEq, Eq)]
+enum PatchOp {
+    Add,
+    Remove,
+    Replace,
+}
+
+impl PatchOperation {
+    fn op(&self) -> Result<PatchOp, Error> {
+        match self.op.to_lowercase().as_str() {
+            "add" => Ok(PatchOp::Add),
+            "remove" => Ok(PatchOp::Remove),
+            "replace" => Ok(PatchOp::Replace),
+            _ => Err(Error::Http(Status::BadRequest)),
+        }
+    }
+
+    /// The attributes an operation sets: the value under the path or, without a path, every
+    /// attribute of the value object. Paths are normalized with [`filter::attribute_path`].
+    fn attributes(&self) -> Result<Vec<(String, Value)>, Error> {
+        match (&self.path, &self.value) {
+            (Some(path), value) => Ok(vec![(filter::attribute_path(path), value.clone())]),
+            (None, Value::Object(attributes)) => Ok(attributes
+                .iter()
+                .map(|(path, value)| (filter::attribute_path(path), value.clone()))
+                .collect()),
+            (None, _) => Err(Error::Http(Status::BadRequest)),
+        }
+    }
+
+    /// The value filter of a path like `members[value eq "alice"]`, which selects the values
+    /// of a multi-valued attribute an operation applies to.
+    fn value_filter(&self) -> Result<Option<Filter>, Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        match (path.find('['), path.rfind(']')) {
+            (Some(start), Some(end)) if start < end => Filter::parse(&path[start + 1..end])
+                .map(Some)
+                .map_err(|()| Error::Http(Status::BadRequest)),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Reads a string attribute of a PATCH or PUT body.
+fn string_value(value: &Value) -> Result<String, Error> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Null => Ok(String::new()),
+        _ => Err(Error::Http(Status::BadRequest)),
+    }
+}
+
+/// Reads a boolean attribute, accepting the strings `"True"` and `"False"` some clients send.
+fn bool_value(value: &Value) -> Result<bool, Error> {
+    match value {
+        Value::Bool(value) => Ok(*value),
+        Value::String(value) if value.eq_ignore_ascii_case("true") => Ok(true),
+        Value::String(value) if value.eq_ignore_ascii_case("false") => Ok(false),
+        _ => Err(Error::Http(Status::BadRequest)),
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct ScimError {
+    schemas: [&'static str; 1],
+    status: String,
+    detail: &'static str,
+}
+
+/// Answers every error below `/scim` with a SCIM error message.
+#[catch(default)]
+pub(crate) fn scim_error(status: Status, _request: &Request) -> (Status, Scim<ScimError>) {
+    (
+        status,
+        Scim(ScimError {
+            schemas: [SCHEMA_ERROR],
+            status: status.code.to_string(),
+            detail: status.reason().unwrap_or("Unknown Error"),
+        }),
+    )
+}
+
+/// What this implementation supports, so clients do not try bulk requests or sorting.
+#[get("/ServiceProviderConfig")]
+pub(crate) fn service_provider_config() -> Scim<Value> {
+    Scim(json!({
+        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig"],
+        "patch": { "supported": true },
+        "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+        "filter": { "supported": true, "maxResults": MAX_COUNT },
+        "changePassword": { "supported": false },
+        "sort": { "supported": false },
+        "etag": { "supported": false },
+        "authenticationSchemes": [{
+            "type": "oauthbearertoken",
+            "name": "Bearer token",
+            "description": "A key of a service account or an access token issued by Hydra",
+        }],
+    }))
+}
@@ -0,0 +1,479 @@
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_db_pools::Connection;
+use serde_json::{json, Value};
+
+use crate::audit::{AuditContext, AuditEventType};
+use crate::config::AppConfig;
+use crate::controllers::api::ApiPrincipal;
+use crate::controllers::scim::{
+    bool_value, string_value, ListQuery, ListResponse, Meta, PatchOp, PatchRequest, Scim,
+    SCHEMA_USER,
+};
+use crate::db::{DBGroup, DB};
+use crate::directory::{Directory, LdapDirectory, NewUser, User};
+use crate::error::Error;
+use crate::notifications::{Notification, Notifier};
+use crate::service_accounts::ApiScope;
+use crate::sessions::SessionRevoker;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ScimUser {
+    schemas: [&'static str; 1],
+    id: String,
+    user_name: String,
+    name: ScimName,
+    display_name: String,
+    emails: Vec<ScimEmail>,
+    active: bool,
+    /// The legitima groups of the user, only returned for single users.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<ScimGroupRef>>,
+    meta: Meta,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct ScimName {
+    formatted: Option<String>,
+    given_name: Option<String>,
+    family_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ScimEmail {
+    value: String,
+    #[serde(default)]
+    primary: bool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ScimGroupRef {
+    value: String,
+    display: String,
+}
+
+/// The username is the id, so users keep their id as long as they are not renamed in LDAP.
+fn scim_user(user: User, groups: Option<Vec<ScimGroupRef>>) -> ScimUser {
+    ScimUser {
+        schemas: [SCHEMA_USER],
+        meta: Meta {
+            resource_type: "User",
+            location: format!("/scim/v2/Users/{}", user.username),
+        },
+        id: user.username.clone(),
+        user_name: user.username,
+        name: ScimName {
+            formatted: Some(user.name.clone()),
+            given_name: Some(user.first_name),
+            family_name: Some(user.last_name),
+        },
+        display_name: user.name,
+        emails: match user.email.is_empty() {
+            true => Vec::new(),
+            false => vec![ScimEmail {
+                value: user.email,
+                primary: true,
+            }],
+        },
+        active: !user.disabled,
+        groups,
+    }
+}
+
+/// Values of the attribute paths users can be filtered by.
+fn user_attribute(user: &User, path: &str) -> Vec<String> {
+    match path {
+        "id" | "username" => vec![user.username.clone()],
+        "displayname" | "name.formatted" => vec![user.name.clone()],
+        "name.givenname" => vec![user.first_name.clone()],
+        "name.familyname" => vec![user.last_name.clone()],
+        "emails" | "emails.value" if !user.email.is_empty() => vec![user.email.clone()],
+        "active" => vec![(!user.disabled).to_string()],
+        _ => Vec::new(),
+    }
+}
+
+#[get("/Users?<query..>")]
+pub(crate) async fn list_users(
+    principal: ApiPrincipal,
+    query: ListQuery,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Scim<ListResponse<ScimUser>>, Error> {
+    principal
+        .require(ApiScope::UsersRead, &directory, app_config, &mut *db)
+        .await?;
+    let filter = query.filter()?;
+    // Clients check whether a user exists by userName before creating it, which is answered
+    // without listing every user.
+    let users = match filter.as_ref().and_then(|f| f.equality_value("username")) {
+        Some(username) => match directory.get_user(username).await {
+            Ok(user) => vec![user],
+            Err(Error::Http(status)) if status == Status::NotFound => Vec::new(),
+            Err(error) => return Err(error),
+        },
+        None => directory.list_users().await?,
+    };
+    let users = users
+        .into_iter()
+        .filter(|user| {
+            filter.as_ref().map_or(true, |filter| {
+                filter.matches(&|path| user_attribute(user, path))
+            })
+        })
+        .map(|user| scim_user(user, None))
+        .collect();
+    Ok(Scim(ListResponse::of(users, &query)))
+}
+
+#[get("/Users/<id>")]
+pub(crate) async fn get_user(
+    principal: ApiPrincipal,
+    id: &str,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Scim<ScimUser>, Error> {
+    principal
+        .require(ApiScope::UsersRead, &directory, app_config, &mut *db)
+        .await?;
+    let user = directory.get_user(id).await?;
+    let user_groups = directory.get_user_groups(&user.username).await?;
+    let groups = DBGroup::list_all(&mut *db)
+        .await?
+        .into_iter()
+        .filter(|group| user_groups.contains(&group.ldap_dn))
+        .filter_map(|group| {
+            Some(ScimGroupRef {
+                value: group.id?.to_string(),
+                display: group.name,
+            })
+        })
+        .collect();
+    Ok(Scim(scim_user(user, Some(groups))))
+}
+
+/// The attributes of a user that can be provisioned. Passwords are not provisioned, users
+/// set their own.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ScimUserBody {
+    user_name: String,
+    #[serde(default)]
+    name: ScimName,
+    display_name: Option<String>,
+    #[serde(default)]
+    emails: Vec<ScimEmail>,
+    active: Option<bool>,
+}
+
+/// Changes to apply to a user; `None` leaves an attribute as it is.
+#[derive(Default)]
+struct UserChanges {
+    name: Option<String>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    email: Option<String>,
+    active: Option<bool>,
+}
+
+impl UserChanges {
+    fn from_body(body: ScimUserBody) -> UserChanges {
+        UserChanges {
+            name: body.display_name.or(body.name.formatted),
+            first_name: body.name.given_name,
+            last_name: body.name.family_name,
+            email: primary_email(body.emails),
+            active: body.active,
+        }
+    }
+
+    /// Applies one attribute of a PATCH operation. Attributes legitima does not store, like
+    /// `externalId` or those of schema extensions, are ignored.
+    fn set(&mut self, path: &str, value: &Value) -> Result<(), Error> {
+        match path {
+            "active" => self.active = Some(bool_value(value)?),
+            "displayname" | "name.formatted" => self.name = Some(string_value(value)?),
+            "name.givenname" => self.first_name = Some(string_value(value)?),
+            "name.familyname" => self.last_name = Some(string_value(value)?),
+            "name" => {
+                let name: ScimName = serde_json::from_value(value.clone())
+                    .map_err(|_| Error::Http(Status::BadRequest))?;
+                self.name = name.formatted.or(self.name.take());
+                self.first_name = name.given_name.or(self.first_name.take());
+                self.last_name = name.family_name.or(self.last_name.take());
+            }
+            "emails" => {
+                let emails: Vec<ScimEmail> = serde_json::from_value(value.clone())
+                    .map_err(|_| Error::Http(Status::BadRequest))?;
+                self.email = primary_email(emails);
+            }
+            "emails.value" => self.email = Some(string_value(value)?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn primary_email(emails: Vec<ScimEmail>) -> Option<String> {
+    let mut emails = emails;
+    let index = emails.iter().position(|email| email.primary).unwrap_or(0);
+    match emails.is_empty() {
+        true => None,
+        false => Some(emails.swap_remove(index).value),
+    }
+}
+
+/// Writes `changes` to the directory and records every change that was actually made.
+/// Disabled users are cut off right away.
+async fn save_user_changes(
+    user: &User,
+    changes: UserChanges,
+    directory: &LdapDirectory<'_>,
+    audit: &AuditContext,
+    notifier: &Notifier<'_>,
+    revoker: &mut SessionRevoker<'_>,
+    db: &mut Connection<DB>,
+) -> Result<(), Error> {
+    let name = changes.name.unwrap_or_else(|| user.name.clone());
+    let first_name = changes
+        .first_name
+        .unwrap_or_else(|| user.first_name.clone());
+    let last_name = changes.last_name.unwrap_or_else(|| user.last_name.clone());
+    if name != user.name || first_name != user.first_name || last_name != user.last_name {
+        if name.is_empty() || first_name.is_empty() || last_name.is_empty() {
+            return Err(Error::Http(Status::BadRequest));
+        }
+        directory
+            .update_user_name(&user.username, &name, &first_name, &last_name)
+            .await?;
+        audit
+            .record(
+                AuditEventType::NameChanged,
+                &user.username,
+                json!({
+                    "display_name": name,
+                    "first_name": first_name,
+                    "last_name": last_name,
+                }),
+                &mut *db,
+            )
+            .await?;
+    }
+    if let Some(email) = changes.email.filter(|email| *email != user.email) {
+        if email.is_empty() {
+            return Err(Error::Http(Status::BadRequest));
+        }
+        directory.update_user_email(&user.username, &email).await?;
+        audit
+            .record(
+                AuditEventType::EmailChanged,
+                &user.username,
+                json!({ "previous_email": user.email, "email": email }),
+                &mut *db,
+            )
+            .await?;
+        notifier
+            .notify(
+                &user.username,
+                Notification::EmailChanged {
+                    previous_email: user.email.clone(),
+                    email,
+                },
+            )
+            .await;
+    }
+    if let Some(active) = changes.active.filter(|active| *active == user.disabled) {
+        directory.set_user_disabled(&user.username, !active).await?;
+        let event_type = match active {
+            true => AuditEventType::UserEnabled,
+            false => AuditEventType::UserDisabled,
+        };
+        audit
+            .record(event_type, &user.username, json!({}), &mut *db)
+            .await?;
+        if !active {
+            revoker
+                .cut_off(&user.username, event_type, audit, &mut *db)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+#[post("/Users", data = "<body>")]
+pub(crate) async fn create_user(
+    principal: ApiPrincipal,
+    body: Json<ScimUserBody>,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+) -> Result<Created<Scim<ScimUser>>, Error> {
+    principal
+        .require(ApiScope::UsersWrite, &directory, app_config, &mut *db)
+        .await?;
+    let body = body.into_inner();
+    if body.user_name.is_empty() {
+        return Err(Error::Http(Status::BadRequest));
+    }
+    let username = body.user_name.clone();
+    let changes = UserChanges::from_body(body);
+    let mut user = directory
+        .create_user(NewUser {
+            username: username.clone(),
+            name: changes.name.unwrap_or_default(),
+            first_name: changes.first_name.unwrap_or_default(),
+            last_name: changes.last_name.unwrap_or_default(),
+            email: changes.email.unwrap_or_default(),
+        })
+        .await?;
+    let audit = audit.with_actor(principal.actor());
+    audit
+        .record(
+            AuditEventType::UserCreated,
+            &username,
+            json!({ "name": user.name, "email": user.email }),
+            &mut *db,
+        )
+        .await?;
+    if changes.active == Some(user.disabled) {
+        directory
+            .set_user_disabled(&username, !user.disabled)
+            .await?;
+        audit
+            .record(
+                match user.disabled {
+                    true => AuditEventType::UserEnabled,
+                    false => AuditEventType::UserDisabled,
+                },
+                &username,
+                json!({}),
+                &mut *db,
+            )
+            .await?;
+        user = directory.get_user(&username).await?;
+    }
+    Ok(Created::new(format!("/scim/v2/Users/{}", username)).body(Scim(scim_user(user, None))))
+}
+
+/// Replaces the attributes of a user. The userName cannot be changed.
+#[put("/Users/<id>", data = "<body>")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn replace_user(
+    principal: ApiPrincipal,
+    id: &str,
+    body: Json<ScimUserBody>,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+    notifier: Notifier<'_>,
+    mut revoker: SessionRevoker<'_>,
+) -> Result<Scim<ScimUser>, Error> {
+    principal
+        .require(ApiScope::UsersWrite, &directory, app_config, &mut *db)
+        .await?;
+    let user = directory.get_user(id).await?;
+    let body = body.into_inner();
+    if body.user_name != user.username {
+        return Err(Error::Http(Status::BadRequest));
+    }
+    save_user_changes(
+        &user,
+        UserChanges::from_body(body),
+        &directory,
+        &audit.with_actor(principal.actor()),
+        &notifier,
+        &mut revoker,
+        &mut db,
+    )
+    .await?;
+    Ok(Scim(scim_user(directory.get_user(id).await?, None)))
+}
+
+/// Supports `add` and `replace` of the names, the e-mail address and `active`. Nothing can be
+/// removed, as every user needs a name and an e-mail address.
+#[patch("/Users/<id>", data = "<body>")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn patch_user(
+    principal: ApiPrincipal,
+    id: &str,
+    body: Json<PatchRequest>,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+    notifier: Notifier<'_>,
+    mut revoker: SessionRevoker<'_>,
+) -> Result<Scim<ScimUser>, Error> {
+    principal
+        .require(ApiScope::UsersWrite, &directory, app_config, &mut *db)
+        .await?;
+    let user = directory.get_user(id).await?;
+    let mut changes = UserChanges::default();
+    for operation in &body.operations {
+        if operation.op()? == PatchOp::Remove {
+            return Err(Error::Http(Status::BadRequest));
+        }
+        for (path, value) in operation.attributes()? {
+            // Clients send the unchanged userName along, which cannot be changed.
+            if path == "username" && string_value(&value)? != user.username {
+                return Err(Error::Http(Status::BadRequest));
+            }
+            changes.set(&path, &value)?;
+        }
+    }
+    save_user_changes(
+        &user,
+        changes,
+        &directory,
+        &audit.with_actor(principal.actor()),
+        &notifier,
+        &mut revoker,
+        &mut db,
+    )
+    .await?;
+    Ok(Scim(scim_user(directory.get_user(id).await?, None)))
+}
+
+#[delete("/Users/<id>")]
+pub(crate) async fn delete_user(
+    principal: ApiPrincipal,
+    id: &str,
+    app_config: &State<AppConfig>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+    mut revoker: SessionRevoker<'_>,
+) -> Result<Status, Error> {
+    principal
+        .require(ApiScope::UsersWrite, &directory, app_config, &mut *db)
+        .await?;
+    let user = directory.get_user(id).await?;
+    directory.delete_user(&user.username).await?;
+    let audit = audit.with_actor(principal.actor());
+    audit
+        .record(
+            AuditEventType::UserDeleted,
+            &user.username,
+            json!({}),
+            &mut *db,
+        )
+        .await?;
+    revoker
+        .cut_off(
+            &user.username,
+            AuditEventType::UserDeleted,
+            &audit,
+            &mut *db,
+        )
+        .await?;
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,31 @@
+use rocket::http::{Cookie, CookieJar};
+use rocket::response::Redirect;
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::Template;
+
+use crate::controllers::admin::audit::AuditEventPage;
+use crate::db::DB;
+use crate::error::Error;
+use crate::sessions::User;
+
+#[get("/activity", rank = 2)]
+pub(crate) async fn get_activity(cookies: &CookieJar<'_>) -> Redirect {
+    cookies.add(Cookie::new(
+        "redirect_url",
+        uri!("/selfservice", get_activity()).to_string(),
+    ));
+    Redirect::to(uri!("/auth", crate::controllers::auth::login::login()))
+}
+
+/// Events the user triggered or was affected by, such as logins and credential changes.
+#[get("/activity?<page>")]
+pub(crate) async fn auth_get_activity(
+    cookie_user: User,
+    page: Option<usize>,
+    mut db: Connection<DB>,
+) -> Result<Template, Error> {
+    let username = cookie_user.get_username();
+    let mut events = AuditEventPage::load(None, Some(&username[..]), page, &mut db).await?;
+    events.hide_foreign_origins(&username);
+    Ok(Template::render("selfservice/activity", events))
+}
@@ -0,0 +1,132 @@
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::Template;
+
+use crate::audit::AuditContext;
+use crate::config::AppConfig;
+use crate::controllers::admin::groups::{
+    record_members_changed, update_members, GroupDataMembers, MemberPicker,
+};
+use crate::db::{DBGroup, DB};
+use crate::directory::{Directory, LdapDirectory};
+use crate::error::Error;
+use crate::sessions::GroupOwner;
+
+#[derive(Serialize)]
+struct OwnedGroupsContext {
+    groups: Vec<OwnedGroup>,
+}
+
+#[derive(Serialize)]
+struct OwnedGroup {
+    id: i32,
+    name: String,
+    ldap_dn: String,
+    member_count: usize,
+}
+
+#[derive(Serialize)]
+struct EditOwnedGroupContext {
+    id: i32,
+    name: String,
+    ldap_dn: String,
+    #[serde(flatten)]
+    member_picker: MemberPicker,
+}
+
+#[get("/groups", rank = 2)]
+pub(crate) async fn list_groups() -> Status {
+    Status::Forbidden
+}
+
+#[get("/groups")]
+pub(crate) async fn auth_list_groups(
+    owner: GroupOwner,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Template, Error> {
+    let mut groups = Vec::new();
+    for group_id in owner.group_ids() {
+        let db_group = DBGroup::find_by_id(*group_id, &mut *db).await?;
+        let ldap_group = directory.get_group(&db_group.ldap_dn).await?;
+        groups.push(OwnedGroup {
+            id: *group_id,
+            name: db_group.name,
+            ldap_dn: db_group.ldap_dn,
+            member_count: ldap_group.members.len(),
+        });
+    }
+    groups.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Template::render(
+        "selfservice/groups",
+        OwnedGroupsContext { groups },
+    ))
+}
+
+#[get("/groups/<group_id>?<q>&<page>")]
+pub(crate) async fn auth_edit_group(
+    owner: GroupOwner,
+    group_id: i32,
+    q: Option<String>,
+    page: Option<usize>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+) -> Result<Template, Error> {
+    if !owner.owns(group_id) {
+        return Err(Error::Http(Status::Forbidden));
+    }
+    let db_group = DBGroup::find_by_id(group_id, &mut *db).await?;
+    let ldap_group = directory.get_group(&db_group.ldap_dn).await?;
+    let member_picker = MemberPicker::load(&directory, &ldap_group, q, page).await?;
+
+    Ok(Template::render(
+        "selfservice/groups_edit",
+        EditOwnedGroupContext {
+            id: group_id,
+            name: db_group.name,
+            ldap_dn: db_group.ldap_dn,
+            member_picker,
+        },
+    ))
+}
+
+#[post("/groups/<group_id>/members", data = "<form>")]
+pub(crate) async fn auth_edit_group_memberform(
+    owner: GroupOwner,
+    group_id: i32,
+    form: Form<GroupDataMembers>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+    app_config: &State<AppConfig>,
+) -> Result<Redirect, Error> {
+    if !owner.owns(group_id) {
+        return Err(Error::Http(Status::Forbidden));
+    }
+    let db_group_ldap_dn = DBGroup::find_ldap_dn_by_id(group_id, &mut *db).await?;
+    let (added, removed) = update_members(
+        &directory,
+        app_config.inner(),
+        &db_group_ldap_dn,
+        form.into_inner(),
+    )
+    .await?;
+    record_members_changed(
+        &audit,
+        group_id,
+        &db_group_ldap_dn,
+        &added,
+        &removed,
+        &mut db,
+    )
+    .await?;
+    Ok(Redirect::to(uri!(
+        "/selfservice",
+        auth_edit_group(group_id, _, _)
+    )))
+}
@@ -1,2 +1,4 @@
+pub(crate) mod activity;
+pub(crate) mod groups;
 pub(crate) mod personal_data;
 pub(crate) mod security;
@@ -1,18 +1,17 @@
-use std::collections::HashSet;
-
-use ldap3::Mod;
 use rocket::form::Form;
 use rocket::http::{Cookie, CookieJar, Status};
 use rocket::response::Redirect;
 use rocket::serde::Serialize;
-use rocket::State;
+use rocket_db_pools::Connection;
 use rocket_dyn_templates::Template;
+use serde_json::json;
 
-use crate::config::AppConfig;
+use crate::audit::{AuditContext, AuditEventType};
+use crate::db::DB;
+use crate::directory::{Directory, LdapDirectory};
 use crate::error::Error;
-use crate::ldap::{change_attrs, format_user_dn, get_ldap_user};
+use crate::notifications::{Notification, Notifier};
 use crate::sessions::User;
-use crate::DBLdapConn;
 
 #[derive(Serialize)]
 struct PersonalDataContext {
@@ -34,12 +33,10 @@ pub(crate) async fn get_personal_data(cookies: &CookieJar<'_>) -> Redirect {
 
 #[get("/personal_data")]
 pub(crate) async fn auth_get_personal_data(
-    app_config: &State<AppConfig>,
-    ldap_conn: DBLdapConn,
+    directory: LdapDirectory<'_>,
     cookie_user: User,
 ) -> Result<Template, Error> {
-    let app_config = app_config.inner();
-    let ldap_user = get_ldap_user(app_config, &ldap_conn, &cookie_user.get_username()[..]).await?;
+    let ldap_user = directory.get_user(&cookie_user.get_username()).await?;
     Ok(Template::render(
         "selfservice/personal_data",
         PersonalDataContext {
@@ -64,25 +61,31 @@ pub(crate) struct PersonalDataName<'r> {
 
 #[post("/personal_data/name", data = "<form>")]
 pub(crate) async fn change_name<'r>(
-    app_config: &State<AppConfig>,
-    ldap_conn: DBLdapConn,
+    directory: LdapDirectory<'_>,
     form: Form<PersonalDataName<'r>>,
     cookie_user: User,
+    mut db: Connection<DB>,
+    audit: AuditContext,
 ) -> Result<Template, Error> {
-    let app_config = app_config.inner();
     let form = form.into_inner();
-    let changes = Vec::from([
-        Mod::Replace(
-            "displayName".to_owned(),
-            HashSet::from([form.display_name.to_owned()]),
-        ),
-        Mod::Replace("cn".to_owned(), HashSet::from([form.first_name.to_owned()])),
-        Mod::Replace("sn".to_owned(), HashSet::from([form.last_name.to_owned()])),
-    ]);
     let username = &cookie_user.get_username()[..];
 
-    change_attrs(&ldap_conn, format_user_dn(app_config, username), changes).await?;
-    let ldap_user = get_ldap_user(app_config, &ldap_conn, username).await?;
+    directory
+        .update_user_name(username, form.display_name, form.first_name, form.last_name)
+        .await?;
+    audit
+        .record(
+            AuditEventType::NameChanged,
+            username,
+            json!({
+                "display_name": form.display_name,
+                "first_name": form.first_name,
+                "last_name": form.last_name,
+            }),
+            &mut *db,
+        )
+        .await?;
+    let ldap_user = directory.get_user(username).await?;
     Ok(Template::render(
         "selfservice/personal_data",
         PersonalDataContext {
@@ -105,23 +108,38 @@ pub(crate) struct PersonalDataEmail<'r> {
 
 #[post("/personal_data/email", data = "<form>")]
 pub(crate) async fn change_email<'r>(
-    app_config: &State<AppConfig>,
-    ldap_conn: DBLdapConn,
+    directory: LdapDirectory<'_>,
     form: Form<PersonalDataEmail<'r>>,
     cookie_user: User,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+    notifier: Notifier<'_>,
 ) -> Result<Template, Error> {
-    let app_config = app_config.inner();
     let form = form.into_inner();
     if form.email != form.email_validation {
         return Err(Error::Http(Status::BadRequest));
     }
-    let changes = Vec::from([Mod::Replace(
-        "mail".to_owned(),
-        HashSet::from([form.email.to_owned()]),
-    )]);
     let username = &cookie_user.get_username()[..];
-    change_attrs(&ldap_conn, format_user_dn(app_config, username), changes).await?;
-    let ldap_user = get_ldap_user(app_config, &ldap_conn, username).await?;
+    let previous_email = directory.get_user(username).await?.email;
+    directory.update_user_email(username, form.email).await?;
+    audit
+        .record(
+            AuditEventType::EmailChanged,
+            username,
+            json!({ "previous_email": previous_email, "email": form.email }),
+            &mut *db,
+        )
+        .await?;
+    notifier
+        .notify(
+            username,
+            Notification::EmailChanged {
+                previous_email,
+                email: form.email.to_owned(),
+            },
+        )
+        .await;
+    let ldap_user = directory.get_user(username).await?;
     Ok(Template::render(
         "selfservice/personal_data",
         PersonalDataContext {
@@ -1,7 +1,10 @@
+use crate::audit::{AuditContext, AuditEventType};
 use crate::config::{AppConfig, WebauthnStaticConfig};
 use crate::db::{DBTotpCredential, DBUserCredential, DBUserCredentialTypes, DB};
+use crate::directory::{Directory, LdapDirectory};
 use crate::error::Error;
-use crate::sessions::User;
+use crate::notifications::{Notification, Notifier};
+use crate::sessions::{SessionRevoker, User};
 use rocket::form::Form;
 use rocket::http::{Cookie, CookieJar, Status};
 use rocket::response::Redirect;
@@ -10,6 +13,7 @@ use rocket::{Either, State};
 use rocket_db_pools::Connection;
 use rocket_dyn_templates::{context, Template};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use totp_rs::{Algorithm, Secret, TOTP};
 use webauthn_rs::proto::{
     CreationChallengeResponse, Credential, CredentialID, RegisterPublicKeyCredential,
@@ -27,6 +31,7 @@ pub(crate) async fn get_security(cookies: &CookieJar<'_>) -> Redirect {
 
 #[derive(Serialize)]
 struct SecurityHomeContext {
+    message: Option<String>,
     webauthn_credentials: Vec<(uuid::Uuid, String)>,
     totp_credentials: Vec<(uuid::Uuid, String)>,
 }
@@ -36,14 +41,22 @@ pub(crate) async fn auth_get_security(
     cookie_user: User,
     mut db: Connection<DB>,
 ) -> Result<Template, Error> {
-    let username = cookie_user.get_username();
+    render_security(&cookie_user.get_username(), None, &mut db).await
+}
+
+async fn render_security(
+    username: &str,
+    message: Option<String>,
+    db: &mut Connection<DB>,
+) -> Result<Template, Error> {
     let webauthn_credentials =
-        DBUserCredential::find_webauthn_credentials_by_username(&*username, &mut *db).await?;
+        DBUserCredential::find_webauthn_credentials_by_username(username, &mut *db).await?;
     let totp_credentials =
-        DBUserCredential::find_totp_credentials_by_username(&*username, &mut *db).await?;
+        DBUserCredential::find_totp_credentials_by_username(username, &mut *db).await?;
     Ok(Template::render(
         "selfservice/security",
         SecurityHomeContext {
+            message,
             webauthn_credentials: webauthn_credentials
                 .iter()
                 .map(|c| {
@@ -66,18 +79,105 @@ pub(crate) async fn auth_get_security(
     ))
 }
 
+#[derive(FromForm)]
+pub(crate) struct PasswordChangeForm<'r> {
+    current_password: &'r str,
+    #[field(validate = len(1..))]
+    new_password: &'r str,
+    new_password_validation: &'r str,
+}
+
+#[post("/security/password", data = "<form>")]
+pub(crate) async fn auth_change_password<'r>(
+    cookie_user: User,
+    form: Form<PasswordChangeForm<'r>>,
+    directory: LdapDirectory<'_>,
+    mut db: Connection<DB>,
+    audit: AuditContext,
+    notifier: Notifier<'_>,
+) -> Result<Template, Error> {
+    let form = form.into_inner();
+    let username = cookie_user.get_username();
+    if form.new_password != form.new_password_validation {
+        return render_security(
+            &username,
+            Some("The new passwords do not match.".to_owned()),
+            &mut db,
+        )
+        .await;
+    }
+    let (message, event) = match directory
+        .change_password(&username, form.current_password, form.new_password)
+        .await
+    {
+        Ok(true) => (
+            "Your password has been changed.",
+            (AuditEventType::PasswordChanged, json!({})),
+        ),
+        Ok(false) => (
+            "The current password is wrong.",
+            (
+                AuditEventType::PasswordChangeFailed,
+                json!({ "reason": "wrong_password" }),
+            ),
+        ),
+        Err(Error::Http(status)) if status == Status::BadRequest => (
+            "The new password does not meet the password policy.",
+            (
+                AuditEventType::PasswordChangeFailed,
+                json!({ "reason": "password_policy" }),
+            ),
+        ),
+        Err(error) => return Err(error),
+    };
+    audit.record(event.0, &username, event.1, &mut *db).await?;
+    if event.0 == AuditEventType::PasswordChanged {
+        notifier
+            .notify(&username, Notification::PasswordChanged {})
+            .await;
+    }
+    render_security(&username, Some(message.to_owned()), &mut db).await
+}
+
 #[get("/security/credential/<credential_id>/delete")]
 pub(crate) async fn auth_credential_delete(
     cookie_user: User,
     credential_id: uuid::Uuid,
     mut db: Connection<DB>,
+    audit: AuditContext,
+    notifier: Notifier<'_>,
+    mut revoker: SessionRevoker<'_>,
 ) -> Result<Redirect, Error> {
-    DBUserCredential::<Credential>::delete_credential(
+    let username = cookie_user.get_username();
+    let label = DBUserCredential::<Credential>::find_label_by_id_and_username(
         credential_id,
-        &*cookie_user.get_username(),
+        &username,
         &mut *db,
     )
     .await?;
+    if DBUserCredential::<Credential>::delete_credential(credential_id, &*username, &mut *db)
+        .await?
+    {
+        audit
+            .record(
+                AuditEventType::CredentialDeleted,
+                &username,
+                json!({ "credential_id": credential_id }),
+                &mut *db,
+            )
+            .await?;
+        revoker
+            .cut_off(
+                &username,
+                AuditEventType::CredentialDeleted,
+                &audit,
+                &mut *db,
+            )
+            .await?;
+        notifier
+            .notify(&username, Notification::SecondFactorRemoved { label })
+            .await;
+    }
     Ok(Redirect::to(uri!("/selfservice", auth_get_security())))
 }
 
@@ -142,6 +242,8 @@ pub(crate) async fn auth_webauthn_register(
     reg: Json<RegisterPublicKeyCredential>,
     webauthn_static_config: &State<WebauthnStaticConfig>,
     mut db: Connection<DB>,
+    audit: AuditContext,
+    notifier: Notifier<'_>,
 ) -> Result<(), Error> {
     let webauthn_static_config = webauthn_static_config.inner().clone();
     let webauthn_client = Webauthn::new(webauthn_static_config);
@@ -173,6 +275,29 @@ pub(crate) async fn auth_webauthn_register(
                 &mut *db,
             )
             .await?;
+            audit
+                .record(
+                    AuditEventType::WebauthnCredentialAdded,
+                    cookie_username,
+                    json!({ "credential_id": credential_id }),
+                    &mut *db,
+                )
+                .await?;
+            let label = DBUserCredential::<Credential>::find_label_by_id_and_username(
+                credential_id,
+                cookie_username,
+                &mut *db,
+            )
+            .await?;
+            notifier
+                .notify(
+                    cookie_username,
+                    Notification::SecondFactorAdded {
+                        method: "webauthn",
+                        label,
+                    },
+                )
+                .await;
             Ok(())
         }
         Err(_) => Err(Error::Http(Status::InternalServerError)),
@@ -258,6 +383,8 @@ pub(crate) async fn auth_totp_setup_step3(
     app_config: &State<AppConfig>,
     form: Form<TOTPSetupStep2Form>,
     mut db: Connection<DB>,
+    audit: AuditContext,
+    notifier: Notifier<'_>,
 ) -> Result<Either<Redirect, Template>, Error> {
     let form = form.into_inner();
     let username = user.get_username();
@@ -274,13 +401,30 @@ pub(crate) async fn auth_totp_setup_step3(
             .to_bytes()
             .unwrap(),
         Some(app_config.name.clone()),
-        username,
+        username.clone(),
     ) {
         Ok(totp) => totp,
         Err(_) => return Err(Error::Http(Status::InternalServerError)),
     };
     if totp.check_current(&*form.otp.to_string())? {
         DBUserCredential::<DBTotpCredential>::update_temporary(form.db_id, false, &mut *db).await?;
+        audit
+            .record(
+                AuditEventType::TotpCredentialAdded,
+                &username,
+                json!({ "credential_id": form.db_id }),
+                &mut *db,
+            )
+            .await?;
+        notifier
+            .notify(
+                &username,
+                Notification::SecondFactorAdded {
+                    method: "totp",
+                    label: totp_credential.label.clone(),
+                },
+            )
+            .await;
         Ok(Either::Left(Redirect::to(uri!(
             "/selfservice",
             auth_get_security()
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use rocket::{fairing, Build, Rocket};
 use rocket_db_pools::Database;
 use serde::{Deserialize, Serialize};
@@ -18,6 +19,52 @@ pub(crate) struct DB(sqlx::PgPool);
 pub(crate) struct DBOAuthClient {
     pub client_id: String,
     pub login_allowed: bool,
+    pub skip_consent: bool,
+    pub require_2fa: bool,
+}
+
+impl DBOAuthClient {
+    pub async fn list_all(connection: &mut PoolConnection<Postgres>) -> Result<Vec<DBOAuthClient>> {
+        let clients = sqlx::query_as!(
+            DBOAuthClient,
+            "SELECT client_id, login_allowed, skip_consent, require_2fa FROM oauth_client"
+        )
+        .fetch_all(connection)
+        .await?;
+
+        Ok(clients)
+    }
+    pub async fn find_by_client_id(
+        client_id: &str,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Option<DBOAuthClient>> {
+        let client = sqlx::query_as!(
+            DBOAuthClient,
+            "SELECT client_id, login_allowed, skip_consent, require_2fa FROM oauth_client WHERE client_id = $1",
+            client_id
+        )
+        .fetch_optional(connection)
+        .await?;
+
+        Ok(client)
+    }
+    pub async fn upsert_one(
+        client: DBOAuthClient,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<bool> {
+        let rows_affected = sqlx::query!(
+            "INSERT INTO oauth_client (client_id, login_allowed, skip_consent, require_2fa) VALUES ($1, $2, $3, $4) ON CONFLICT (client_id) DO UPDATE SET login_allowed = $2, skip_consent = $3, require_2fa = $4",
+            client.client_id,
+            client.login_allowed,
+            client.skip_consent,
+            client.require_2fa
+        )
+        .execute(connection)
+        .await?
+        .rows_affected();
+
+        Ok(rows_affected > 0)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,20 +73,24 @@ pub(crate) struct DBGroup {
     pub id: Option<i32>,
     pub name: String,
     pub ldap_dn: String,
+    pub enforce_2fa: Option<bool>,
 }
 
 impl DBGroup {
     pub async fn list_all(connection: &mut PoolConnection<Postgres>) -> Result<Vec<DBGroup>> {
-        let groups = sqlx::query_as!(DBGroup, r#"SELECT id as "id?", name, ldap_dn FROM "group""#)
-            .fetch_all(connection)
-            .await?;
+        let groups = sqlx::query_as!(
+            DBGroup,
+            r#"SELECT id as "id?", name, ldap_dn, enforce_2fa FROM "group""#
+        )
+        .fetch_all(connection)
+        .await?;
 
         Ok(groups)
     }
     pub async fn find_by_id(id: i32, connection: &mut PoolConnection<Postgres>) -> Result<DBGroup> {
         let groups = sqlx::query_as!(
             DBGroup,
-            r#"SELECT id as "id?", name, ldap_dn FROM "group" WHERE id = $1"#,
+            r#"SELECT id as "id?", name, ldap_dn, enforce_2fa FROM "group" WHERE id = $1"#,
             id
         )
         .fetch_one(connection)
@@ -72,6 +123,591 @@ impl DBGroup {
 
         Ok(rec.id)
     }
+    pub async fn update_one(
+        group: DBGroup,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<bool> {
+        let rows_affected = sqlx::query!(
+            r#"UPDATE "group" SET name = $1, ldap_dn = $2 WHERE id = $3"#,
+            group.name,
+            group.ldap_dn,
+            group.id
+        )
+        .execute(connection)
+        .await?
+        .rows_affected();
+
+        Ok(rows_affected > 0)
+    }
+    pub async fn delete_one(id: i32, connection: &mut PoolConnection<Postgres>) -> Result<bool> {
+        let rows_affected = sqlx::query!(r#"DELETE FROM "group" WHERE id = $1"#, id)
+            .execute(connection)
+            .await?
+            .rows_affected();
+
+        Ok(rows_affected > 0)
+    }
+    pub async fn count_permissions(
+        id: i32,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<i64> {
+        let count = sqlx::query!(
+            r#"SELECT COUNT(*) as "count!" FROM group_permission WHERE group_id = $1"#,
+            id
+        )
+        .fetch_one(connection)
+        .await?
+        .count;
+
+        Ok(count)
+    }
+    pub async fn update_enforce_2fa(
+        id: i32,
+        enforce_2fa: Option<bool>,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<bool> {
+        let rows_affected = sqlx::query!(
+            r#"UPDATE "group" SET enforce_2fa = $1 WHERE id = $2"#,
+            enforce_2fa,
+            id
+        )
+        .execute(connection)
+        .await?
+        .rows_affected();
+
+        Ok(rows_affected > 0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct DBGroupOwner {
+    pub group_id: i32,
+    pub username: String,
+}
+
+impl DBGroupOwner {
+    pub async fn list_usernames_by_group_id(
+        group_id: i32,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Vec<String>> {
+        let usernames = sqlx::query!(
+            "SELECT username FROM group_owner WHERE group_id = $1 ORDER BY username",
+            group_id
+        )
+        .fetch_all(connection)
+        .await?
+        .into_iter()
+        .map(|rec| rec.username)
+        .collect();
+
+        Ok(usernames)
+    }
+    pub async fn list_group_ids_by_username(
+        username: &str,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Vec<i32>> {
+        let group_ids = sqlx::query!(
+            "SELECT group_id FROM group_owner WHERE username = $1",
+            username
+        )
+        .fetch_all(connection)
+        .await?
+        .into_iter()
+        .map(|rec| rec.group_id)
+        .collect();
+
+        Ok(group_ids)
+    }
+    pub async fn create_one(
+        owner: DBGroupOwner,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<bool> {
+        let rows_affected = sqlx::query!(
+            "INSERT INTO group_owner (group_id, username) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            owner.group_id,
+            owner.username
+        )
+        .execute(connection)
+        .await?
+        .rows_affected();
+
+        Ok(rows_affected > 0)
+    }
+    pub async fn delete_one(
+        group_id: i32,
+        username: &str,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<bool> {
+        let rows_affected = sqlx::query!(
+            "DELETE FROM group_owner WHERE group_id = $1 AND username = $2",
+            group_id,
+            username
+        )
+        .execute(connection)
+        .await?
+        .rows_affected();
+
+        Ok(rows_affected > 0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct DBAuditEvent {
+    pub id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub event_type: String,
+    pub actor: Option<String>,
+    pub subject: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub payload: Json<serde_json::Value>,
+}
+
+impl DBAuditEvent {
+    pub async fn create_one(
+        event: DBAuditEvent,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<i64> {
+        let rec = sqlx::query!(
+            "INSERT INTO audit_event (created_at, event_type, actor, subject, ip_address, user_agent, payload) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+            event.created_at,
+            event.event_type,
+            event.actor,
+            event.subject,
+            event.ip_address,
+            event.user_agent,
+            event.payload as _
+        )
+        .fetch_one(connection)
+        .await?;
+
+        Ok(rec.id)
+    }
+    /// Lists events newest first, optionally only those of `event_type` and those `username`
+    /// triggered or was affected by.
+    pub async fn list(
+        event_type: Option<&str>,
+        username: Option<&str>,
+        limit: i64,
+        offset: i64,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Vec<DBAuditEvent>> {
+        let events = sqlx::query_as!(
+            DBAuditEvent,
+            r#"SELECT id as "id?", created_at, event_type, actor, subject, ip_address, user_agent, payload as "payload!: Json<serde_json::Value>" FROM audit_event WHERE ($1::varchar IS NULL OR event_type = $1) AND ($2::varchar IS NULL OR actor = $2 OR subject = $2) ORDER BY id DESC LIMIT $3 OFFSET $4"#,
+            event_type,
+            username,
+            limit,
+            offset
+        )
+        .fetch_all(connection)
+        .await?;
+
+        Ok(events)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct DBUser2FAGrace {
+    pub username: String,
+    pub first_login_at: DateTime<Utc>,
+    pub login_count: i32,
+}
+
+impl DBUser2FAGrace {
+    pub async fn list_all(
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Vec<DBUser2FAGrace>> {
+        let graces = sqlx::query_as!(
+            DBUser2FAGrace,
+            "SELECT username, first_login_at, login_count FROM user_2fa_grace"
+        )
+        .fetch_all(connection)
+        .await?;
+
+        Ok(graces)
+    }
+    pub async fn find_by_username(
+        username: &str,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Option<DBUser2FAGrace>> {
+        let grace = sqlx::query_as!(
+            DBUser2FAGrace,
+            "SELECT username, first_login_at, login_count FROM user_2fa_grace WHERE username = $1",
+            username
+        )
+        .fetch_optional(connection)
+        .await?;
+
+        Ok(grace)
+    }
+    pub async fn record_login(
+        username: &str,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<DBUser2FAGrace> {
+        let grace = sqlx::query_as!(
+            DBUser2FAGrace,
+            "INSERT INTO user_2fa_grace (username, login_count) VALUES ($1, 1) ON CONFLICT (username) DO UPDATE SET login_count = user_2fa_grace.login_count + 1 RETURNING username, first_login_at, login_count",
+            username
+        )
+        .fetch_one(connection)
+        .await?;
+
+        Ok(grace)
+    }
+}
+
+/// A browser a user completed a login with, recognized by the `legitima_device` cookie.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct DBKnownDevice {
+    pub username: String,
+    pub device_id: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl DBKnownDevice {
+    pub async fn count_by_username(
+        username: &str,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<i64> {
+        let rec = sqlx::query!(
+            r#"SELECT COUNT(*) as "count!" FROM known_device WHERE username = $1"#,
+            username
+        )
+        .fetch_one(connection)
+        .await?;
+
+        Ok(rec.count)
+    }
+    /// Updates when and from where the device was last seen, returning false for unknown devices.
+    pub async fn touch(
+        device: &DBKnownDevice,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<bool> {
+        let rows_affected = sqlx::query!(
+            "UPDATE known_device SET last_seen_at = now(), ip_address = $3, user_agent = $4 WHERE username = $1 AND device_id = $2",
+            device.username,
+            device.device_id,
+            device.ip_address,
+            device.user_agent
+        )
+        .execute(connection)
+        .await?
+        .rows_affected();
+
+        Ok(rows_affected > 0)
+    }
+    pub async fn create_one(
+        device: &DBKnownDevice,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<bool> {
+        let rows_affected = sqlx::query!(
+            "INSERT INTO known_device (username, device_id, ip_address, user_agent) VALUES ($1, $2, $3, $4)",
+            device.username,
+            device.device_id,
+            device.ip_address,
+            device.user_agent
+        )
+        .execute(connection)
+        .await?
+        .rows_affected();
+
+        Ok(rows_affected == 1)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct DBServiceAccount {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl DBServiceAccount {
+    pub async fn list_all(
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Vec<DBServiceAccount>> {
+        let service_accounts = sqlx::query_as!(
+            DBServiceAccount,
+            "SELECT id, name, description, created_at FROM service_account ORDER BY name"
+        )
+        .fetch_all(connection)
+        .await?;
+
+        Ok(service_accounts)
+    }
+    pub async fn find_by_id(
+        id: i32,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<DBServiceAccount> {
+        let service_account = sqlx::query_as!(
+            DBServiceAccount,
+            "SELECT id, name, description, created_at FROM service_account WHERE id = $1",
+            id
+        )
+        .fetch_one(connection)
+        .await?;
+
+        Ok(service_account)
+    }
+    pub async fn create_one(
+        name: &str,
+        description: &str,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<i32> {
+        let rec = sqlx::query!(
+            "INSERT INTO service_account (name, description) VALUES ($1, $2) RETURNING id",
+            name,
+            description
+        )
+        .fetch_one(connection)
+        .await?;
+
+        Ok(rec.id)
+    }
+    pub async fn delete_one(id: i32, connection: &mut PoolConnection<Postgres>) -> Result<bool> {
+        let rows_affected = sqlx::query!("DELETE FROM service_account WHERE id = $1", id)
+            .execute(connection)
+            .await?
+            .rows_affected();
+
+        Ok(rows_affected > 0)
+    }
+}
+
+/// A key of a service account. Only the SHA-256 digest of the key is stored; `scopes` is a
+/// space-separated list as in OAuth2.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct DBApiKey {
+    pub id: uuid::Uuid,
+    pub service_account_id: i32,
+    pub label: String,
+    pub scopes: String,
+    pub created_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// A key that is not revoked, along with the name of its service account.
+#[derive(Debug, Clone)]
+pub(crate) struct DBActiveApiKey {
+    pub id: uuid::Uuid,
+    pub service_account_name: String,
+    pub scopes: String,
+}
+
+impl DBApiKey {
+    pub async fn list_all(connection: &mut PoolConnection<Postgres>) -> Result<Vec<DBApiKey>> {
+        let api_keys = sqlx::query_as!(
+            DBApiKey,
+            "SELECT id, service_account_id, label, scopes, created_at, rotated_at, last_used_at, revoked_at FROM api_key ORDER BY created_at"
+        )
+        .fetch_all(connection)
+        .await?;
+
+        Ok(api_keys)
+    }
+    pub async fn find_active_by_key_hash(
+        key_hash: &str,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Option<DBActiveApiKey>> {
+        let api_key = sqlx::query_as!(
+            DBActiveApiKey,
+            "SELECT api_key.id, service_account.name as service_account_name, api_key.scopes FROM api_key JOIN service_account ON service_account.id = api_key.service_account_id WHERE api_key.key_hash = $1 AND api_key.revoked_at IS NULL",
+            key_hash
+        )
+        .fetch_optional(connection)
+        .await?;
+
+        Ok(api_key)
+    }
+    pub async fn create_one(
+        service_account_id: i32,
+        label: &str,
+        key_hash: &str,
+        scopes: &str,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<uuid::Uuid> {
+        let rec = sqlx::query!(
+            "INSERT INTO api_key (service_account_id, label, key_hash, scopes) VALUES ($1, $2, $3, $4) RETURNING id",
+            service_account_id,
+            label,
+            key_hash,
+            scopes
+        )
+        .fetch_one(connection)
+        .await?;
+
+        Ok(rec.id)
+    }
+    /// Replaces the key of a key that is not revoked, keeping its label and scopes. Returns
+    /// the label, or `None` if there is no such key.
+    pub async fn rotate(
+        id: uuid::Uuid,
+        service_account_id: i32,
+        key_hash: &str,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Option<String>> {
+        let rec = sqlx::query!(
+            "UPDATE api_key SET key_hash = $1, rotated_at = now() WHERE id = $2 AND service_account_id = $3 AND revoked_at IS NULL RETURNING label",
+            key_hash,
+            id,
+            service_account_id
+        )
+        .fetch_optional(connection)
+        .await?;
+
+        Ok(rec.map(|rec| rec.label))
+    }
+    pub async fn revoke(
+        id: uuid::Uuid,
+        service_account_id: i32,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<bool> {
+        let rows_affected = sqlx::query!(
+            "UPDATE api_key SET revoked_at = now() WHERE id = $1 AND service_account_id = $2 AND revoked_at IS NULL",
+            id,
+            service_account_id
+        )
+        .execute(connection)
+        .await?
+        .rows_affected();
+
+        Ok(rows_affected > 0)
+    }
+    pub async fn touch(id: uuid::Uuid, connection: &mut PoolConnection<Postgres>) -> Result<()> {
+        sqlx::query!("UPDATE api_key SET last_used_at = now() WHERE id = $1", id)
+            .execute(connection)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// A change to push to the app of a client. `status` is `pending` until the change has been
+/// `delivered` or, after too many failed attempts, `failed`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct DBProvisioningDelivery {
+    pub id: i64,
+    pub client_id: String,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub event_type: String,
+    pub subject: Option<String>,
+}
+
+/// A delivery that is due, along with the audit event describing the change.
+#[derive(Debug, Clone)]
+pub(crate) struct DBDueProvisioningDelivery {
+    pub id: i64,
+    pub client_id: String,
+    pub attempts: i32,
+    pub event_type: String,
+    pub subject: Option<String>,
+    pub event_created_at: DateTime<Utc>,
+    pub payload: Json<serde_json::Value>,
+}
+
+impl DBProvisioningDelivery {
+    pub async fn create_one(
+        client_id: &str,
+        audit_event_id: i64,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO provisioning_delivery (client_id, audit_event_id) VALUES ($1, $2)",
+            client_id,
+            audit_event_id
+        )
+        .execute(connection)
+        .await?;
+
+        Ok(())
+    }
+    /// Lists due deliveries oldest first. Deliveries of a client waiting for the retry of an
+    /// earlier one are held back, so every app receives its changes in order.
+    pub async fn list_due(
+        limit: i64,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Vec<DBDueProvisioningDelivery>> {
+        let deliveries = sqlx::query_as!(
+            DBDueProvisioningDelivery,
+            r#"SELECT d.id, d.client_id, d.attempts, e.event_type, e.subject, e.created_at as event_created_at, e.payload as "payload!: Json<serde_json::Value>" FROM provisioning_delivery d JOIN audit_event e ON e.id = d.audit_event_id WHERE d.status = 'pending' AND d.next_attempt_at <= now() AND NOT EXISTS (SELECT 1 FROM provisioning_delivery p WHERE p.client_id = d.client_id AND p.status = 'pending' AND p.id < d.id AND p.next_attempt_at > now()) ORDER BY d.id LIMIT $1"#,
+            limit
+        )
+        .fetch_all(connection)
+        .await?;
+
+        Ok(deliveries)
+    }
+    pub async fn mark_delivered(id: i64, connection: &mut PoolConnection<Postgres>) -> Result<()> {
+        sqlx::query!(
+            "UPDATE provisioning_delivery SET status = 'delivered', attempts = attempts + 1, delivered_at = now(), last_error = NULL WHERE id = $1",
+            id
+        )
+        .execute(connection)
+        .await?;
+
+        Ok(())
+    }
+    /// Records a failed attempt. Without `next_attempt_at` the delivery is given up.
+    pub async fn record_failure(
+        id: i64,
+        error: &str,
+        next_attempt_at: Option<DateTime<Utc>>,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<()> {
+        sqlx::query!(
+            "UPDATE provisioning_delivery SET status = CASE WHEN $1::timestamptz IS NULL THEN 'failed' ELSE 'pending' END, attempts = attempts + 1, next_attempt_at = COALESCE($1, next_attempt_at), last_error = $2 WHERE id = $3",
+            next_attempt_at,
+            error,
+            id
+        )
+        .execute(connection)
+        .await?;
+
+        Ok(())
+    }
+    /// Queues a given up delivery again.
+    pub async fn retry(id: i64, connection: &mut PoolConnection<Postgres>) -> Result<bool> {
+        let rows_affected = sqlx::query!(
+            "UPDATE provisioning_delivery SET status = 'pending', attempts = 0, next_attempt_at = now() WHERE id = $1 AND status = 'failed'",
+            id
+        )
+        .execute(connection)
+        .await?
+        .rows_affected();
+
+        Ok(rows_affected > 0)
+    }
+    /// Lists deliveries newest first, optionally only those of `client_id` and `status`.
+    pub async fn list(
+        client_id: Option<&str>,
+        status: Option<&str>,
+        limit: i64,
+        offset: i64,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Vec<DBProvisioningDelivery>> {
+        let deliveries = sqlx::query_as!(
+            DBProvisioningDelivery,
+            "SELECT d.id, d.client_id, d.status, d.attempts, d.next_attempt_at, d.last_error, d.created_at, d.delivered_at, e.event_type, e.subject FROM provisioning_delivery d JOIN audit_event e ON e.id = d.audit_event_id WHERE ($1::varchar IS NULL OR d.client_id = $1) AND ($2::varchar IS NULL OR d.status = $2) ORDER BY d.id DESC LIMIT $3 OFFSET $4",
+            client_id,
+            status,
+            limit,
+            offset
+        )
+        .fetch_all(connection)
+        .await?;
+
+        Ok(deliveries)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -257,6 +893,21 @@ impl<D: DBUserCredentialData + Serialize + Sync> DBUserCredential<D> {
 
         Ok(rows_affected > 0)
     }
+    pub async fn find_label_by_id_and_username(
+        id: uuid::Uuid,
+        username: &str,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Option<String>> {
+        let rec = sqlx::query!(
+            "SELECT label FROM user_credential WHERE id = $1 AND username = $2",
+            id,
+            username
+        )
+        .fetch_optional(connection)
+        .await?;
+
+        Ok(rec.and_then(|rec| rec.label))
+    }
     pub async fn delete_credential(
         id: uuid::Uuid,
         username: &str,
@@ -305,6 +956,16 @@ impl<D: DBUserCredentialData + Serialize + Sync> DBUserCredential<D> {
             .map(|cred| cred.credential_type)
             .collect())
     }
+    pub async fn find_usernames_with_permanent_credentials(
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<Vec<String>> {
+        let usernames =
+            sqlx::query!("SELECT DISTINCT username FROM user_credential WHERE temporary = false")
+                .fetch_all(connection)
+                .await?;
+
+        Ok(usernames.into_iter().map(|rec| rec.username).collect())
+    }
 }
 
 pub(crate) async fn run_migrations(rocket: Rocket<Build>) -> fairing::Result {
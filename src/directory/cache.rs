@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::directory::{Group, User};
+
+/// Map whose entries expire after the TTL passed on lookup.
+pub(crate) struct TtlMap<K, V> {
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K, V> Default for TtlMap<K, V> {
+    fn default() -> Self {
+        TtlMap {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> TtlMap<K, V> {
+    pub(crate) fn get(&self, key: &K, ttl: Duration) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((cached_at, value)) if cached_at.elapsed() < ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Stores `value` and drops every entry older than `ttl`, so keys that are never looked up
+    /// again do not pile up.
+    pub(crate) fn insert(&self, key: K, value: V, ttl: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (cached_at, _)| cached_at.elapsed() < ttl);
+        entries.insert(key, (Instant::now(), value));
+    }
+
+    pub(crate) fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Short-lived copies of directory lookups, shared by all requests. Every write through the
+/// directory clears the affected maps, changes made elsewhere show up once the TTL expired.
+#[derive(Default)]
+pub(crate) struct DirectoryCache {
+    pub(crate) users: TtlMap<String, User>,
+    pub(crate) user_list: TtlMap<(), Vec<User>>,
+    pub(crate) user_groups: TtlMap<String, Vec<String>>,
+    pub(crate) groups: TtlMap<String, Group>,
+    pub(crate) group_list: TtlMap<(), Vec<Group>>,
+}
+
+impl DirectoryCache {
+    pub(crate) fn invalidate_users(&self) {
+        self.users.clear();
+        self.user_list.clear();
+    }
+
+    /// Memberships may be nested, so a change to one group can affect every user.
+    pub(crate) fn invalidate_groups(&self) {
+        self.user_groups.clear();
+        self.groups.clear();
+        self.group_list.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_evicts_expired_entries() {
+        let map = TtlMap::default();
+        map.insert("old", 1, Duration::from_secs(60));
+        std::thread::sleep(Duration::from_millis(20));
+        map.insert("new", 2, Duration::from_millis(10));
+        assert_eq!(map.entries.lock().unwrap().len(), 1);
+        assert_eq!(map.get(&"new", Duration::from_secs(60)), Some(2));
+    }
+
+    #[test]
+    fn get_honours_the_ttl() {
+        let map = TtlMap::default();
+        map.insert("key", 1, Duration::from_secs(60));
+        assert_eq!(map.get(&"key", Duration::from_secs(60)), Some(1));
+        assert_eq!(map.get(&"key", Duration::ZERO), None);
+        assert_eq!(map.get(&"key", Duration::from_secs(60)), None);
+    }
+}
@@ -0,0 +1,698 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use ldap3::asn1::{parse_tag, parse_uint, IResult, StructureTag, TagClass, Types, PL};
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use serde_json::json;
+
+use crate::directory::pool::LdapPool;
+
+const SERVICE_DN: &str = "cn=legitima,dc=example,dc=com";
+const SERVICE_PASSWORD: &str = "service";
+
+const PAGED_RESULTS_OID: &[u8] = b"1.2.840.113556.1.4.319";
+const IN_CHAIN_OID: &[u8] = b"1.2.840.113556.1.4.1941";
+
+const SUCCESS: u8 = 0;
+const NO_SUCH_ATTRIBUTE: u8 = 16;
+const CONSTRAINT_VIOLATION: u8 = 19;
+const ATTRIBUTE_OR_VALUE_EXISTS: u8 = 20;
+const NO_SUCH_OBJECT: u8 = 32;
+const INVALID_CREDENTIALS: u8 = 49;
+const INSUFFICIENT_ACCESS_RIGHTS: u8 = 50;
+const UNWILLING_TO_PERFORM: u8 = 53;
+const OBJECT_CLASS_VIOLATION: u8 = 65;
+const ENTRY_ALREADY_EXISTS: u8 = 68;
+
+#[derive(Clone)]
+struct Entry {
+    dn: String,
+    attrs: Vec<(String, Vec<Vec<u8>>)>,
+}
+
+impl Entry {
+    fn values(&self, attr: &str) -> &[Vec<u8>] {
+        self.attrs
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(attr))
+            .map_or(&[], |(_, values)| values)
+    }
+
+    fn values_mut(&mut self, attr: &str) -> &mut Vec<Vec<u8>> {
+        let index = match self
+            .attrs
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(attr))
+        {
+            Some(index) => index,
+            None => {
+                self.attrs.push((attr.to_owned(), Vec::new()));
+                self.attrs.len() - 1
+            }
+        };
+        &mut self.attrs[index].1
+    }
+
+    fn has_value(&self, attr: &str, value: &[u8]) -> bool {
+        self.values(attr)
+            .iter()
+            .any(|stored| stored.eq_ignore_ascii_case(value))
+    }
+
+    fn in_scope(&self, base: &str, scope: u64) -> bool {
+        let dn = self.dn.to_ascii_lowercase();
+        let base = base.to_ascii_lowercase();
+        match scope {
+            0 => dn == base,
+            _ if base.is_empty() => scope == 2 || !dn.contains(','),
+            1 => dn
+                .strip_suffix(&base)
+                .and_then(|rdn| rdn.strip_suffix(','))
+                .map_or(false, |rdn| !rdn.contains(',')),
+            _ => dn == base || dn.ends_with(&format!(",{}", base)),
+        }
+    }
+}
+
+fn unicode_pwd(password: &[u8]) -> Vec<u8> {
+    format!("\"{}\"", String::from_utf8_lossy(password))
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+/// LDAP server on a local port answering from memory, speaking just enough of the protocol
+/// for `LdapDirectory`: simple binds, paged searches, adds, deletes and modifications. It
+/// mimics OpenLDAP without the refint overlay or, if created for it, Active Directory:
+///
+/// - `groupOfNames` and `groupOfUniqueNames` must keep a member on OpenLDAP.
+/// - Active Directory keeps passwords in `unicodePwd`, which is never returned, rejects binds
+///   of accounts with the disabled flag and resolves nested groups with the in-chain matching
+///   rule. Deleted entries are dropped from `member` and `managedBy` of all other entries.
+/// - A password change, deleting the old `unicodePwd` and adding the new one, is only
+///   accepted on a connection bound as that user.
+#[derive(Clone, Default)]
+pub(crate) struct FakeLdapServer {
+    entries: Arc<Mutex<BTreeMap<String, Entry>>>,
+    active_directory: bool,
+}
+
+impl FakeLdapServer {
+    /// A server holding the tree `AppConfig::for_tests` points to and the service account.
+    pub(crate) fn open_ldap() -> Self {
+        FakeLdapServer::default().with_tree()
+    }
+
+    pub(crate) fn active_directory() -> Self {
+        FakeLdapServer {
+            active_directory: true,
+            ..FakeLdapServer::default()
+        }
+        .with_tree()
+    }
+
+    fn with_tree(self) -> Self {
+        self.with_entry("dc=example,dc=com", &[("objectClass", &["domain"])])
+            .with_entry(
+                "ou=users,dc=example,dc=com",
+                &[("objectClass", &["organizationalUnit"])],
+            )
+            .with_entry(
+                "ou=groups,dc=example,dc=com",
+                &[("objectClass", &["organizationalUnit"])],
+            )
+            .with_password(SERVICE_DN, SERVICE_PASSWORD)
+    }
+
+    pub(crate) fn with_entry(self, dn: &str, attrs: &[(&str, &[&str])]) -> Self {
+        let entry = Entry {
+            dn: dn.to_owned(),
+            attrs: attrs
+                .iter()
+                .map(|(attr, values)| {
+                    (
+                        attr.to_string(),
+                        values
+                            .iter()
+                            .map(|value| value.as_bytes().to_vec())
+                            .collect(),
+                    )
+                })
+                .collect(),
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(dn.to_ascii_lowercase(), entry);
+        self
+    }
+
+    /// Sets the password `dn` binds with, in `unicodePwd` on Active Directory and in
+    /// `userPassword` otherwise.
+    pub(crate) fn with_password(self, dn: &str, password: &str) -> Self {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let entry = entries
+                .entry(dn.to_ascii_lowercase())
+                .or_insert_with(|| Entry {
+                    dn: dn.to_owned(),
+                    attrs: Vec::new(),
+                });
+            *entry.values_mut(self.password_attr()) =
+                vec![self.password_value(password.as_bytes())];
+        }
+        self
+    }
+
+    /// The values of `attr` in the entry `dn`, or nothing if there is no such entry.
+    pub(crate) fn values(&self, dn: &str, attr: &str) -> Option<Vec<String>> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(&dn.to_ascii_lowercase()).map(|entry| {
+            entry
+                .values(attr)
+                .iter()
+                .map(|value| String::from_utf8_lossy(value).into_owned())
+                .collect()
+        })
+    }
+
+    /// Listens on a local port and returns a pool for it, binding as the service account.
+    pub(crate) async fn start(&self) -> LdapPool {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        let server = self.clone();
+        rocket::tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                rocket::tokio::spawn(server.clone().serve(stream));
+            }
+        });
+        LdapPool::new(
+            serde_json::from_value(json!({
+                "urls": [url],
+                "bind_dn": SERVICE_DN,
+                "bind_password": SERVICE_PASSWORD,
+            }))
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn password_attr(&self) -> &'static str {
+        match self.active_directory {
+            true => "unicodePwd",
+            false => "userPassword",
+        }
+    }
+
+    fn password_value(&self, password: &[u8]) -> Vec<u8> {
+        match self.active_directory {
+            true => unicode_pwd(password),
+            false => password.to_vec(),
+        }
+    }
+
+    async fn serve(self, mut stream: TcpStream) {
+        let mut buffer = Vec::new();
+        let mut bound_dn = String::new();
+        loop {
+            let message = match parse_tag(&buffer) {
+                IResult::Done(rest, message) => {
+                    let consumed = buffer.len() - rest.len();
+                    buffer.drain(..consumed);
+                    message
+                }
+                IResult::Incomplete(_) => {
+                    let mut chunk = [0; 4096];
+                    match stream.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+                    }
+                    continue;
+                }
+                IResult::Error(_) => return,
+            };
+            let mut parts = children(message).into_iter();
+            let (id, op) = match (parts.next(), parts.next()) {
+                (Some(id), Some(op)) => (id, op),
+                _ => return,
+            };
+            let controls = parts.next().map(children).unwrap_or_default();
+            let responses = match op.id {
+                0 => vec![self.bind(op, &mut bound_dn)],
+                // Unbind
+                2 => return,
+                3 => self.search(op, &controls),
+                6 => vec![self.modify(op, &bound_dn)],
+                8 => vec![self.add(op)],
+                10 => vec![self.delete(op)],
+                // Abandon
+                16 => Vec::new(),
+                23 => vec![(result(24, UNWILLING_TO_PERFORM), None)],
+                _ => return,
+            };
+            let mut output = Vec::new();
+            for (op, controls) in responses {
+                let mut message = vec![id.clone(), op];
+                message.extend(controls);
+                encode(&constructed(TagClass::Universal, 16, message), &mut output);
+            }
+            if stream.write_all(&output).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn bind(
+        &self,
+        op: StructureTag,
+        bound_dn: &mut String,
+    ) -> (StructureTag, Option<StructureTag>) {
+        let mut parts = children(op).into_iter().skip(1);
+        let name = string(parts.next().unwrap());
+        let password = bytes(parts.next().unwrap());
+        *bound_dn = String::new();
+        let code = if name.is_empty() || password.is_empty() {
+            // Anonymous and unauthenticated binds
+            SUCCESS
+        } else {
+            let entries = self.entries.lock().unwrap();
+            match entries.get(&name.to_ascii_lowercase()) {
+                Some(entry)
+                    if entry.has_value(self.password_attr(), &self.password_value(&password))
+                        && !(self.active_directory && is_disabled(entry)) =>
+                {
+                    *bound_dn = name.to_ascii_lowercase();
+                    SUCCESS
+                }
+                _ => INVALID_CREDENTIALS,
+            }
+        };
+        (result(1, code), None)
+    }
+
+    fn search(
+        &self,
+        op: StructureTag,
+        controls: &[StructureTag],
+    ) -> Vec<(StructureTag, Option<StructureTag>)> {
+        let parts = children(op);
+        let base = string(parts[0].clone());
+        let scope = uint(parts[1].clone());
+        let filter = &parts[6];
+        let attrs: Vec<String> = children(parts[7].clone()).into_iter().map(string).collect();
+        let entries = self.entries.lock().unwrap();
+        if !base.is_empty() && !entries.contains_key(&base.to_ascii_lowercase()) {
+            return vec![(result(5, NO_SUCH_OBJECT), None)];
+        }
+        let found: Vec<&Entry> = entries
+            .values()
+            .filter(|entry| entry.in_scope(&base, scope) && self.matches(&entries, entry, filter))
+            .collect();
+        // Pages are cut by offset, which the cookie carries.
+        let paging = controls.iter().find_map(|control| {
+            let parts = children(control.clone());
+            match bytes(parts[0].clone()) == PAGED_RESULTS_OID {
+                true => match parse_tag(&bytes(parts.last().unwrap().clone())) {
+                    IResult::Done(_, value) => Some(children(value)),
+                    _ => None,
+                },
+                false => None,
+            }
+        });
+        let (start, end) = match &paging {
+            Some(value) => {
+                let size = uint(value[0].clone()) as usize;
+                let start = String::from_utf8_lossy(&bytes(value[1].clone()))
+                    .parse()
+                    .unwrap_or(0);
+                (start, found.len().min(start + size))
+            }
+            None => (0, found.len()),
+        };
+        let mut responses: Vec<_> = found[start..end]
+            .iter()
+            .map(|entry| (self.search_entry(entry, &attrs), None))
+            .collect();
+        let done_controls = paging.map(|_| {
+            let cookie = match end < found.len() {
+                true => end.to_string().into_bytes(),
+                false => Vec::new(),
+            };
+            let mut value = Vec::new();
+            encode(
+                &sequence(vec![
+                    primitive(TagClass::Universal, Types::Integer as u64, vec![0]),
+                    octet_string(cookie),
+                ]),
+                &mut value,
+            );
+            constructed(
+                TagClass::Context,
+                0,
+                vec![sequence(vec![
+                    octet_string(PAGED_RESULTS_OID.to_vec()),
+                    octet_string(value),
+                ])],
+            )
+        });
+        responses.push((result(5, SUCCESS), done_controls));
+        responses
+    }
+
+    fn search_entry(&self, entry: &Entry, attrs: &[String]) -> StructureTag {
+        let all = attrs.is_empty() || attrs.iter().any(|attr| attr == "*");
+        let returned = entry
+            .attrs
+            .iter()
+            .filter(|(name, values)| {
+                let hidden = self.active_directory && name.eq_ignore_ascii_case("unicodePwd");
+                let requested = all || attrs.iter().any(|attr| attr.eq_ignore_ascii_case(name));
+                !values.is_empty() && !hidden && requested
+            })
+            .map(|(name, values)| {
+                sequence(vec![
+                    octet_string(name.as_bytes().to_vec()),
+                    constructed(
+                        TagClass::Universal,
+                        Types::Set as u64,
+                        values.iter().cloned().map(octet_string).collect(),
+                    ),
+                ])
+            })
+            .collect();
+        constructed(
+            TagClass::Application,
+            4,
+            vec![
+                octet_string(entry.dn.as_bytes().to_vec()),
+                sequence(returned),
+            ],
+        )
+    }
+
+    fn matches(
+        &self,
+        entries: &BTreeMap<String, Entry>,
+        entry: &Entry,
+        filter: &StructureTag,
+    ) -> bool {
+        let parts = match &filter.payload {
+            PL::C(parts) => parts.clone(),
+            // Presence
+            PL::P(attr) => {
+                let attr = String::from_utf8_lossy(attr);
+                return attr.eq_ignore_ascii_case("objectClass") || !entry.values(&attr).is_empty();
+            }
+        };
+        match filter.id {
+            0 => parts.iter().all(|part| self.matches(entries, entry, part)),
+            1 => parts.iter().any(|part| self.matches(entries, entry, part)),
+            2 => !self.matches(entries, entry, &parts[0]),
+            3 => entry.has_value(&string(parts[0].clone()), &bytes(parts[1].clone())),
+            4 => {
+                let attr = string(parts[0].clone());
+                let substrings = children(parts[1].clone());
+                entry.values(&attr).iter().any(|value| {
+                    let mut rest = String::from_utf8_lossy(value).to_lowercase();
+                    substrings.iter().all(|substring| {
+                        let wanted =
+                            String::from_utf8_lossy(&bytes(substring.clone())).to_lowercase();
+                        let found = match substring.id {
+                            0 => rest.starts_with(&wanted).then_some(wanted.len()),
+                            1 => rest.find(&wanted).map(|index| index + wanted.len()),
+                            _ => rest.ends_with(&wanted).then_some(rest.len()),
+                        };
+                        match found {
+                            Some(end) => {
+                                rest = rest[end..].to_owned();
+                                true
+                            }
+                            None => false,
+                        }
+                    })
+                })
+            }
+            9 => {
+                let part = |id| parts.iter().find(|part| part.id == id).cloned().map(bytes);
+                let (rule, attr, value) = (part(1), part(2), part(3).unwrap_or_default());
+                let attr = String::from_utf8_lossy(&attr.unwrap_or_default()).into_owned();
+                match rule.as_deref() {
+                    None => entry.has_value(&attr, &value),
+                    Some(IN_CHAIN_OID) if self.active_directory => {
+                        in_chain(entries, entry, &attr, &value, &mut HashSet::new())
+                    }
+                    Some(_) => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn modify(&self, op: StructureTag, bound_dn: &str) -> (StructureTag, Option<StructureTag>) {
+        let mut parts = children(op).into_iter();
+        let dn = string(parts.next().unwrap()).to_ascii_lowercase();
+        let mut entries = self.entries.lock().unwrap();
+        let mut entry = match entries.get(&dn) {
+            Some(entry) => entry.clone(),
+            None => return (result(7, NO_SUCH_OBJECT), None),
+        };
+        for change in children(parts.next().unwrap()) {
+            let change = children(change);
+            let operation = uint(change[0].clone());
+            let modification = children(change[1].clone());
+            let attr = string(modification[0].clone());
+            let values: Vec<Vec<u8>> = children(modification[1].clone())
+                .into_iter()
+                .map(bytes)
+                .collect();
+            let password = self.active_directory && attr.eq_ignore_ascii_case("unicodePwd");
+            if password && operation != 2 && bound_dn != dn {
+                return (result(7, INSUFFICIENT_ACCESS_RIGHTS), None);
+            }
+            let stored = entry.values_mut(&attr);
+            let code = match operation {
+                0 => {
+                    for value in values {
+                        if stored
+                            .iter()
+                            .any(|other| other.eq_ignore_ascii_case(&value))
+                        {
+                            return (result(7, ATTRIBUTE_OR_VALUE_EXISTS), None);
+                        }
+                        stored.push(value);
+                    }
+                    SUCCESS
+                }
+                1 if values.is_empty() => match stored.is_empty() {
+                    true => NO_SUCH_ATTRIBUTE,
+                    false => {
+                        stored.clear();
+                        SUCCESS
+                    }
+                },
+                1 => {
+                    let before = stored.len();
+                    stored.retain(|other| {
+                        !values.iter().any(|value| value.eq_ignore_ascii_case(other))
+                    });
+                    match before - stored.len() == values.len() {
+                        true => SUCCESS,
+                        // A wrong current password
+                        false if password => CONSTRAINT_VIOLATION,
+                        false => NO_SUCH_ATTRIBUTE,
+                    }
+                }
+                _ => {
+                    *stored = values;
+                    SUCCESS
+                }
+            };
+            if code != SUCCESS {
+                return (result(7, code), None);
+            }
+        }
+        let code = self.check_schema(&entry);
+        if code == SUCCESS {
+            entries.insert(dn, entry);
+        }
+        (result(7, code), None)
+    }
+
+    fn add(&self, op: StructureTag) -> (StructureTag, Option<StructureTag>) {
+        let mut parts = children(op).into_iter();
+        let dn = string(parts.next().unwrap());
+        let entry = Entry {
+            dn: dn.clone(),
+            attrs: children(parts.next().unwrap())
+                .into_iter()
+                .map(|attr| {
+                    let mut attr = children(attr).into_iter();
+                    (
+                        string(attr.next().unwrap()),
+                        children(attr.next().unwrap())
+                            .into_iter()
+                            .map(bytes)
+                            .collect(),
+                    )
+                })
+                .collect(),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        let code = match entries.contains_key(&dn.to_ascii_lowercase()) {
+            true => ENTRY_ALREADY_EXISTS,
+            false => self.check_schema(&entry),
+        };
+        if code == SUCCESS {
+            entries.insert(dn.to_ascii_lowercase(), entry);
+        }
+        (result(9, code), None)
+    }
+
+    fn delete(&self, op: StructureTag) -> (StructureTag, Option<StructureTag>) {
+        let dn = string(op).to_ascii_lowercase();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.remove(&dn).is_none() {
+            return (result(11, NO_SUCH_OBJECT), None);
+        }
+        if self.active_directory {
+            for entry in entries.values_mut() {
+                for attr in ["member", "managedBy"] {
+                    entry
+                        .values_mut(attr)
+                        .retain(|value| !value.eq_ignore_ascii_case(dn.as_bytes()));
+                }
+            }
+        }
+        (result(11, SUCCESS), None)
+    }
+
+    fn check_schema(&self, entry: &Entry) -> u8 {
+        if self.active_directory {
+            return SUCCESS;
+        }
+        let required = [
+            ("groupOfNames", "member"),
+            ("groupOfUniqueNames", "uniqueMember"),
+        ];
+        match required.iter().any(|(class, attr)| {
+            entry.has_value("objectClass", class.as_bytes()) && entry.values(attr).is_empty()
+        }) {
+            true => OBJECT_CLASS_VIOLATION,
+            false => SUCCESS,
+        }
+    }
+}
+
+fn is_disabled(entry: &Entry) -> bool {
+    entry
+        .values("userAccountControl")
+        .first()
+        .and_then(|flags| String::from_utf8_lossy(flags).parse::<u32>().ok())
+        .map_or(false, |flags| flags & 0x2 != 0)
+}
+
+/// Whether `value` is in `attr` of `entry` or of an entry listed there, transitively.
+fn in_chain(
+    entries: &BTreeMap<String, Entry>,
+    entry: &Entry,
+    attr: &str,
+    value: &[u8],
+    seen: &mut HashSet<String>,
+) -> bool {
+    entry.values(attr).iter().any(|member| {
+        let member = String::from_utf8_lossy(member).to_ascii_lowercase();
+        member.as_bytes().eq_ignore_ascii_case(value)
+            || (seen.insert(member.clone())
+                && entries
+                    .get(&member)
+                    .map_or(false, |nested| in_chain(entries, nested, attr, value, seen)))
+    })
+}
+
+fn children(tag: StructureTag) -> Vec<StructureTag> {
+    tag.expect_constructed().unwrap_or_default()
+}
+
+fn bytes(tag: StructureTag) -> Vec<u8> {
+    tag.expect_primitive().unwrap_or_default()
+}
+
+fn string(tag: StructureTag) -> String {
+    String::from_utf8_lossy(&bytes(tag)).into_owned()
+}
+
+fn uint(tag: StructureTag) -> u64 {
+    match parse_uint(&bytes(tag)) {
+        IResult::Done(_, value) => value,
+        _ => 0,
+    }
+}
+
+fn primitive(class: TagClass, id: u64, value: Vec<u8>) -> StructureTag {
+    StructureTag {
+        class,
+        id,
+        payload: PL::P(value),
+    }
+}
+
+fn constructed(class: TagClass, id: u64, parts: Vec<StructureTag>) -> StructureTag {
+    StructureTag {
+        class,
+        id,
+        payload: PL::C(parts),
+    }
+}
+
+fn octet_string(value: Vec<u8>) -> StructureTag {
+    primitive(TagClass::Universal, Types::OctetString as u64, value)
+}
+
+fn sequence(parts: Vec<StructureTag>) -> StructureTag {
+    constructed(TagClass::Universal, Types::Sequence as u64, parts)
+}
+
+/// An `LDAPResult` of the response `op`.
+fn result(op: u64, code: u8) -> StructureTag {
+    constructed(
+        TagClass::Application,
+        op,
+        vec![
+            primitive(TagClass::Universal, Types::Enumerated as u64, vec![code]),
+            octet_string(Vec::new()),
+            octet_string(Vec::new()),
+        ],
+    )
+}
+
+/// BER-encodes `tag`, which only ever has low tag numbers here.
+fn encode(tag: &StructureTag, output: &mut Vec<u8>) {
+    let class = match tag.class {
+        TagClass::Universal => 0x00,
+        TagClass::Application => 0x40,
+        TagClass::Context => 0x80,
+        TagClass::Private => 0xc0,
+    };
+    let (structure, content) = match &tag.payload {
+        PL::P(value) => (0x00, value.clone()),
+        PL::C(parts) => {
+            let mut content = Vec::new();
+            for part in parts {
+                encode(part, &mut content);
+            }
+            (0x20, content)
+        }
+    };
+    output.push(class | structure | tag.id as u8);
+    match content.len() {
+        len if len < 0x80 => output.push(len as u8),
+        len => {
+            let len = (len as u32).to_be_bytes();
+            let len = &len[len.iter().position(|byte| *byte != 0).unwrap()..];
+            output.push(0x80 | len.len() as u8);
+            output.extend_from_slice(len);
+        }
+    }
+    output.extend(content);
+}
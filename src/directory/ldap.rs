@@ -0,0 +1,1422 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::time::Duration;
+
+use ldap3::adapters::{Adapter, EntriesOnly, PagedResults};
+use ldap3::exop::PasswordModify;
+use ldap3::{dn_escape, ldap_escape, Ldap, LdapError, Mod, Scope, SearchEntry};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+use crate::config::{AppConfig, LdapGroupSchema, LdapProfile};
+use crate::directory::cache::DirectoryCache;
+use crate::directory::pool::LdapPool;
+use crate::directory::{Directory, Group, NewUser, Page, User};
+use crate::error::Error;
+
+/// `userAccountControl` flag of disabled Active Directory accounts.
+const AD_ACCOUNTDISABLE: u32 = 0x2;
+/// Matching rule resolving nested group membership on Active Directory.
+const AD_MATCHING_RULE_IN_CHAIN: &str = "1.2.840.113556.1.4.1941";
+/// First `gidNumber` handed out to `posixGroup`s created here.
+const POSIX_GROUP_MIN_GID: u32 = 10000;
+/// `pwdAccountLockedTime` locking an OpenLDAP account until an administrator unlocks it.
+const PPOLICY_PERMANENT_LOCK: &str = "000001010000Z";
+
+pub(crate) struct LdapDirectory<'r> {
+    pool: &'r LdapPool,
+    config: &'r AppConfig,
+    cache: &'r DirectoryCache,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LdapDirectory<'r> {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<LdapDirectory<'r>, Self::Error> {
+        let pool = request.rocket().state::<LdapPool>().unwrap();
+        let config = request.rocket().state::<AppConfig>().unwrap();
+        let cache = request.rocket().state::<DirectoryCache>().unwrap();
+        Outcome::Success(LdapDirectory::new(pool, config, cache))
+    }
+}
+
+/// Maps LDAP result codes to the HTTP status the failed operation should surface as.
+fn map_ldap_error(error: LdapError) -> Error {
+    match error {
+        LdapError::LdapResult { result } => match result.rc {
+            // noSuchObject
+            32 => Error::Http(Status::NotFound),
+            // insufficientAccessRights
+            50 => Error::Http(Status::Forbidden),
+            // noSuchAttribute, attributeOrValueExists, entryAlreadyExists
+            16 | 20 | 68 => Error::Http(Status::Conflict),
+            // constraintViolation, invalidAttributeSyntax, namingViolation, objectClassViolation
+            19 | 21 | 64 | 65 => Error::Http(Status::BadRequest),
+            // busy, unavailable
+            51 | 52 => Error::Http(Status::ServiceUnavailable),
+            _ => Error::Ldap(LdapError::LdapResult { result }),
+        },
+        error => Error::Ldap(error),
+    }
+}
+
+fn first_attr(entry: &SearchEntry, attr: &str) -> String {
+    entry
+        .attrs
+        .get(attr)
+        .and_then(|values| values.first())
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn add_attr(attrs: &mut HashMap<String, HashSet<String>>, attr: &str, value: &str) {
+    if !value.is_empty() {
+        attrs
+            .entry(attr.to_owned())
+            .or_default()
+            .insert(value.to_owned());
+    }
+}
+
+/// Encodes a password the way Active Directory expects it in `unicodePwd`: quoted UTF-16LE.
+fn ad_unicode_pwd(password: &str) -> Vec<u8> {
+    format!("\"{}\"", password)
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+/// Active Directory only accepts a user's own password change as a delete of the old value
+/// followed by an add of the new one.
+fn ad_password_change(current_password: &str, new_password: &str) -> Vec<Mod<Vec<u8>>> {
+    vec![
+        Mod::Delete(
+            b"unicodePwd".to_vec(),
+            HashSet::from([ad_unicode_pwd(current_password)]),
+        ),
+        Mod::Add(
+            b"unicodePwd".to_vec(),
+            HashSet::from([ad_unicode_pwd(new_password)]),
+        ),
+    ]
+}
+
+/// Sets or clears the disabled flag in a `userAccountControl` value, keeping all other flags.
+fn ad_account_control(flags: &str, disabled: bool) -> String {
+    let flags = flags.parse::<u32>().unwrap_or_default();
+    match disabled {
+        true => flags | AD_ACCOUNTDISABLE,
+        false => flags & !AD_ACCOUNTDISABLE,
+    }
+    .to_string()
+}
+
+impl<'r> LdapDirectory<'r> {
+    /// Directory for use outside of requests, e.g. in background jobs.
+    pub(crate) fn new(
+        pool: &'r LdapPool,
+        config: &'r AppConfig,
+        cache: &'r DirectoryCache,
+    ) -> Self {
+        LdapDirectory {
+            pool,
+            config,
+            cache,
+        }
+    }
+
+    fn cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.config.ldap_cache_ttl)
+    }
+
+    fn user_object_filter(&self) -> &'static str {
+        match self.config.ldap_profile {
+            LdapProfile::OpenLdap => "(objectClass=inetOrgPerson)",
+            LdapProfile::ActiveDirectory => "(&(objectCategory=person)(objectClass=user))",
+        }
+    }
+
+    fn group_object_class(&self) -> &'static str {
+        match (self.config.ldap_profile, self.config.ldap_group_schema) {
+            (LdapProfile::ActiveDirectory, _) => "group",
+            (LdapProfile::OpenLdap, LdapGroupSchema::GroupOfNames) => "groupOfNames",
+            (LdapProfile::OpenLdap, LdapGroupSchema::GroupOfUniqueNames) => "groupOfUniqueNames",
+            (LdapProfile::OpenLdap, LdapGroupSchema::PosixGroup) => "posixGroup",
+        }
+    }
+
+    fn group_member_attr(&self) -> &'static str {
+        match (self.config.ldap_profile, self.config.ldap_group_schema) {
+            (LdapProfile::ActiveDirectory, _) => "member",
+            (LdapProfile::OpenLdap, LdapGroupSchema::GroupOfNames) => "member",
+            (LdapProfile::OpenLdap, LdapGroupSchema::GroupOfUniqueNames) => "uniqueMember",
+            (LdapProfile::OpenLdap, LdapGroupSchema::PosixGroup) => "memberUid",
+        }
+    }
+
+    /// `posixGroup`s have no owner attribute, Active Directory allows a single owner.
+    fn group_owner_attr(&self) -> &'static str {
+        match self.config.ldap_profile {
+            LdapProfile::OpenLdap => "owner",
+            LdapProfile::ActiveDirectory => "managedBy",
+        }
+    }
+
+    fn group_attrs(&self) -> Vec<String> {
+        vec![
+            self.group_member_attr().to_owned(),
+            self.group_owner_attr().to_owned(),
+        ]
+    }
+
+    /// `posixGroup`s list usernames instead of DNs, which have to be translated in both
+    /// directions since `Group::members` always holds DNs.
+    fn group_members_are_usernames(&self) -> bool {
+        self.config.ldap_profile == LdapProfile::OpenLdap
+            && self.config.ldap_group_schema == LdapGroupSchema::PosixGroup
+    }
+
+    /// Maps usernames to user DNs, only needed for groups listing usernames.
+    async fn user_dns_by_username(&self) -> Result<Option<HashMap<String, String>>, Error> {
+        if !self.group_members_are_usernames() {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.search(
+                self.config.ldap_user_base_dn.clone(),
+                Scope::Subtree,
+                self.user_object_filter().to_owned(),
+                vec![self.config.ldap_username_attr().to_owned()],
+            )
+            .await?
+            .into_iter()
+            .map(|entry| {
+                (
+                    first_attr(&entry, self.config.ldap_username_attr()),
+                    entry.dn,
+                )
+            })
+            .collect(),
+        ))
+    }
+
+    /// Translates member DNs into the values stored in the group's member attribute.
+    async fn member_values(&self, members: Vec<String>) -> Result<HashSet<String>, Error> {
+        Ok(match self.user_dns_by_username().await? {
+            Some(user_dns) => user_dns
+                .into_iter()
+                .filter(|(_, dn)| members.contains(dn))
+                .map(|(username, _)| username)
+                .collect(),
+            None => members.into_iter().collect(),
+        })
+    }
+
+    fn group_from_entry(
+        &self,
+        mut entry: SearchEntry,
+        user_dns: Option<&HashMap<String, String>>,
+    ) -> Group {
+        let values = entry
+            .attrs
+            .remove(self.group_member_attr())
+            .unwrap_or_default();
+        Group {
+            owners: entry
+                .attrs
+                .remove(self.group_owner_attr())
+                .unwrap_or_default(),
+            members: match user_dns {
+                Some(user_dns) => values
+                    .iter()
+                    .filter_map(|username| user_dns.get(username).cloned())
+                    .collect(),
+                None => values,
+            },
+            dn: entry.dn,
+        }
+    }
+
+    /// Finds the groups containing `user_dn` and, transitively, the groups containing those.
+    async fn search_nested_groups(
+        &self,
+        username: &str,
+        user_dn: String,
+    ) -> Result<Vec<String>, Error> {
+        let mut groups = Vec::new();
+        let mut seen = HashSet::new();
+        let mut frontier = if self.group_members_are_usernames() {
+            vec![username.to_owned()]
+        } else {
+            vec![user_dn]
+        };
+        while !frontier.is_empty() {
+            let filter = format!(
+                "(&(objectClass={})(|{}))",
+                self.group_object_class(),
+                frontier
+                    .iter()
+                    .map(|value| format!("({}={})", self.group_member_attr(), ldap_escape(value)))
+                    .collect::<String>()
+            );
+            // Groups already seen are dropped, which ends the search on membership cycles.
+            frontier = self
+                .search(
+                    self.config.ldap_groups_base_dn.clone(),
+                    Scope::Subtree,
+                    filter,
+                    vec!["1.1".to_owned()],
+                )
+                .await?
+                .into_iter()
+                .map(|entry| entry.dn)
+                .filter(|dn| seen.insert(dn.clone()))
+                .collect();
+            groups.extend(frontier.iter().cloned());
+            if self.group_members_are_usernames() {
+                break;
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Follows the `memberOf` attributes maintained by the overlay, starting at `user_dn`.
+    async fn member_of_groups(&self, user_dn: String) -> Result<Vec<String>, Error> {
+        let mut groups = Vec::new();
+        let mut seen = HashSet::new();
+        let mut frontier = vec![user_dn];
+        while let Some(dn) = frontier.pop() {
+            let mut entry = match self
+                .search_one(
+                    dn,
+                    "(objectClass=*)".to_owned(),
+                    vec!["memberOf".to_owned()],
+                )
+                .await
+            {
+                Ok(entry) => entry,
+                // memberOf may still point to a group that has been deleted since.
+                Err(Error::Http(status)) if status == Status::NotFound => continue,
+                Err(error) => return Err(error),
+            };
+            for group_dn in entry.attrs.remove("memberOf").unwrap_or_default() {
+                if seen.insert(group_dn.clone()) {
+                    groups.push(group_dn.clone());
+                    frontier.push(group_dn);
+                }
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Removes a user about to be deleted from the members and owners of all groups. Without the
+    /// refint overlay, a user created later under the same name would get them back otherwise.
+    /// Groups that must not be empty keep the root DN as a placeholder member.
+    async fn leave_groups(&self, username: &str, user_dn: &str) -> Result<(), Error> {
+        let member = match self.group_members_are_usernames() {
+            true => username,
+            false => user_dn,
+        };
+        let entries = self
+            .search(
+                self.config.ldap_groups_base_dn.clone(),
+                Scope::Subtree,
+                format!(
+                    "(&(objectClass={})(|({}={})({}={})))",
+                    self.group_object_class(),
+                    self.group_member_attr(),
+                    ldap_escape(member),
+                    self.group_owner_attr(),
+                    ldap_escape(user_dn)
+                ),
+                self.group_attrs(),
+            )
+            .await?;
+        for mut entry in entries {
+            let mut changes = Vec::new();
+            let (removed, kept): (Vec<String>, Vec<String>) = entry
+                .attrs
+                .remove(self.group_member_attr())
+                .unwrap_or_default()
+                .into_iter()
+                .partition(|value| value.eq_ignore_ascii_case(member));
+            if !removed.is_empty() {
+                if kept.is_empty() && self.config.ldap_groups_require_members() {
+                    changes.push(Mod::Add(
+                        self.group_member_attr().to_owned(),
+                        HashSet::from([self.config.ldap_root_dn.clone()]),
+                    ));
+                }
+                changes.push(Mod::Delete(
+                    self.group_member_attr().to_owned(),
+                    removed.into_iter().collect(),
+                ));
+            }
+            let owners: HashSet<String> = entry
+                .attrs
+                .remove(self.group_owner_attr())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|owner| owner.eq_ignore_ascii_case(user_dn))
+                .collect();
+            if !owners.is_empty() {
+                changes.push(Mod::Delete(self.group_owner_attr().to_owned(), owners));
+            }
+            if !changes.is_empty() {
+                self.modify(entry.dn, changes).await?;
+            }
+        }
+        self.cache.invalidate_groups();
+        Ok(())
+    }
+
+    /// Picks the `gidNumber` for a new `posixGroup` after the highest one in use.
+    async fn next_gid_number(&self) -> Result<u32, Error> {
+        Ok(self
+            .search(
+                self.config.ldap_groups_base_dn.clone(),
+                Scope::Subtree,
+                "(objectClass=posixGroup)".to_owned(),
+                vec!["gidNumber".to_owned()],
+            )
+            .await?
+            .iter()
+            .filter_map(|entry| first_attr(entry, "gidNumber").parse::<u32>().ok())
+            .map(|gid_number| gid_number + 1)
+            .fold(POSIX_GROUP_MIN_GID, u32::max))
+    }
+
+    /// The attribute marking an account as disabled: the ppolicy lock on OpenLDAP and the
+    /// account control flags on Active Directory.
+    fn disabled_attr(&self) -> &'static str {
+        match self.config.ldap_profile {
+            LdapProfile::OpenLdap => "pwdAccountLockedTime",
+            LdapProfile::ActiveDirectory => "userAccountControl",
+        }
+    }
+
+    fn is_disabled(&self, entry: &SearchEntry) -> bool {
+        let value = first_attr(entry, self.disabled_attr());
+        match self.config.ldap_profile {
+            LdapProfile::OpenLdap => !value.is_empty(),
+            LdapProfile::ActiveDirectory => value
+                .parse::<u32>()
+                .map_or(false, |flags| flags & AD_ACCOUNTDISABLE != 0),
+        }
+    }
+
+    fn user_attrs(&self) -> Vec<String> {
+        vec![
+            self.config.ldap_username_attr().to_owned(),
+            self.config.ldap_display_name_attr().to_owned(),
+            self.config.ldap_first_name_attr().to_owned(),
+            self.config.ldap_last_name_attr().to_owned(),
+            self.config.ldap_email_attr().to_owned(),
+            self.disabled_attr().to_owned(),
+        ]
+    }
+
+    fn user_from_entry(&self, entry: SearchEntry) -> User {
+        User {
+            username: first_attr(&entry, self.config.ldap_username_attr()),
+            name: first_attr(&entry, self.config.ldap_display_name_attr()),
+            first_name: first_attr(&entry, self.config.ldap_first_name_attr()),
+            last_name: first_attr(&entry, self.config.ldap_last_name_attr()),
+            email: first_attr(&entry, self.config.ldap_email_attr()),
+            disabled: self.is_disabled(&entry),
+            dn: entry.dn,
+        }
+    }
+
+    /// Builds the DN a user binds as when no search filter is configured.
+    fn direct_user_dn(&self, username: &str) -> String {
+        format!(
+            "{}={},{}",
+            self.config.ldap_username_attr(),
+            dn_escape(username),
+            self.config.ldap_user_base_dn
+        )
+    }
+
+    /// DN of a user created here. Active Directory names user entries by their common name,
+    /// which is set to the username as well.
+    fn new_user_dn(&self, username: &str) -> String {
+        match self.config.ldap_profile {
+            LdapProfile::OpenLdap => self.direct_user_dn(username),
+            LdapProfile::ActiveDirectory => format!(
+                "cn={},{}",
+                dn_escape(username),
+                self.config.ldap_user_base_dn
+            ),
+        }
+    }
+
+    /// Looks up the entry of `username`, which is always the value of the username attribute
+    /// and never an alternative login like the e-mail address.
+    async fn find_user(&self, username: &str, attrs: Vec<String>) -> Result<SearchEntry, Error> {
+        if self.config.ldap_user_search_filter().is_none() {
+            return self
+                .search_one(
+                    self.direct_user_dn(username),
+                    self.user_object_filter().to_owned(),
+                    attrs,
+                )
+                .await;
+        }
+        let mut entries = self
+            .search(
+                self.config.ldap_user_base_dn.clone(),
+                Scope::Subtree,
+                format!(
+                    "(&{}({}={}))",
+                    self.user_object_filter(),
+                    self.config.ldap_username_attr(),
+                    ldap_escape(username)
+                ),
+                attrs,
+            )
+            .await?;
+        match entries.len() {
+            1 => Ok(entries.remove(0)),
+            _ => Err(Error::Http(Status::NotFound)),
+        }
+    }
+
+    async fn user_dn(&self, username: &str) -> Result<String, Error> {
+        match self.config.ldap_user_search_filter() {
+            Some(_) => Ok(self.find_user(username, vec!["1.1".to_owned()]).await?.dn),
+            None => Ok(self.direct_user_dn(username)),
+        }
+    }
+
+    /// The configured user search filter with every `{0}` replaced by the escaped login,
+    /// restricted to user objects.
+    fn login_filter(&self, search_filter: &str, login: &str) -> String {
+        format!(
+            "(&{}{})",
+            self.user_object_filter(),
+            search_filter.replace("{0}", &ldap_escape(login))
+        )
+    }
+
+    /// Resolves a login to the DN to bind as and the canonical username. Logins matching no
+    /// or more than one entry, as well as disabled accounts, resolve to nothing.
+    async fn resolve_login(&self, login: &str) -> Result<Option<(String, String)>, Error> {
+        let attrs = vec![
+            self.config.ldap_username_attr().to_owned(),
+            self.disabled_attr().to_owned(),
+        ];
+        let entry = match self.config.ldap_user_search_filter() {
+            Some(search_filter) => {
+                let mut entries = self
+                    .search(
+                        self.config.ldap_user_base_dn.clone(),
+                        Scope::Subtree,
+                        self.login_filter(search_filter, login),
+                        attrs,
+                    )
+                    .await?;
+                if entries.len() != 1 {
+                    return Ok(None);
+                }
+                entries.remove(0)
+            }
+            None => match self.find_user(login, attrs).await {
+                Ok(entry) => entry,
+                Err(Error::Http(status)) if status == Status::NotFound => return Ok(None),
+                Err(error) => return Err(error),
+            },
+        };
+        if self.is_disabled(&entry) {
+            return Ok(None);
+        }
+        let username = first_attr(&entry, self.config.ldap_username_attr());
+        Ok(Some((entry.dn, username)))
+    }
+
+    fn group_dn(&self, name: &str) -> String {
+        format!("cn={},{}", dn_escape(name), self.config.ldap_groups_base_dn)
+    }
+
+    async fn search(
+        &self,
+        base: String,
+        scope: Scope,
+        filter: String,
+        attrs: Vec<String>,
+    ) -> Result<Vec<SearchEntry>, Error> {
+        let mut pooled = self.pool.reader().await?;
+        let result = self
+            .paged_search(&mut pooled.ldap, &base, scope, &filter, attrs)
+            .await;
+        result.map_err(|e| map_ldap_error(pooled.check_error(e)))
+    }
+
+    /// Fetches the results page by page with the Simple Paged Results control, which keeps large
+    /// searches below the server's size limit.
+    async fn paged_search(
+        &self,
+        ldap: &mut Ldap,
+        base: &str,
+        scope: Scope,
+        filter: &str,
+        attrs: Vec<String>,
+    ) -> Result<Vec<SearchEntry>, LdapError> {
+        let adapters: Vec<Box<dyn Adapter<_>>> = vec![
+            Box::new(EntriesOnly::new()),
+            Box::new(PagedResults::new(self.config.ldap_page_size)),
+        ];
+        let mut stream = ldap
+            .streaming_search_with(adapters, base, scope, filter, attrs)
+            .await?;
+        let mut entries = Vec::new();
+        while let Some(entry) = stream.next().await? {
+            entries.push(SearchEntry::construct(entry));
+        }
+        stream.finish().await.success()?;
+        Ok(entries)
+    }
+
+    async fn search_one(
+        &self,
+        dn: String,
+        filter: String,
+        attrs: Vec<String>,
+    ) -> Result<SearchEntry, Error> {
+        self.search(dn, Scope::Base, filter, attrs)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(Error::Http(Status::NotFound))
+    }
+
+    async fn modify(&self, dn: String, changes: Vec<Mod<String>>) -> Result<(), Error> {
+        let mut pooled = self.pool.writer().await?;
+        let result = pooled.ldap.modify(&dn, changes).await;
+        result
+            .and_then(|result| result.success())
+            .map_err(|e| map_ldap_error(pooled.check_error(e)))?;
+        Ok(())
+    }
+
+    async fn delete(&self, dn: String) -> Result<(), Error> {
+        let mut pooled = self.pool.writer().await?;
+        let result = pooled.ldap.delete(&dn).await;
+        result
+            .and_then(|result| result.success())
+            .map_err(|e| map_ldap_error(pooled.check_error(e)))?;
+        Ok(())
+    }
+
+    async fn add(&self, dn: String, attrs: Vec<(String, HashSet<String>)>) -> Result<(), Error> {
+        let mut pooled = self.pool.writer().await?;
+        let result = pooled.ldap.add(&dn, attrs).await;
+        result
+            .and_then(|result| result.success())
+            .map_err(|e| map_ldap_error(pooled.check_error(e)))?;
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl Directory for LdapDirectory<'_> {
+    async fn authenticate(&self, login: &str, password: &str) -> Result<Option<String>, Error> {
+        // An empty password would be an unauthenticated bind, which most servers accept.
+        if password.is_empty() {
+            return Ok(None);
+        }
+        let (user_dn, username) = match self.resolve_login(login).await? {
+            Some(resolved) => resolved,
+            None => return Ok(None),
+        };
+        let mut ldap = self.pool.user_connection(false).await?;
+        let bind = ldap
+            .simple_bind(&user_dn, password)
+            .await
+            .map_err(map_ldap_error)?;
+        let _ = ldap.unbind().await;
+        Ok(bind.success().ok().map(|_| username))
+    }
+
+    async fn change_password(
+        &self,
+        username: &str,
+        current_password: &str,
+        new_password: &str,
+    ) -> Result<bool, Error> {
+        if current_password.is_empty() {
+            return Ok(false);
+        }
+        let user_dn = self.user_dn(username).await?;
+        // Both servers only apply their password policy to changes made by the user itself,
+        // so the change runs bound as the user.
+        let mut ldap = self.pool.user_connection(true).await?;
+        if ldap
+            .simple_bind(&user_dn, current_password)
+            .await
+            .map_err(map_ldap_error)?
+            .success()
+            .is_err()
+        {
+            let _ = ldap.unbind().await;
+            return Ok(false);
+        }
+        let result = match self.config.ldap_profile {
+            LdapProfile::OpenLdap => ldap
+                .extended(PasswordModify {
+                    user_id: Some(user_dn.as_str()),
+                    old_pass: Some(current_password),
+                    new_pass: Some(new_password),
+                })
+                .await
+                .and_then(|result| result.success())
+                .map(|_| true),
+            LdapProfile::ActiveDirectory => ldap
+                .modify(&user_dn, ad_password_change(current_password, new_password))
+                .await
+                .and_then(|result| result.success())
+                .map(|_| true),
+        };
+        let _ = ldap.unbind().await;
+        result.map_err(map_ldap_error)
+    }
+
+    async fn get_user(&self, username: &str) -> Result<User, Error> {
+        if let Some(user) = self.cache.users.get(&username.to_owned(), self.cache_ttl()) {
+            return Ok(user);
+        }
+        let entry = self.find_user(username, self.user_attrs()).await?;
+        let user = self.user_from_entry(entry);
+        self.cache
+            .users
+            .insert(username.to_owned(), user.clone(), self.cache_ttl());
+        Ok(user)
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, Error> {
+        if let Some(users) = self.cache.user_list.get(&(), self.cache_ttl()) {
+            return Ok(users);
+        }
+        let mut users: Vec<User> = self
+            .search(
+                self.config.ldap_user_base_dn.clone(),
+                Scope::Subtree,
+                self.user_object_filter().to_owned(),
+                self.user_attrs(),
+            )
+            .await?
+            .into_iter()
+            .map(|entry| self.user_from_entry(entry))
+            .collect();
+        users.sort_by(|a, b| a.dn.cmp(&b.dn));
+        self.cache
+            .user_list
+            .insert((), users.clone(), self.cache_ttl());
+        Ok(users)
+    }
+
+    async fn search_users(
+        &self,
+        query: &str,
+        page: usize,
+        per_page: usize,
+    ) -> Result<Page<User>, Error> {
+        if query.is_empty() {
+            return Ok(Page::of(self.list_users().await?, page, per_page));
+        }
+        let query = ldap_escape(query);
+        let mut users: Vec<User> = self
+            .search(
+                self.config.ldap_user_base_dn.clone(),
+                Scope::Subtree,
+                format!(
+                    "(&{}(|({}=*{query}*)({}=*{query}*)({}=*{query}*)))",
+                    self.user_object_filter(),
+                    self.config.ldap_username_attr(),
+                    self.config.ldap_display_name_attr(),
+                    self.config.ldap_email_attr(),
+                    query = query
+                ),
+                self.user_attrs(),
+            )
+            .await?
+            .into_iter()
+            .map(|entry| self.user_from_entry(entry))
+            .collect();
+        users.sort_by(|a, b| a.dn.cmp(&b.dn));
+        Ok(Page::of(users, page, per_page))
+    }
+
+    async fn update_user_name(
+        &self,
+        username: &str,
+        display_name: &str,
+        first_name: &str,
+        last_name: &str,
+    ) -> Result<(), Error> {
+        let user_dn = self.user_dn(username).await?;
+        self.modify(
+            user_dn,
+            vec![
+                Mod::Replace(
+                    self.config.ldap_display_name_attr().to_owned(),
+                    HashSet::from([display_name.to_owned()]),
+                ),
+                Mod::Replace(
+                    self.config.ldap_first_name_attr().to_owned(),
+                    HashSet::from([first_name.to_owned()]),
+                ),
+                Mod::Replace(
+                    self.config.ldap_last_name_attr().to_owned(),
+                    HashSet::from([last_name.to_owned()]),
+                ),
+            ],
+        )
+        .await?;
+        self.cache.invalidate_users();
+        Ok(())
+    }
+
+    async fn update_user_email(&self, username: &str, email: &str) -> Result<(), Error> {
+        let user_dn = self.user_dn(username).await?;
+        self.modify(
+            user_dn,
+            vec![Mod::Replace(
+                self.config.ldap_email_attr().to_owned(),
+                HashSet::from([email.to_owned()]),
+            )],
+        )
+        .await?;
+        self.cache.invalidate_users();
+        Ok(())
+    }
+
+    async fn create_user(&self, user: NewUser) -> Result<User, Error> {
+        let dn = self.new_user_dn(&user.username);
+        let mut attrs = HashMap::new();
+        add_attr(&mut attrs, self.config.ldap_username_attr(), &user.username);
+        add_attr(&mut attrs, self.config.ldap_display_name_attr(), &user.name);
+        add_attr(
+            &mut attrs,
+            self.config.ldap_first_name_attr(),
+            &user.first_name,
+        );
+        add_attr(
+            &mut attrs,
+            self.config.ldap_last_name_attr(),
+            &user.last_name,
+        );
+        add_attr(&mut attrs, self.config.ldap_email_attr(), &user.email);
+        let object_classes = match self.config.ldap_profile {
+            LdapProfile::OpenLdap => ["top", "person", "organizationalPerson", "inetOrgPerson"],
+            // Without a password Active Directory creates the account disabled.
+            LdapProfile::ActiveDirectory => ["top", "person", "organizationalPerson", "user"],
+        };
+        attrs.insert(
+            "objectClass".to_owned(),
+            object_classes
+                .iter()
+                .map(|class| class.to_string())
+                .collect(),
+        );
+        // person requires a common name and a surname.
+        if !attrs.contains_key("cn") {
+            add_attr(&mut attrs, "cn", &user.username);
+        }
+        if !attrs.contains_key("sn") {
+            add_attr(&mut attrs, "sn", &user.username);
+        }
+        self.add(dn, attrs.into_iter().collect()).await?;
+        self.cache.invalidate_users();
+        self.get_user(&user.username).await
+    }
+
+    async fn delete_user(&self, username: &str) -> Result<(), Error> {
+        let user_dn = self.user_dn(username).await?;
+        // Active Directory drops the memberships of deleted entries itself.
+        if self.config.ldap_profile == LdapProfile::OpenLdap {
+            self.leave_groups(username, &user_dn).await?;
+        }
+        self.delete(user_dn).await?;
+        self.cache.invalidate_users();
+        self.cache.invalidate_groups();
+        Ok(())
+    }
+
+    async fn set_user_disabled(&self, username: &str, disabled: bool) -> Result<(), Error> {
+        let dn = self.user_dn(username).await?;
+        let values = match self.config.ldap_profile {
+            // Replacing with no values removes the lock, whether there is one or not.
+            LdapProfile::OpenLdap if disabled => HashSet::from([PPOLICY_PERMANENT_LOCK.to_owned()]),
+            LdapProfile::OpenLdap => HashSet::new(),
+            LdapProfile::ActiveDirectory => {
+                let entry = self
+                    .search_one(
+                        dn.clone(),
+                        "(objectClass=*)".to_owned(),
+                        vec![self.disabled_attr().to_owned()],
+                    )
+                    .await?;
+                let flags = first_attr(&entry, self.disabled_attr());
+                HashSet::from([ad_account_control(&flags, disabled)])
+            }
+        };
+        self.modify(
+            dn,
+            vec![Mod::Replace(self.disabled_attr().to_owned(), values)],
+        )
+        .await?;
+        self.cache.invalidate_users();
+        Ok(())
+    }
+
+    async fn get_user_groups(&self, username: &str) -> Result<Vec<String>, Error> {
+        if let Some(groups) = self
+            .cache
+            .user_groups
+            .get(&username.to_owned(), self.cache_ttl())
+        {
+            return Ok(groups);
+        }
+        let user_dn = self.user_dn(username).await?;
+        let groups = match self.config.ldap_profile {
+            LdapProfile::OpenLdap if self.config.ldap_member_of => {
+                self.member_of_groups(user_dn).await
+            }
+            LdapProfile::OpenLdap => self.search_nested_groups(username, user_dn).await,
+            // The matching rule resolves nested memberships on the server.
+            LdapProfile::ActiveDirectory => Ok(self
+                .search(
+                    self.config.ldap_groups_base_dn.clone(),
+                    Scope::Subtree,
+                    format!(
+                        "(&(objectClass=group)(member:{}:={}))",
+                        AD_MATCHING_RULE_IN_CHAIN,
+                        ldap_escape(user_dn)
+                    ),
+                    vec!["1.1".to_owned()],
+                )
+                .await?
+                .into_iter()
+                .map(|entry| entry.dn)
+                .collect()),
+        }?;
+        self.cache
+            .user_groups
+            .insert(username.to_owned(), groups.clone(), self.cache_ttl());
+        Ok(groups)
+    }
+
+    async fn get_group(&self, dn: &str) -> Result<Group, Error> {
+        if let Some(group) = self.cache.groups.get(&dn.to_owned(), self.cache_ttl()) {
+            return Ok(group);
+        }
+        let entry = self
+            .search_one(
+                dn.to_owned(),
+                format!("(objectClass={})", self.group_object_class()),
+                self.group_attrs(),
+            )
+            .await?;
+        let user_dns = self.user_dns_by_username().await?;
+        let group = self.group_from_entry(entry, user_dns.as_ref());
+        self.cache
+            .groups
+            .insert(dn.to_owned(), group.clone(), self.cache_ttl());
+        Ok(group)
+    }
+
+    async fn list_groups(&self) -> Result<Vec<Group>, Error> {
+        if let Some(groups) = self.cache.group_list.get(&(), self.cache_ttl()) {
+            return Ok(groups);
+        }
+        let entries = self
+            .search(
+                self.config.ldap_groups_base_dn.clone(),
+                Scope::Subtree,
+                format!("(objectClass={})", self.group_object_class()),
+                self.group_attrs(),
+            )
+            .await?;
+        let user_dns = self.user_dns_by_username().await?;
+        let groups: Vec<Group> = entries
+            .into_iter()
+            .map(|entry| self.group_from_entry(entry, user_dns.as_ref()))
+            .collect();
+        self.cache
+            .group_list
+            .insert((), groups.clone(), self.cache_ttl());
+        Ok(groups)
+    }
+
+    async fn search_groups(
+        &self,
+        query: &str,
+        page: usize,
+        per_page: usize,
+    ) -> Result<Page<Group>, Error> {
+        if query.is_empty() {
+            return Ok(Page::of(self.list_groups().await?, page, per_page));
+        }
+        let entries = self
+            .search(
+                self.config.ldap_groups_base_dn.clone(),
+                Scope::Subtree,
+                format!(
+                    "(&(objectClass={})(cn=*{}*))",
+                    self.group_object_class(),
+                    ldap_escape(query)
+                ),
+                self.group_attrs(),
+            )
+            .await?;
+        let user_dns = self.user_dns_by_username().await?;
+        let mut groups: Vec<Group> = entries
+            .into_iter()
+            .map(|entry| self.group_from_entry(entry, user_dns.as_ref()))
+            .collect();
+        groups.sort_by(|a, b| a.dn.cmp(&b.dn));
+        Ok(Page::of(groups, page, per_page))
+    }
+
+    async fn get_owned_groups(&self, username: &str) -> Result<Vec<String>, Error> {
+        let user_dn = self.user_dn(username).await?;
+        Ok(self
+            .search(
+                self.config.ldap_groups_base_dn.clone(),
+                Scope::Subtree,
+                format!(
+                    "(&(objectClass={})({}={}))",
+                    self.group_object_class(),
+                    self.group_owner_attr(),
+                    ldap_escape(user_dn)
+                ),
+                vec!["1.1".to_owned()],
+            )
+            .await?
+            .into_iter()
+            .map(|entry| entry.dn)
+            .collect())
+    }
+
+    async fn create_group(&self, name: &str, members: Vec<String>) -> Result<Group, Error> {
+        let dn = self.group_dn(name);
+        let mut attrs = vec![
+            (
+                "objectClass".to_owned(),
+                HashSet::from([self.group_object_class().to_owned(), "top".to_owned()]),
+            ),
+            ("cn".to_owned(), HashSet::from([name.to_owned()])),
+        ];
+        if self.config.ldap_profile == LdapProfile::ActiveDirectory {
+            attrs.push((
+                "sAMAccountName".to_owned(),
+                HashSet::from([name.to_owned()]),
+            ));
+        }
+        if self.group_members_are_usernames() {
+            attrs.push((
+                "gidNumber".to_owned(),
+                HashSet::from([self.next_gid_number().await?.to_string()]),
+            ));
+        }
+        let member_values = self.member_values(members.clone()).await?;
+        if !member_values.is_empty() {
+            attrs.push((self.group_member_attr().to_owned(), member_values));
+        }
+        self.add(dn.clone(), attrs).await?;
+        self.cache.invalidate_groups();
+        Ok(Group {
+            dn,
+            members,
+            owners: Vec::new(),
+        })
+    }
+
+    async fn delete_group(&self, dn: &str) -> Result<(), Error> {
+        self.delete(dn.to_owned()).await?;
+        self.cache.invalidate_groups();
+        Ok(())
+    }
+
+    async fn update_group_members(
+        &self,
+        dn: &str,
+        added: Vec<String>,
+        removed: Vec<String>,
+    ) -> Result<(), Error> {
+        // An empty value set would delete the attribute as a whole.
+        let mut changes = Vec::new();
+        let added = self.member_values(added).await?;
+        if !added.is_empty() {
+            changes.push(Mod::Add(self.group_member_attr().to_owned(), added));
+        }
+        let removed = self.member_values(removed).await?;
+        if !removed.is_empty() {
+            changes.push(Mod::Delete(
+                self.group_member_attr().to_owned(),
+                removed.into_iter().collect(),
+            ));
+        }
+        if changes.is_empty() {
+            return Ok(());
+        }
+        self.modify(dn.to_owned(), changes).await?;
+        self.cache.invalidate_groups();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::directory::fake_server::FakeLdapServer;
+
+    /// Runs `test` against a directory for `config`. Nothing here connects to a server.
+    fn with_directory(config: serde_json::Value, test: impl FnOnce(&LdapDirectory)) {
+        let pool = LdapPool::new(
+            serde_json::from_value(json!({ "urls": ["ldap://ldap.example.com"] })).unwrap(),
+        )
+        .unwrap();
+        let config = AppConfig::for_tests(config);
+        let cache = DirectoryCache::default();
+        test(&LdapDirectory::new(&pool, &config, &cache));
+    }
+
+    fn entry(dn: &str, attrs: &[(&str, &str)]) -> SearchEntry {
+        SearchEntry {
+            dn: dn.to_owned(),
+            attrs: attrs
+                .iter()
+                .map(|(attr, value)| (attr.to_string(), vec![value.to_string()]))
+                .collect(),
+            bin_attrs: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn ad_unicode_pwd_is_quoted_utf16le() {
+        assert_eq!(
+            ad_unicode_pwd("pä"),
+            vec![b'"', 0, b'p', 0, 0xe4, 0, b'"', 0]
+        );
+    }
+
+    #[test]
+    fn ad_password_change_deletes_the_old_and_adds_the_new_password() {
+        assert_eq!(
+            ad_password_change("old", "new"),
+            vec![
+                Mod::Delete(
+                    b"unicodePwd".to_vec(),
+                    HashSet::from([ad_unicode_pwd("old")])
+                ),
+                Mod::Add(
+                    b"unicodePwd".to_vec(),
+                    HashSet::from([ad_unicode_pwd("new")])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn ad_account_control_keeps_other_flags() {
+        // NORMAL_ACCOUNT | DONT_EXPIRE_PASSWORD
+        assert_eq!(ad_account_control("66048", true), "66050");
+        assert_eq!(ad_account_control("66050", false), "66048");
+        assert_eq!(ad_account_control("66050", true), "66050");
+        assert_eq!(ad_account_control("", true), "2");
+    }
+
+    #[test]
+    fn ad_accounts_are_disabled_by_flag() {
+        with_directory(json!({ "ldap_profile": "active_directory" }), |directory| {
+            let user = directory.user_from_entry(entry(
+                "cn=jdoe,ou=users,dc=example,dc=com",
+                &[
+                    ("sAMAccountName", "jdoe"),
+                    ("mail", "jdoe@example.com"),
+                    ("userAccountControl", "514"),
+                ],
+            ));
+            assert_eq!(user.username, "jdoe");
+            assert_eq!(user.email, "jdoe@example.com");
+            assert!(user.disabled);
+            assert!(!directory.is_disabled(&entry("", &[("userAccountControl", "512")])));
+            assert!(!directory.is_disabled(&entry("", &[])));
+        });
+    }
+
+    #[test]
+    fn openldap_accounts_are_disabled_by_lock() {
+        with_directory(json!({}), |directory| {
+            assert!(directory.is_disabled(&entry(
+                "",
+                &[("pwdAccountLockedTime", PPOLICY_PERMANENT_LOCK)]
+            )));
+            assert!(!directory.is_disabled(&entry("", &[("userAccountControl", "514")])));
+        });
+    }
+
+    #[test]
+    fn login_filter_escapes_every_placeholder() {
+        with_directory(json!({ "ldap_profile": "active_directory" }), |directory| {
+            assert_eq!(
+                directory.login_filter(
+                    directory.config.ldap_user_search_filter().unwrap(),
+                    "*)(cn=*"
+                ),
+                "(&(&(objectCategory=person)(objectClass=user))\
+                 (|(sAMAccountName=\\2a\\29\\28cn=\\2a)(userPrincipalName=\\2a\\29\\28cn=\\2a)))"
+            );
+        });
+    }
+
+    #[test]
+    fn user_dns_escape_the_username() {
+        with_directory(json!({}), |directory| {
+            assert_eq!(
+                directory.direct_user_dn("doe, j"),
+                "uid=doe\\2c j,ou=users,dc=example,dc=com"
+            );
+        });
+        with_directory(json!({ "ldap_profile": "active_directory" }), |directory| {
+            assert_eq!(
+                directory.new_user_dn("doe, j"),
+                "cn=doe\\2c j,ou=users,dc=example,dc=com"
+            );
+        });
+    }
+
+    /// Adds an Active Directory account, named by its username, with the given
+    /// `userAccountControl` flags.
+    fn with_ad_user(
+        server: FakeLdapServer,
+        username: &str,
+        password: &str,
+        flags: &str,
+    ) -> FakeLdapServer {
+        let dn = format!("cn={},ou=users,dc=example,dc=com", username);
+        server
+            .with_entry(
+                &dn,
+                &[
+                    (
+                        "objectClass",
+                        &["top", "person", "organizationalPerson", "user"],
+                    ),
+                    ("objectCategory", &["person"]),
+                    ("sAMAccountName", &[username]),
+                    ("userPrincipalName", &[&format!("{}@example.com", username)]),
+                    ("userAccountControl", &[flags]),
+                ],
+            )
+            .with_password(&dn, password)
+    }
+
+    fn with_openldap_user(server: FakeLdapServer, username: &str) -> FakeLdapServer {
+        server.with_entry(
+            &format!("uid={},ou=users,dc=example,dc=com", username),
+            &[
+                ("objectClass", &["top", "person", "inetOrgPerson"]),
+                ("uid", &[username]),
+                ("cn", &[username]),
+                ("sn", &[username]),
+            ],
+        )
+    }
+
+    #[rocket::async_test]
+    async fn ad_logins_bind_as_the_account_found() {
+        let server = with_ad_user(
+            FakeLdapServer::active_directory(),
+            "jdoe",
+            "secret",
+            "66048",
+        );
+        // ACCOUNTDISABLE
+        let server = with_ad_user(server, "mmuster", "secret", "66050");
+        let pool = server.start().await;
+        let config = AppConfig::for_tests(json!({ "ldap_profile": "active_directory" }));
+        let cache = DirectoryCache::default();
+        let directory = LdapDirectory::new(&pool, &config, &cache);
+
+        for login in ["jdoe", "JDoe@example.com"] {
+            assert_eq!(
+                directory.authenticate(login, "secret").await.unwrap(),
+                Some("jdoe".to_owned())
+            );
+        }
+        assert_eq!(directory.authenticate("jdoe", "wrong").await.unwrap(), None);
+        assert_eq!(directory.authenticate("jdoe", "").await.unwrap(), None);
+        assert_eq!(directory.authenticate("*", "secret").await.unwrap(), None);
+        assert_eq!(
+            directory.authenticate("mmuster", "secret").await.unwrap(),
+            None
+        );
+    }
+
+    #[rocket::async_test]
+    async fn ad_password_changes_are_made_as_the_user() {
+        let server = with_ad_user(FakeLdapServer::active_directory(), "jdoe", "old", "66048");
+        let pool = server.start().await;
+        let config = AppConfig::for_tests(json!({ "ldap_profile": "active_directory" }));
+        let cache = DirectoryCache::default();
+        let directory = LdapDirectory::new(&pool, &config, &cache);
+
+        assert!(!directory
+            .change_password("jdoe", "wrong", "new")
+            .await
+            .unwrap());
+        assert!(directory
+            .change_password("jdoe", "old", "new")
+            .await
+            .unwrap());
+        assert_eq!(directory.authenticate("jdoe", "old").await.unwrap(), None);
+        assert_eq!(
+            directory.authenticate("jdoe", "new").await.unwrap(),
+            Some("jdoe".to_owned())
+        );
+    }
+
+    #[rocket::async_test]
+    async fn ad_accounts_are_disabled_keeping_their_other_flags() {
+        let dn = "cn=jdoe,ou=users,dc=example,dc=com";
+        // NORMAL_ACCOUNT | DONT_EXPIRE_PASSWORD
+        let server = with_ad_user(
+            FakeLdapServer::active_directory(),
+            "jdoe",
+            "secret",
+            "66048",
+        );
+        let pool = server.start().await;
+        let config = AppConfig::for_tests(json!({ "ldap_profile": "active_directory" }));
+        let cache = DirectoryCache::default();
+        let directory = LdapDirectory::new(&pool, &config, &cache);
+
+        directory.set_user_disabled("jdoe", true).await.unwrap();
+        assert_eq!(
+            server.values(dn, "userAccountControl"),
+            Some(vec!["66050".to_owned()])
+        );
+        assert!(directory.get_user("jdoe").await.unwrap().disabled);
+        assert_eq!(
+            directory.authenticate("jdoe", "secret").await.unwrap(),
+            None
+        );
+
+        directory.set_user_disabled("jdoe", false).await.unwrap();
+        assert_eq!(
+            server.values(dn, "userAccountControl"),
+            Some(vec!["66048".to_owned()])
+        );
+        assert!(!directory.get_user("jdoe").await.unwrap().disabled);
+    }
+
+    #[rocket::async_test]
+    async fn ad_group_memberships_include_nested_groups() {
+        let user = "cn=jdoe,ou=users,dc=example,dc=com";
+        let (staff, admins, other) = (
+            "cn=staff,ou=groups,dc=example,dc=com",
+            "cn=admins,ou=groups,dc=example,dc=com",
+            "cn=other,ou=groups,dc=example,dc=com",
+        );
+        let server = with_ad_user(
+            FakeLdapServer::active_directory(),
+            "jdoe",
+            "secret",
+            "66048",
+        )
+        .with_entry(staff, &[("objectClass", &["group"]), ("member", &[user])])
+        .with_entry(admins, &[("objectClass", &["group"]), ("member", &[staff])])
+        .with_entry(other, &[("objectClass", &["group"]), ("member", &[admins])])
+        .with_entry(
+            "cn=unrelated,ou=groups,dc=example,dc=com",
+            &[
+                ("objectClass", &["group"]),
+                ("member", &["cn=someone,ou=users,dc=example,dc=com"]),
+            ],
+        );
+        let pool = server.start().await;
+        // Small pages, so the results span several.
+        let config = AppConfig::for_tests(json!({
+            "ldap_profile": "active_directory",
+            "ldap_page_size": 1,
+        }));
+        let cache = DirectoryCache::default();
+        let directory = LdapDirectory::new(&pool, &config, &cache);
+
+        let mut groups = directory.get_user_groups("jdoe").await.unwrap();
+        groups.sort();
+        assert_eq!(groups, vec![admins, other, staff]);
+    }
+
+    #[rocket::async_test]
+    async fn deleted_users_leave_their_groups() {
+        let (alice, bob) = (
+            "uid=alice,ou=users,dc=example,dc=com",
+            "uid=bob,ou=users,dc=example,dc=com",
+        );
+        let (staff, solo) = (
+            "cn=staff,ou=groups,dc=example,dc=com",
+            "cn=solo,ou=groups,dc=example,dc=com",
+        );
+        let server = with_openldap_user(FakeLdapServer::open_ldap(), "alice");
+        let server = with_openldap_user(server, "bob")
+            .with_entry(
+                staff,
+                &[
+                    ("objectClass", &["groupOfNames"]),
+                    // Stored with a different case than the user's DN.
+                    ("member", &["UID=alice,ou=users,dc=example,dc=com", bob]),
+                    ("owner", &[alice]),
+                ],
+            )
+            .with_entry(
+                solo,
+                &[("objectClass", &["groupOfNames"]), ("member", &[alice])],
+            );
+        let pool = server.start().await;
+        let config = AppConfig::for_tests(json!({}));
+        let cache = DirectoryCache::default();
+        let directory = LdapDirectory::new(&pool, &config, &cache);
+        assert_eq!(directory.get_user_groups("alice").await.unwrap().len(), 2);
+
+        directory.delete_user("alice").await.unwrap();
+        assert_eq!(server.values(alice, "uid"), None);
+        assert_eq!(server.values(staff, "member"), Some(vec![bob.to_owned()]));
+        assert_eq!(server.values(staff, "owner"), Some(Vec::new()));
+        assert_eq!(
+            server.values(solo, "member"),
+            Some(vec![config.ldap_root_dn.clone()])
+        );
+        assert_eq!(
+            directory.get_group(solo).await.unwrap().members,
+            vec![config.ldap_root_dn.clone()]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn deleted_users_leave_their_posix_groups() {
+        let staff = "cn=staff,ou=groups,dc=example,dc=com";
+        let server = with_openldap_user(FakeLdapServer::open_ldap(), "alice").with_entry(
+            staff,
+            &[
+                ("objectClass", &["posixGroup"]),
+                ("gidNumber", &["10000"]),
+                ("memberUid", &["alice"]),
+            ],
+        );
+        let pool = server.start().await;
+        let config = AppConfig::for_tests(json!({ "ldap_group_schema": "posix_group" }));
+        let cache = DirectoryCache::default();
+        let directory = LdapDirectory::new(&pool, &config, &cache);
+
+        directory.delete_user("alice").await.unwrap();
+        assert_eq!(server.values(staff, "memberUid"), Some(Vec::new()));
+    }
+}
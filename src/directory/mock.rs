@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use rocket::http::Status;
+
+use crate::directory::{rdn_value, Directory, Group, NewUser, Page, User};
+use crate::error::Error;
+
+const USER_BASE_DN: &str = "ou=users,dc=example,dc=com";
+const GROUP_BASE_DN: &str = "ou=groups,dc=example,dc=com";
+/// The placeholder member of groups that must not be empty, as in `AppConfig::for_tests`.
+const ROOT_DN: &str = "dc=example,dc=com";
+
+struct MockUser {
+    user: User,
+    password: String,
+}
+
+/// In-memory directory for tests, behaving like `LdapDirectory` does against OpenLDAP.
+#[derive(Default)]
+pub(crate) struct MockDirectory {
+    users: Mutex<BTreeMap<String, MockUser>>,
+    groups: Mutex<BTreeMap<String, Group>>,
+    /// Rejects changes leaving a group without members, like `groupOfNames` does.
+    require_members: bool,
+}
+
+impl MockDirectory {
+    pub(crate) fn user_dn(username: &str) -> String {
+        format!("uid={},{}", username, USER_BASE_DN)
+    }
+
+    pub(crate) fn group_dn(name: &str) -> String {
+        format!("cn={},{}", name, GROUP_BASE_DN)
+    }
+
+    pub(crate) fn requiring_members(mut self) -> Self {
+        self.require_members = true;
+        self
+    }
+
+    pub(crate) fn with_user(self, username: &str, password: &str) -> Self {
+        self.users.lock().unwrap().insert(
+            username.to_owned(),
+            MockUser {
+                user: User {
+                    dn: Self::user_dn(username),
+                    username: username.to_owned(),
+                    name: username.to_owned(),
+                    first_name: String::new(),
+                    last_name: String::new(),
+                    email: format!("{}@example.com", username),
+                    disabled: false,
+                },
+                password: password.to_owned(),
+            },
+        );
+        self
+    }
+
+    pub(crate) fn with_group(self, name: &str, members: &[&str]) -> Self {
+        let dn = Self::group_dn(name);
+        self.groups.lock().unwrap().insert(
+            dn.clone(),
+            Group {
+                dn,
+                members: members.iter().map(|member| member.to_string()).collect(),
+                owners: Vec::new(),
+            },
+        );
+        self
+    }
+}
+
+fn not_found() -> Error {
+    Error::Http(Status::NotFound)
+}
+
+#[rocket::async_trait]
+impl Directory for MockDirectory {
+    async fn authenticate(&self, login: &str, password: &str) -> Result<Option<String>, Error> {
+        let users = self.users.lock().unwrap();
+        Ok(users
+            .get(login)
+            .filter(|mock| !mock.user.disabled && mock.password == password)
+            .map(|mock| mock.user.username.clone()))
+    }
+
+    async fn change_password(
+        &self,
+        username: &str,
+        current_password: &str,
+        new_password: &str,
+    ) -> Result<bool, Error> {
+        if self
+            .authenticate(username, current_password)
+            .await?
+            .is_none()
+        {
+            return Ok(false);
+        }
+        let mut users = self.users.lock().unwrap();
+        users.get_mut(username).ok_or_else(not_found)?.password = new_password.to_owned();
+        Ok(true)
+    }
+
+    async fn get_user(&self, username: &str) -> Result<User, Error> {
+        let users = self.users.lock().unwrap();
+        Ok(users.get(username).ok_or_else(not_found)?.user.clone())
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, Error> {
+        let users = self.users.lock().unwrap();
+        Ok(users.values().map(|mock| mock.user.clone()).collect())
+    }
+
+    async fn search_users(
+        &self,
+        query: &str,
+        page: usize,
+        per_page: usize,
+    ) -> Result<Page<User>, Error> {
+        let users = self
+            .list_users()
+            .await?
+            .into_iter()
+            .filter(|user| {
+                user.username.contains(query)
+                    || user.name.contains(query)
+                    || user.email.contains(query)
+            })
+            .collect();
+        Ok(Page::of(users, page, per_page))
+    }
+
+    async fn update_user_name(
+        &self,
+        username: &str,
+        display_name: &str,
+        first_name: &str,
+        last_name: &str,
+    ) -> Result<(), Error> {
+        let mut users = self.users.lock().unwrap();
+        let user = &mut users.get_mut(username).ok_or_else(not_found)?.user;
+        user.name = display_name.to_owned();
+        user.first_name = first_name.to_owned();
+        user.last_name = last_name.to_owned();
+        Ok(())
+    }
+
+    async fn update_user_email(&self, username: &str, email: &str) -> Result<(), Error> {
+        let mut users = self.users.lock().unwrap();
+        users.get_mut(username).ok_or_else(not_found)?.user.email = email.to_owned();
+        Ok(())
+    }
+
+    async fn create_user(&self, user: NewUser) -> Result<User, Error> {
+        let mut users = self.users.lock().unwrap();
+        if users.contains_key(&user.username) {
+            return Err(Error::Http(Status::Conflict));
+        }
+        let created = User {
+            dn: Self::user_dn(&user.username),
+            username: user.username.clone(),
+            name: user.name,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            email: user.email,
+            disabled: false,
+        };
+        users.insert(
+            user.username,
+            MockUser {
+                user: created.clone(),
+                password: String::new(),
+            },
+        );
+        Ok(created)
+    }
+
+    async fn delete_user(&self, username: &str) -> Result<(), Error> {
+        let removed = self.users.lock().unwrap().remove(username);
+        let dn = removed.ok_or_else(not_found)?.user.dn;
+        for group in self.groups.lock().unwrap().values_mut() {
+            if group.members.contains(&dn) {
+                group.members.retain(|member| *member != dn);
+                if self.require_members && group.members.is_empty() {
+                    group.members.push(ROOT_DN.to_owned());
+                }
+            }
+            group.owners.retain(|owner| *owner != dn);
+        }
+        Ok(())
+    }
+
+    async fn set_user_disabled(&self, username: &str, disabled: bool) -> Result<(), Error> {
+        let mut users = self.users.lock().unwrap();
+        users.get_mut(username).ok_or_else(not_found)?.user.disabled = disabled;
+        Ok(())
+    }
+
+    async fn get_user_groups(&self, username: &str) -> Result<Vec<String>, Error> {
+        let dn = Self::user_dn(username);
+        let groups = self.groups.lock().unwrap();
+        Ok(groups
+            .values()
+            .filter(|group| group.members.contains(&dn))
+            .map(|group| group.dn.clone())
+            .collect())
+    }
+
+    async fn get_group(&self, dn: &str) -> Result<Group, Error> {
+        let groups = self.groups.lock().unwrap();
+        groups.get(dn).cloned().ok_or_else(not_found)
+    }
+
+    async fn list_groups(&self) -> Result<Vec<Group>, Error> {
+        Ok(self.groups.lock().unwrap().values().cloned().collect())
+    }
+
+    async fn search_groups(
+        &self,
+        query: &str,
+        page: usize,
+        per_page: usize,
+    ) -> Result<Page<Group>, Error> {
+        let groups = self
+            .list_groups()
+            .await?
+            .into_iter()
+            .filter(|group| rdn_value(&group.dn).contains(query))
+            .collect();
+        Ok(Page::of(groups, page, per_page))
+    }
+
+    async fn get_owned_groups(&self, username: &str) -> Result<Vec<String>, Error> {
+        let dn = Self::user_dn(username);
+        let groups = self.groups.lock().unwrap();
+        Ok(groups
+            .values()
+            .filter(|group| group.owners.contains(&dn))
+            .map(|group| group.dn.clone())
+            .collect())
+    }
+
+    async fn create_group(&self, name: &str, members: Vec<String>) -> Result<Group, Error> {
+        let group = Group {
+            dn: Self::group_dn(name),
+            members,
+            owners: Vec::new(),
+        };
+        let mut groups = self.groups.lock().unwrap();
+        if groups.contains_key(&group.dn) {
+            return Err(Error::Http(Status::Conflict));
+        }
+        groups.insert(group.dn.clone(), group.clone());
+        Ok(group)
+    }
+
+    async fn delete_group(&self, dn: &str) -> Result<(), Error> {
+        let removed = self.groups.lock().unwrap().remove(dn);
+        removed.map(|_| ()).ok_or_else(not_found)
+    }
+
+    async fn update_group_members(
+        &self,
+        dn: &str,
+        added: Vec<String>,
+        removed: Vec<String>,
+    ) -> Result<(), Error> {
+        let mut groups = self.groups.lock().unwrap();
+        let group = groups.get_mut(dn).ok_or_else(not_found)?;
+        let mut members = group.members.clone();
+        members.retain(|member| !removed.contains(member));
+        members.extend(added);
+        if self.require_members && members.is_empty() {
+            // objectClassViolation
+            return Err(Error::Http(Status::BadRequest));
+        }
+        group.members = members;
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use rocket::serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+
+pub(crate) mod cache;
+#[cfg(test)]
+mod fake_server;
+pub(crate) mod ldap;
+#[cfg(test)]
+pub(crate) mod mock;
+pub(crate) mod pool;
+
+pub(crate) use self::cache::DirectoryCache;
+pub(crate) use self::ldap::LdapDirectory;
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct User {
+    pub(crate) dn: String,
+    pub(crate) username: String,
+    pub(crate) name: String,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) email: String,
+    pub(crate) disabled: bool,
+}
+
+/// A user to be created. Empty values are left out of the entry.
+pub(crate) struct NewUser {
+    pub(crate) username: String,
+    pub(crate) name: String,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) email: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Group {
+    pub(crate) dn: String,
+    pub(crate) members: Vec<String>,
+    /// DNs of the users allowed to manage the members, from the `owner` attribute.
+    pub(crate) owners: Vec<String>,
+}
+
+impl Group {
+    /// Token identifying the current member list, so edits based on an outdated list can be
+    /// detected.
+    pub(crate) fn version(&self) -> String {
+        let mut members = self.members.clone();
+        members.sort();
+        let mut hasher = Sha256::new();
+        for member in members {
+            hasher.update(member.as_bytes());
+            hasher.update(b"\n");
+        }
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// Returns the value of the first RDN of `dn`, e.g. the common name of a group.
+pub(crate) fn rdn_value(dn: &str) -> String {
+    let mut value = String::new();
+    let mut chars = dn.chars().skip_while(|c| *c != '=').skip(1);
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.extend(chars.next()),
+            ',' | '+' => break,
+            c => value.push(c),
+        }
+    }
+    value
+}
+
+/// Entries per page in admin lists and member pickers.
+pub(crate) const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// One page of a sorted listing, `page` counts from 1.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Page<T> {
+    pub(crate) items: Vec<T>,
+    pub(crate) page: usize,
+    pub(crate) pages: usize,
+    pub(crate) total: usize,
+}
+
+impl<T> Page<T> {
+    pub(crate) fn of(items: Vec<T>, page: usize, per_page: usize) -> Self {
+        let total = items.len();
+        let pages = total.div_ceil(per_page).max(1);
+        let page = page.clamp(1, pages);
+        Page {
+            items: items
+                .into_iter()
+                .skip((page - 1) * per_page)
+                .take(per_page)
+                .collect(),
+            page,
+            pages,
+            total,
+        }
+    }
+}
+
+/// Access to the user and group directory. Implementations take care of escaping every value
+/// they put into DNs or search filters, so callers can pass user input as is.
+#[rocket::async_trait]
+pub(crate) trait Directory: Send + Sync {
+    /// Checks the password of `login`, which may be any value the configured user search
+    /// accepts, and returns the canonical username on success.
+    async fn authenticate(&self, login: &str, password: &str) -> Result<Option<String>, Error>;
+    /// Changes the password of `username` with the user's own credentials. Returns `false` if
+    /// `current_password` is wrong.
+    async fn change_password(
+        &self,
+        username: &str,
+        current_password: &str,
+        new_password: &str,
+    ) -> Result<bool, Error>;
+
+    async fn get_user(&self, username: &str) -> Result<User, Error>;
+    async fn list_users(&self) -> Result<Vec<User>, Error>;
+    /// Returns one page of the users whose username, display name or e-mail address contain
+    /// `query`, filtered by the server. An empty `query` matches all users.
+    async fn search_users(
+        &self,
+        query: &str,
+        page: usize,
+        per_page: usize,
+    ) -> Result<Page<User>, Error>;
+    async fn update_user_name(
+        &self,
+        username: &str,
+        display_name: &str,
+        first_name: &str,
+        last_name: &str,
+    ) -> Result<(), Error>;
+    async fn update_user_email(&self, username: &str, email: &str) -> Result<(), Error>;
+    /// Creates a user without a password, who cannot log in before one has been set.
+    async fn create_user(&self, user: NewUser) -> Result<User, Error>;
+    async fn delete_user(&self, username: &str) -> Result<(), Error>;
+    /// Locks or unlocks the account the same way `User::disabled` reads it.
+    async fn set_user_disabled(&self, username: &str, disabled: bool) -> Result<(), Error>;
+
+    /// Returns the DNs of all groups `username` is a member of.
+    async fn get_user_groups(&self, username: &str) -> Result<Vec<String>, Error>;
+    async fn get_group(&self, dn: &str) -> Result<Group, Error>;
+    async fn list_groups(&self) -> Result<Vec<Group>, Error>;
+    /// Returns one page of the groups whose common name contains `query`.
+    async fn search_groups(
+        &self,
+        query: &str,
+        page: usize,
+        per_page: usize,
+    ) -> Result<Page<Group>, Error>;
+    /// Returns the DNs of all groups naming `username` as an owner.
+    async fn get_owned_groups(&self, username: &str) -> Result<Vec<String>, Error>;
+    async fn create_group(&self, name: &str, members: Vec<String>) -> Result<Group, Error>;
+    async fn delete_group(&self, dn: &str) -> Result<(), Error>;
+    /// Adds and removes the given member DNs, leaving all other members untouched.
+    async fn update_group_members(
+        &self,
+        dn: &str,
+        added: Vec<String>,
+        removed: Vec<String>,
+    ) -> Result<(), Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_of_slices_the_requested_page() {
+        let page = Page::of((1..=7).collect(), 2, 3);
+        assert_eq!(page.items, vec![4, 5, 6]);
+        assert_eq!((page.page, page.pages, page.total), (2, 3, 7));
+        assert_eq!(Page::of((1..=7).collect(), 3, 3).items, vec![7]);
+    }
+
+    #[test]
+    fn page_of_clamps_the_page() {
+        let page = Page::of((1..=7).collect(), 0, 3);
+        assert_eq!((page.page, page.items), (1, vec![1, 2, 3]));
+        let page = Page::of((1..=7).collect(), 99, 3);
+        assert_eq!((page.page, page.items), (3, vec![7]));
+    }
+
+    #[test]
+    fn page_of_nothing_is_one_empty_page() {
+        let page = Page::of(Vec::<i32>::new(), 2, 3);
+        assert!(page.items.is_empty());
+        assert_eq!((page.page, page.pages, page.total), (1, 1, 0));
+    }
+}
@@ -0,0 +1,233 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope};
+use rocket::fairing;
+use rocket::http::Status;
+use rocket::{Build, Orbit, Rocket};
+
+use crate::config::LdapConfig;
+use crate::error::Error;
+
+struct Server {
+    url: String,
+    connection: Mutex<Option<Ldap>>,
+    healthy: AtomicBool,
+}
+
+impl Server {
+    fn new(url: &str) -> Self {
+        Server {
+            url: url.to_owned(),
+            connection: Mutex::new(None),
+            healthy: AtomicBool::new(true),
+        }
+    }
+
+    fn mark_down(&self) {
+        if self.healthy.swap(false, Ordering::Relaxed) {
+            warn!("LDAP server {} is unavailable", self.url);
+        }
+        *self.connection.lock().unwrap() = None;
+    }
+}
+
+/// Connections to an ordered list of primary LDAP servers and optional read replicas. Writes
+/// go to the first reachable primary, reads are spread across the replicas. Servers failing
+/// an operation are taken out of rotation until the health check reaches them again.
+#[derive(Clone)]
+pub(crate) struct LdapPool {
+    config: Arc<LdapConfig>,
+    tls_connector: Option<native_tls::TlsConnector>,
+    primaries: Arc<Vec<Server>>,
+    replicas: Arc<Vec<Server>>,
+    next_replica: Arc<AtomicUsize>,
+}
+
+/// A connection bound as the service account, multiplexed between requests.
+pub(crate) struct PooledLdap<'p> {
+    server: &'p Server,
+    pub(crate) ldap: Ldap,
+}
+
+impl PooledLdap<'_> {
+    /// Takes the server out of rotation if `error` is not a result the server answered with.
+    pub(crate) fn check_error(&self, error: LdapError) -> LdapError {
+        if !matches!(error, LdapError::LdapResult { .. }) {
+            self.server.mark_down();
+        }
+        error
+    }
+}
+
+impl LdapPool {
+    pub(crate) fn new(config: LdapConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let tls_connector = match &config.ca_certificate {
+            Some(path) => Some(
+                native_tls::TlsConnector::builder()
+                    .add_root_certificate(native_tls::Certificate::from_pem(&std::fs::read(path)?)?)
+                    .build()?,
+            ),
+            None => None,
+        };
+        Ok(LdapPool {
+            tls_connector,
+            primaries: Arc::new(config.urls.iter().map(|url| Server::new(url)).collect()),
+            replicas: Arc::new(
+                config
+                    .replica_urls
+                    .iter()
+                    .map(|url| Server::new(url))
+                    .collect(),
+            ),
+            next_replica: Arc::new(AtomicUsize::new(0)),
+            config: Arc::new(config),
+        })
+    }
+
+    async fn open(&self, url: &str) -> Result<Ldap, LdapError> {
+        let mut settings = LdapConnSettings::new()
+            .set_conn_timeout(Duration::from_secs(self.config.timeout))
+            .set_starttls(self.config.starttls);
+        if let Some(tls_connector) = &self.tls_connector {
+            settings = settings.set_connector(tls_connector.clone());
+        }
+        let (conn, ldap) = LdapConnAsync::with_settings(settings, url).await?;
+        rocket::tokio::spawn(async move {
+            if let Err(e) = conn.drive().await {
+                warn!("LDAP connection error: {}", e);
+            }
+        });
+        Ok(ldap)
+    }
+
+    async fn open_service(&self, url: &str) -> Result<Ldap, LdapError> {
+        let mut ldap = self.open(url).await?;
+        if let Some(bind_dn) = &self.config.bind_dn {
+            ldap.simple_bind(bind_dn, &self.config.bind_password)
+                .await?
+                .success()?;
+        }
+        Ok(ldap)
+    }
+
+    async fn checkout<'p>(&self, server: &'p Server) -> Option<PooledLdap<'p>> {
+        if !server.healthy.load(Ordering::Relaxed) {
+            return None;
+        }
+        let connection = server.connection.lock().unwrap().clone();
+        if let Some(ldap) = connection {
+            return Some(PooledLdap { server, ldap });
+        }
+        match self.open_service(&server.url).await {
+            Ok(ldap) => {
+                *server.connection.lock().unwrap() = Some(ldap.clone());
+                Some(PooledLdap { server, ldap })
+            }
+            Err(_) => {
+                server.mark_down();
+                None
+            }
+        }
+    }
+
+    /// Connection to the first reachable primary, used for writes.
+    pub(crate) async fn writer(&self) -> Result<PooledLdap<'_>, Error> {
+        for server in self.primaries.iter() {
+            if let Some(ldap) = self.checkout(server).await {
+                return Ok(ldap);
+            }
+        }
+        Err(Error::Http(Status::ServiceUnavailable))
+    }
+
+    /// Connection to a replica, falling back to the primaries if none is reachable.
+    pub(crate) async fn reader(&self) -> Result<PooledLdap<'_>, Error> {
+        let offset = self.next_replica.fetch_add(1, Ordering::Relaxed);
+        for i in 0..self.replicas.len() {
+            let server = &self.replicas[(offset + i) % self.replicas.len()];
+            if let Some(ldap) = self.checkout(server).await {
+                return Ok(ldap);
+            }
+        }
+        self.writer().await
+    }
+
+    /// Opens a new connection without the service bind for operations bound as a user, which
+    /// must not share a connection with other requests.
+    pub(crate) async fn user_connection(&self, write: bool) -> Result<Ldap, Error> {
+        let replicas = if write {
+            &self.replicas[..0]
+        } else {
+            &self.replicas[..]
+        };
+        for server in replicas
+            .iter()
+            .chain(self.primaries.iter())
+            .filter(|server| server.healthy.load(Ordering::Relaxed))
+        {
+            match self.open(&server.url).await {
+                Ok(ldap) => return Ok(ldap),
+                Err(_) => server.mark_down(),
+            }
+        }
+        Err(Error::Http(Status::ServiceUnavailable))
+    }
+
+    async fn check_health(&self) {
+        for server in self.primaries.iter().chain(self.replicas.iter()) {
+            let connection = server.connection.lock().unwrap().clone();
+            let reachable = match connection {
+                Some(mut ldap) => ldap
+                    .with_timeout(Duration::from_secs(self.config.timeout))
+                    .search("", Scope::Base, "(objectClass=*)", vec!["1.1"])
+                    .await
+                    .and_then(|search_result| search_result.success())
+                    .is_ok(),
+                None => match self.open_service(&server.url).await {
+                    Ok(ldap) => {
+                        *server.connection.lock().unwrap() = Some(ldap);
+                        true
+                    }
+                    Err(_) => false,
+                },
+            };
+            if !reachable {
+                server.mark_down();
+            } else if !server.healthy.swap(true, Ordering::Relaxed) {
+                info!("LDAP server {} is available again", server.url);
+            }
+        }
+    }
+}
+
+pub(crate) async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    let config = match rocket.figment().focus("ldap").extract::<LdapConfig>() {
+        Ok(config) => config,
+        Err(e) => {
+            rocket::config::pretty_print_error(e);
+            return Err(rocket);
+        }
+    };
+    match LdapPool::new(config) {
+        Ok(pool) => Ok(rocket.manage(pool)),
+        Err(e) => {
+            error!("Failed to set up LDAP connections: {}", e);
+            Err(rocket)
+        }
+    }
+}
+
+pub(crate) fn spawn_health_check(rocket: &Rocket<Orbit>) {
+    let pool = rocket.state::<LdapPool>().unwrap().clone();
+    rocket::tokio::spawn(async move {
+        let mut interval = rocket::tokio::time::interval(Duration::from_secs(
+            pool.config.health_check_interval.max(1),
+        ));
+        loop {
+            interval.tick().await;
+            pool.check_health().await;
+        }
+    });
+}
@@ -52,9 +52,16 @@ impl<'r> Responder<'r, 'static> for Error {
         match self {
             Error::Http(s) => s.respond_to(req),
             Error::Hydra { status } => Err(status),
-            Error::Ldap(_) => Err(Status::InternalServerError),
+            Error::Ldap(ldap3::LdapError::LdapResult { .. }) => Err(Status::InternalServerError),
+            // Anything but a result means the server could not be reached or stopped answering.
+            Error::Ldap(_) => Err(Status::ServiceUnavailable),
             Error::SerdeJSON(_) => Err(Status::InternalServerError),
             Error::Redis(_) => Err(Status::InternalServerError),
+            Error::DB(sqlx::Error::RowNotFound) => Err(Status::NotFound),
+            // unique_violation
+            Error::DB(sqlx::Error::Database(e)) if e.code().as_deref() == Some("23505") => {
+                Err(Status::Conflict)
+            }
             Error::DB(_) => Err(Status::InternalServerError),
             Error::StandardTimeError(_) => Err(Status::InternalServerError),
             Error::Other(_) => Err(Status::InternalServerError),
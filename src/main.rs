@@ -1,19 +1,20 @@
 #[macro_use]
 extern crate rocket;
 
-use rocket_sync_db_pools::{database, ldap3};
-
+mod audit;
 mod config;
 mod controllers;
 mod db;
+mod directory;
 mod error;
-mod ldap;
+mod notifications;
+mod policy;
+mod provisioning;
+mod reconcile;
 mod routes;
+mod service_accounts;
 mod sessions;
 
-#[database("ldap")]
-pub(crate) struct DBLdapConn(ldap3::LdapConn);
-
 #[rocket::main]
 async fn main() {
     let _ = routes::build().launch().await;
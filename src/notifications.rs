@@ -0,0 +1,316 @@
+use std::sync::Arc;
+
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use rand::Rng;
+use rocket::fairing;
+use rocket::http::{Cookie, CookieJar, SameSite};
+use rocket::outcome::try_outcome;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Build, Orbit, Request, Rocket, State};
+use rocket_dyn_templates::Template;
+use serde::Serialize;
+use sqlx::pool::PoolConnection;
+use sqlx::Postgres;
+
+use crate::config::{AppConfig, MailConfig, MailTransportConfig, SmtpTls};
+use crate::db::DBKnownDevice;
+use crate::directory::{Directory, LdapDirectory};
+use crate::error::Error;
+
+const DEVICE_COOKIE: &str = "legitima_device";
+
+/// Events users are told about by mail. Each has a template `email/<name>` rendered with the
+/// fields of the variant.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub(crate) enum Notification {
+    SecondFactorAdded {
+        method: &'static str,
+        label: Option<String>,
+    },
+    SecondFactorRemoved {
+        label: Option<String>,
+    },
+    /// Sent to the previous address, which the change cannot be hidden from.
+    EmailChanged {
+        previous_email: String,
+        email: String,
+    },
+    PasswordChanged {},
+    NewDeviceLogin {},
+}
+
+impl Notification {
+    fn template(&self) -> &'static str {
+        match self {
+            Notification::SecondFactorAdded { .. } => "email/second_factor_added",
+            Notification::SecondFactorRemoved { .. } => "email/second_factor_removed",
+            Notification::EmailChanged { .. } => "email/email_changed",
+            Notification::PasswordChanged {} => "email/password_changed",
+            Notification::NewDeviceLogin {} => "email/new_device_login",
+        }
+    }
+
+    fn subject(&self) -> &'static str {
+        match self {
+            Notification::SecondFactorAdded { .. } => "A second factor was added to your account",
+            Notification::SecondFactorRemoved { .. } => {
+                "A second factor was removed from your account"
+            }
+            Notification::EmailChanged { .. } => "Your e-mail address was changed",
+            Notification::PasswordChanged {} => "Your password was changed",
+            Notification::NewDeviceLogin {} => "New login to your account",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct NotificationContext<'a> {
+    app_name: &'a str,
+    username: &'a str,
+    name: &'a str,
+    time: String,
+    ip_address: Option<&'a str>,
+    user_agent: Option<&'a str>,
+    #[serde(flatten)]
+    notification: &'a Notification,
+}
+
+enum MailTransport {
+    Disabled,
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    File(AsyncFileTransport<Tokio1Executor>),
+}
+
+#[derive(Clone)]
+pub(crate) struct Mailer {
+    from: Option<Mailbox>,
+    transport: Arc<MailTransport>,
+}
+
+impl Mailer {
+    fn new(config: MailConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let transport = match config.transport {
+            MailTransportConfig::Smtp {
+                host,
+                port,
+                tls,
+                username,
+                password,
+            } => {
+                let mut builder = match tls {
+                    SmtpTls::Starttls => {
+                        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)?
+                    }
+                    SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&host)?,
+                    SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+                };
+                if let Some(port) = port {
+                    builder = builder.port(port);
+                }
+                if let Some(username) = username {
+                    builder = builder.credentials(Credentials::new(username, password));
+                }
+                MailTransport::Smtp(builder.build())
+            }
+            MailTransportConfig::File { directory } => {
+                MailTransport::File(AsyncFileTransport::<Tokio1Executor>::new(directory))
+            }
+        };
+        Ok(Mailer {
+            from: Some(config.from.parse()?),
+            transport: Arc::new(transport),
+        })
+    }
+
+    /// Sends `message` in the background; failures are only logged.
+    fn send(&self, message: Message) {
+        let transport = self.transport.clone();
+        rocket::tokio::spawn(async move {
+            let result = match &*transport {
+                MailTransport::Disabled => return,
+                MailTransport::Smtp(smtp) => smtp
+                    .send(message)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string()),
+                MailTransport::File(file) => file
+                    .send(message)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string()),
+            };
+            if let Err(e) = result {
+                warn!("Failed to send notification mail: {}", e);
+            }
+        });
+    }
+}
+
+/// Sends security notifications to users.
+pub(crate) struct Notifier<'r> {
+    rocket: &'r Rocket<Orbit>,
+    mailer: &'r Mailer,
+    app_config: &'r AppConfig,
+    directory: LdapDirectory<'r>,
+    ip_address: Option<String>,
+    user_agent: Option<&'r str>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Notifier<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Notifier<'r>, Self::Error> {
+        let mailer = try_outcome!(request.guard::<&State<Mailer>>().await);
+        let app_config = try_outcome!(request.guard::<&State<AppConfig>>().await);
+        let directory = match request.guard::<LdapDirectory<'r>>().await {
+            Outcome::Success(directory) => directory,
+            _ => return Outcome::Forward(()),
+        };
+        Outcome::Success(Notifier {
+            rocket: request.rocket(),
+            mailer: mailer.inner(),
+            app_config: app_config.inner(),
+            directory,
+            ip_address: request.client_ip().map(|ip| ip.to_string()),
+            user_agent: request.headers().get_one("User-Agent"),
+        })
+    }
+}
+
+impl Notifier<'_> {
+    /// Tells `username` about `notification`. Users without an e-mail address and failures to
+    /// look them up are skipped, as a notification must never fail the change it is about.
+    pub(crate) async fn notify(&self, username: &str, notification: Notification) {
+        let from = match &self.mailer.from {
+            Some(from) => from.clone(),
+            None => return,
+        };
+        let user = match self.directory.get_user(username).await {
+            Ok(user) => user,
+            Err(e) => {
+                warn!("Failed to look up {} for a notification: {}", username, e);
+                return;
+            }
+        };
+        let recipient = match &notification {
+            Notification::EmailChanged { previous_email, .. } => previous_email,
+            _ => &user.email,
+        };
+        let to: Mailbox = match recipient.parse() {
+            Ok(to) => to,
+            Err(_) => return,
+        };
+        let body = match Template::show(
+            self.rocket,
+            notification.template(),
+            NotificationContext {
+                app_name: &self.app_config.name,
+                username: &user.username,
+                name: &user.name,
+                time: chrono::Utc::now().format("%Y-%m-%d %H:%M UTC").to_string(),
+                ip_address: self.ip_address.as_deref(),
+                user_agent: self.user_agent,
+                notification: &notification,
+            },
+        ) {
+            Some(body) => body,
+            None => {
+                error!("Failed to render {}", notification.template());
+                return;
+            }
+        };
+        let message = Message::builder()
+            .from(from)
+            .to(to)
+            .subject(format!(
+                "{}: {}",
+                self.app_config.name,
+                notification.subject()
+            ))
+            .header(ContentType::TEXT_PLAIN)
+            .body(body);
+        match message {
+            Ok(message) => self.mailer.send(message),
+            Err(e) => error!("Failed to build notification mail: {}", e),
+        }
+    }
+
+    /// Remembers the browser a login was completed with in a cookie, notifying the user when
+    /// the browser has not been seen before. The first browser of a user is not notified
+    /// about.
+    pub(crate) async fn login_completed(
+        &self,
+        username: &str,
+        cookies: &CookieJar<'_>,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<(), Error> {
+        let device_id = match cookies.get_private(DEVICE_COOKIE) {
+            Some(cookie) => cookie.value().to_owned(),
+            None => {
+                let device_id = new_device_id();
+                let mut cookie = Cookie::new(DEVICE_COOKIE, device_id.clone());
+                cookie.set_same_site(SameSite::Lax);
+                cookie.make_permanent();
+                cookies.add_private(cookie);
+                device_id
+            }
+        };
+        let device = DBKnownDevice {
+            username: username.to_owned(),
+            device_id,
+            ip_address: self.ip_address.clone(),
+            user_agent: self.user_agent.map(str::to_owned),
+        };
+        if DBKnownDevice::touch(&device, &mut *connection).await? {
+            return Ok(());
+        }
+        let first_device = DBKnownDevice::count_by_username(username, &mut *connection).await? == 0;
+        DBKnownDevice::create_one(&device, &mut *connection).await?;
+        if !first_device {
+            self.notify(username, Notification::NewDeviceLogin {}).await;
+        }
+        Ok(())
+    }
+}
+
+fn new_device_id() -> String {
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    const DEVICE_ID_LEN: usize = 32;
+    let mut rng = rand::thread_rng();
+
+    (0..DEVICE_ID_LEN)
+        .map(|_| {
+            let idx = rng.gen_range(0..CHARSET.len());
+            CHARSET[idx] as char
+        })
+        .collect()
+}
+
+pub(crate) async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    if rocket.figment().find_value("mail").is_err() {
+        info!("No mail configured, security notifications are disabled");
+        return Ok(rocket.manage(Mailer {
+            from: None,
+            transport: Arc::new(MailTransport::Disabled),
+        }));
+    }
+    let config = match rocket.figment().focus("mail").extract::<MailConfig>() {
+        Ok(config) => config,
+        Err(e) => {
+            rocket::config::pretty_print_error(e);
+            return Err(rocket);
+        }
+    };
+    match Mailer::new(config) {
+        Ok(mailer) => Ok(rocket.manage(mailer)),
+        Err(e) => {
+            error!("Failed to set up mail: {}", e);
+            Err(rocket)
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use rocket_db_pools::Connection;
+
+use crate::config::AppConfig;
+use crate::db::{
+    DBGroup, DBTotpCredential, DBUser2FAGrace, DBUserCredential, DBUserCredentialTypes, DB,
+};
+use crate::directory::Directory;
+use crate::error::Error;
+
+/// Resolves whether a second factor is mandatory for a member of `user_group_dns`. A group
+/// requiring 2FA wins over a group exempting from it, both override the global setting.
+pub(crate) fn is_2fa_enforced(
+    app_config: &AppConfig,
+    groups: &[DBGroup],
+    user_group_dns: &[String],
+) -> bool {
+    let overrides: Vec<bool> = groups
+        .iter()
+        .filter(|group| user_group_dns.contains(&group.ldap_dn))
+        .filter_map(|group| group.enforce_2fa)
+        .collect();
+    if overrides.contains(&true) {
+        true
+    } else if overrides.contains(&false) {
+        false
+    } else {
+        app_config.enforce_2fa
+    }
+}
+
+/// Checks whether a user without a second factor may still skip the enrolment. Grace ends as
+/// soon as one of the configured limits is exceeded; without limits there is no grace at all.
+pub(crate) fn in_grace_period(app_config: &AppConfig, grace: Option<&DBUser2FAGrace>) -> bool {
+    let grace_logins = app_config.enforce_2fa_grace_logins;
+    let grace_days = app_config.enforce_2fa_grace_days;
+    if grace_logins <= 0 && grace_days <= 0 {
+        return false;
+    }
+    match grace {
+        Some(grace) => {
+            (grace_logins <= 0 || grace.login_count <= grace_logins)
+                && (grace_days <= 0
+                    || chrono::Utc::now().signed_duration_since(grace.first_login_at)
+                        < chrono::Duration::days(grace_days))
+        }
+        None => true,
+    }
+}
+
+/// Checks whether `username` has enrolled a TOTP or WebAuthn credential as second factor.
+pub(crate) async fn has_second_factor_enrolled(
+    username: &str,
+    db: &mut Connection<DB>,
+) -> Result<bool, Error> {
+    Ok(
+        DBUserCredential::<DBTotpCredential>::find_permanent_credentials_by_username(
+            username, &mut *db,
+        )
+        .await?
+        .iter()
+        .any(|credential_type| {
+            matches!(
+                credential_type,
+                DBUserCredentialTypes::TotpCredential | DBUserCredentialTypes::WebauthnCredential
+            )
+        }),
+    )
+}
+
+/// Checks whether `username` has no second factor although the policy requires one.
+pub(crate) async fn requires_2fa_enrolment(
+    app_config: &AppConfig,
+    directory: &dyn Directory,
+    username: &str,
+    db: &mut Connection<DB>,
+) -> Result<bool, Error> {
+    if has_second_factor_enrolled(username, db).await? {
+        return Ok(false);
+    }
+    let groups = DBGroup::list_all(&mut *db).await?;
+    let user_group_dns = directory.get_user_groups(username).await?;
+    Ok(is_2fa_enforced(app_config, &groups, &user_group_dns))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+
+    fn group(ldap_dn: &str, enforce_2fa: Option<bool>) -> DBGroup {
+        DBGroup {
+            id: None,
+            name: ldap_dn.to_owned(),
+            ldap_dn: ldap_dn.to_owned(),
+            enforce_2fa,
+        }
+    }
+
+    fn grace(days_ago: i64, login_count: i32) -> DBUser2FAGrace {
+        DBUser2FAGrace {
+            username: "alice".to_owned(),
+            first_login_at: Utc::now() - Duration::days(days_ago),
+            login_count,
+        }
+    }
+
+    #[test]
+    fn groups_override_the_global_setting() {
+        let groups = [
+            group("cn=admins", Some(true)),
+            group("cn=robots", Some(false)),
+            group("cn=staff", None),
+        ];
+        let enforced = AppConfig::for_tests(json!({ "enforce_2fa": true }));
+        let optional = AppConfig::for_tests(json!({}));
+
+        assert!(is_2fa_enforced(
+            &enforced,
+            &groups,
+            &["cn=staff".to_owned()]
+        ));
+        assert!(!is_2fa_enforced(
+            &optional,
+            &groups,
+            &["cn=staff".to_owned()]
+        ));
+        assert!(!is_2fa_enforced(
+            &enforced,
+            &groups,
+            &["cn=robots".to_owned()]
+        ));
+        assert!(is_2fa_enforced(
+            &optional,
+            &groups,
+            &["cn=admins".to_owned()]
+        ));
+        assert!(is_2fa_enforced(
+            &optional,
+            &groups,
+            &["cn=robots".to_owned(), "cn=admins".to_owned()]
+        ));
+    }
+
+    #[test]
+    fn no_grace_without_limits() {
+        let app_config = AppConfig::for_tests(json!({ "enforce_2fa": true }));
+        assert!(!in_grace_period(&app_config, None));
+        assert!(!in_grace_period(&app_config, Some(&grace(0, 1))));
+    }
+
+    #[test]
+    fn grace_ends_at_the_first_exceeded_limit() {
+        let app_config = AppConfig::for_tests(json!({
+            "enforce_2fa_grace_logins": 3,
+            "enforce_2fa_grace_days": 7,
+        }));
+        assert!(in_grace_period(&app_config, None));
+        assert!(in_grace_period(&app_config, Some(&grace(6, 3))));
+        assert!(!in_grace_period(&app_config, Some(&grace(6, 4))));
+        assert!(!in_grace_period(&app_config, Some(&grace(7, 1))));
+    }
+
+    #[test]
+    fn a_single_limit_is_enough() {
+        let logins_only = AppConfig::for_tests(json!({ "enforce_2fa_grace_logins": 2 }));
+        assert!(in_grace_period(&logins_only, Some(&grace(365, 2))));
+        assert!(!in_grace_period(&logins_only, Some(&grace(0, 3))));
+
+        let days_only = AppConfig::for_tests(json!({ "enforce_2fa_grace_days": 1 }));
+        assert!(in_grace_period(&days_only, Some(&grace(0, 100))));
+        assert!(!in_grace_period(&days_only, Some(&grace(2, 1))));
+    }
+}
@@ -0,0 +1,391 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{SecondsFormat, Utc};
+use rocket::http::Status;
+use rocket::serde::Serialize;
+use rocket::{fairing, Build, Orbit, Rocket};
+use rocket_db_pools::Database;
+use serde_json::Value;
+use sqlx::pool::PoolConnection;
+use sqlx::Postgres;
+
+use crate::audit::sink::webhook_signature;
+use crate::audit::AuditEventType;
+use crate::config::{
+    AppConfig, ProvisioningConfig, ProvisioningTargetConfig, ProvisioningTargetKind,
+};
+use crate::db::{DBDueProvisioningDelivery, DBGroup, DBProvisioningDelivery, DB};
+use crate::directory::pool::LdapPool;
+use crate::directory::{rdn_value, Directory, DirectoryCache, LdapDirectory, User};
+use crate::error::Error;
+use scim::ScimTarget;
+
+pub(crate) mod scim;
+
+pub(crate) type DeliveryError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The events apps are told about; all other events only concern legitima itself.
+const PROVISIONED_EVENTS: [AuditEventType; 9] = [
+    AuditEventType::UserCreated,
+    AuditEventType::NameChanged,
+    AuditEventType::EmailChanged,
+    AuditEventType::UserDisabled,
+    AuditEventType::UserEnabled,
+    AuditEventType::UserDeleted,
+    AuditEventType::GroupUpdated,
+    AuditEventType::GroupDeleted,
+    AuditEventType::GroupMembersChanged,
+];
+
+/// Deliveries made per round at most.
+const BATCH_SIZE: i64 = 100;
+
+/// What the apps of the provisioned clients are told about changes. Changes are queued in
+/// Postgres with the audit event describing them, so they survive restarts and are retried
+/// until the app accepts them.
+#[derive(Clone, Default)]
+pub(crate) struct Provisioning {
+    targets: Arc<Vec<Target>>,
+    interval: u64,
+    max_attempts: i32,
+}
+
+struct Target {
+    client_id: String,
+    kind: TargetKind,
+}
+
+enum TargetKind {
+    Scim(ScimTarget),
+    Webhook {
+        client: reqwest::Client,
+        url: String,
+        secret: String,
+    },
+}
+
+/// A change as POSTed to webhooks.
+#[derive(Serialize)]
+struct WebhookDelivery<'a> {
+    delivery_id: i64,
+    client_id: &'a str,
+    #[serde(rename = "type")]
+    event_type: &'a str,
+    /// RFC 3339 in UTC, when the change was made.
+    timestamp: String,
+    subject: Option<&'a str>,
+    payload: &'a Value,
+    /// The user as it is now, for changes of a user that still exists.
+    user: Option<User>,
+}
+
+fn event_type(name: &str) -> Option<AuditEventType> {
+    AuditEventType::ALL
+        .into_iter()
+        .find(|event_type| event_type.as_str() == name)
+}
+
+/// Looks up a user, returning `None` if it has been deleted since.
+async fn current_user(
+    directory: &LdapDirectory<'_>,
+    username: &str,
+) -> Result<Option<User>, DeliveryError> {
+    match directory.get_user(username).await {
+        Ok(user) => Ok(Some(user)),
+        Err(Error::Http(status)) if status == Status::NotFound => Ok(None),
+        Err(e) => Err(e.to_string().into()),
+    }
+}
+
+impl Target {
+    fn new(config: ProvisioningTargetConfig) -> Result<Target, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()?;
+        Ok(Target {
+            client_id: config.client_id,
+            kind: match config.kind {
+                ProvisioningTargetKind::Scim { url, token } => TargetKind::Scim(ScimTarget {
+                    client,
+                    url: url.trim_end_matches('/').to_owned(),
+                    token,
+                }),
+                ProvisioningTargetKind::Webhook { url, secret } => TargetKind::Webhook {
+                    client,
+                    url,
+                    secret,
+                },
+            },
+        })
+    }
+
+    async fn deliver(
+        &self,
+        delivery: &DBDueProvisioningDelivery,
+        directory: &LdapDirectory<'_>,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<(), DeliveryError> {
+        let event_type = event_type(&delivery.event_type);
+        let subject = delivery.subject.as_deref().unwrap_or_default();
+        let payload = &delivery.payload.0;
+        let scim = match &self.kind {
+            TargetKind::Scim(scim) => scim,
+            TargetKind::Webhook {
+                client,
+                url,
+                secret,
+            } => {
+                let user = match event_type {
+                    Some(
+                        AuditEventType::UserCreated
+                        | AuditEventType::NameChanged
+                        | AuditEventType::EmailChanged
+                        | AuditEventType::UserDisabled
+                        | AuditEventType::UserEnabled,
+                    ) => current_user(directory, subject).await?,
+                    _ => None,
+                };
+                let json = serde_json::to_string(&WebhookDelivery {
+                    delivery_id: delivery.id,
+                    client_id: &self.client_id,
+                    event_type: &delivery.event_type,
+                    timestamp: delivery
+                        .event_created_at
+                        .to_rfc3339_opts(SecondsFormat::Micros, true),
+                    subject: delivery.subject.as_deref(),
+                    payload,
+                    user,
+                })?;
+                let timestamp = Utc::now().timestamp().to_string();
+                let signature = webhook_signature(secret, &timestamp, &json);
+                client
+                    .post(url.as_str())
+                    .header("Content-Type", "application/json")
+                    .header("X-Legitima-Timestamp", timestamp)
+                    .header("X-Legitima-Signature", signature)
+                    .body(json)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                return Ok(());
+            }
+        };
+        match event_type {
+            Some(
+                AuditEventType::UserCreated
+                | AuditEventType::NameChanged
+                | AuditEventType::EmailChanged
+                | AuditEventType::UserDisabled
+                | AuditEventType::UserEnabled,
+            ) => {
+                // A user deleted in the meantime is deleted by a later delivery.
+                if let Some(user) = current_user(directory, subject).await? {
+                    scim.put_user(&user).await?;
+                }
+            }
+            Some(AuditEventType::UserDeleted) => scim.delete_user(subject).await?,
+            Some(AuditEventType::GroupMembersChanged) => {
+                let group_id = payload["group_id"]
+                    .as_i64()
+                    .and_then(|group_id| i32::try_from(group_id).ok())
+                    .ok_or("event without group_id")?;
+                let group = match DBGroup::find_by_id(group_id, connection).await {
+                    Ok(group) => group,
+                    // A group deleted in the meantime is deleted by a later delivery.
+                    Err(sqlx::Error::RowNotFound) => return Ok(()),
+                    Err(e) => return Err(e.into()),
+                };
+                let users = directory.list_users().await.map_err(|e| e.to_string())?;
+                // Members are recorded as DNs or, in groups listing usernames, as usernames.
+                let find_user = |member: &str| {
+                    users.iter().find(|user| {
+                        user.dn.eq_ignore_ascii_case(member)
+                            || user.username.eq_ignore_ascii_case(member)
+                    })
+                };
+                let members = |field: &str| -> Vec<String> {
+                    payload[field]
+                        .as_array()
+                        .map(|members| {
+                            members
+                                .iter()
+                                .filter_map(|member| member.as_str().map(str::to_owned))
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                };
+                let added: Vec<User> = members("added")
+                    .iter()
+                    .filter_map(|member| find_user(member).cloned())
+                    .collect();
+                let removed: Vec<String> = members("removed")
+                    .iter()
+                    .map(|member| match find_user(member) {
+                        Some(user) => user.username.clone(),
+                        None if member.contains('=') => rdn_value(member),
+                        None => member.clone(),
+                    })
+                    .collect();
+                scim.change_members(&group.name, &added, &removed).await?;
+            }
+            Some(AuditEventType::GroupUpdated) => {
+                if let (Some(previous_name), Some(name)) =
+                    (payload["previous_name"].as_str(), payload["name"].as_str())
+                {
+                    if previous_name != name {
+                        scim.rename_group(previous_name, name).await?;
+                    }
+                }
+            }
+            Some(AuditEventType::GroupDeleted) => {
+                if let Some(name) = payload["name"].as_str() {
+                    scim.delete_group(name).await?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl Provisioning {
+    pub(crate) fn client_ids(&self) -> Vec<String> {
+        self.targets
+            .iter()
+            .map(|target| target.client_id.clone())
+            .collect()
+    }
+
+    /// Queues the change recorded as audit event `audit_event_id` for every provisioned
+    /// client, unless apps are not told about events of its type.
+    pub(crate) async fn enqueue(
+        &self,
+        event_type: AuditEventType,
+        audit_event_id: i64,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<(), Error> {
+        if !PROVISIONED_EVENTS.contains(&event_type) {
+            return Ok(());
+        }
+        for target in self.targets.iter() {
+            DBProvisioningDelivery::create_one(&target.client_id, audit_event_id, connection)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Makes the due deliveries. After a failure the remaining deliveries of the client wait
+    /// for the next round, so its changes stay in order.
+    async fn deliver_due(
+        &self,
+        directory: &LdapDirectory<'_>,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<(), sqlx::Error> {
+        let mut failed_clients: Vec<String> = Vec::new();
+        for delivery in DBProvisioningDelivery::list_due(BATCH_SIZE, connection).await? {
+            if failed_clients.contains(&delivery.client_id) {
+                continue;
+            }
+            let result = match self
+                .targets
+                .iter()
+                .find(|target| target.client_id == delivery.client_id)
+            {
+                Some(target) => target.deliver(&delivery, directory, connection).await,
+                None => Err("the client is no longer provisioned".into()),
+            };
+            match result {
+                Ok(()) => DBProvisioningDelivery::mark_delivered(delivery.id, connection).await?,
+                Err(e) => {
+                    failed_clients.push(delivery.client_id.clone());
+                    let attempts = delivery.attempts + 1;
+                    // Backs off exponentially up to about an hour.
+                    let next_attempt_at = (self.max_attempts == 0 || attempts < self.max_attempts)
+                        .then(|| Utc::now() + chrono::Duration::seconds(30 << attempts.min(7)));
+                    match next_attempt_at {
+                        Some(_) => warn!(
+                            "Failed to deliver {} to {}: {}",
+                            delivery.id, delivery.client_id, e
+                        ),
+                        None => error!(
+                            "Giving up delivering {} to {} after {} attempts: {}",
+                            delivery.id, delivery.client_id, attempts, e
+                        ),
+                    }
+                    DBProvisioningDelivery::record_failure(
+                        delivery.id,
+                        &e.to_string(),
+                        next_attempt_at,
+                        connection,
+                    )
+                    .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    let config = match rocket
+        .figment()
+        .focus("provisioning")
+        .extract::<ProvisioningConfig>()
+    {
+        Ok(config) => config,
+        Err(e) => {
+            rocket::config::pretty_print_error(e);
+            return Err(rocket);
+        }
+    };
+    let mut targets = Vec::new();
+    for target_config in config.targets {
+        let client_id = target_config.client_id.clone();
+        match Target::new(target_config) {
+            Ok(target) => targets.push(target),
+            Err(e) => {
+                error!("Failed to set up provisioning of {}: {}", client_id, e);
+                return Err(rocket);
+            }
+        }
+    }
+    Ok(rocket.manage(Provisioning {
+        targets: Arc::new(targets),
+        interval: config.interval,
+        max_attempts: config.max_attempts,
+    }))
+}
+
+/// Periodically makes the due deliveries.
+pub(crate) fn spawn_worker(rocket: &Rocket<Orbit>) {
+    let provisioning = rocket.state::<Provisioning>().unwrap().clone();
+    if provisioning.targets.is_empty() {
+        return;
+    }
+    let app_config = rocket.state::<AppConfig>().unwrap().clone();
+    let pool = rocket.state::<LdapPool>().unwrap().clone();
+    let db = match DB::fetch(rocket) {
+        Some(db) => sqlx::PgPool::clone(db),
+        None => return,
+    };
+    rocket::tokio::spawn(async move {
+        let cache = DirectoryCache::default();
+        let directory = LdapDirectory::new(&pool, &app_config, &cache);
+        let mut interval =
+            rocket::tokio::time::interval(Duration::from_secs(provisioning.interval.max(1)));
+        loop {
+            interval.tick().await;
+            // Apps get users and groups as they are in LDAP now.
+            cache.invalidate_users();
+            cache.invalidate_groups();
+            let result = match db.acquire().await {
+                Ok(mut connection) => provisioning.deliver_due(&directory, &mut connection).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                warn!("Provisioning failed: {}", e);
+            }
+        }
+    });
+}
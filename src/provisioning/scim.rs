@@ -0,0 +1,200 @@
+use reqwest::{RequestBuilder, StatusCode};
+use serde_json::{json, Value};
+
+use crate::directory::User;
+use crate::provisioning::DeliveryError;
+
+const SCIM_JSON: &str = "application/scim+json";
+
+/// A SCIM 2.0 service provider users and groups are pushed to. Its ids are not stored; users
+/// are looked up by `userName` and groups by `displayName` for every change.
+pub(crate) struct ScimTarget {
+    pub(crate) client: reqwest::Client,
+    /// Base URL without trailing slash.
+    pub(crate) url: String,
+    pub(crate) token: String,
+}
+
+impl ScimTarget {
+    /// Sends a request, returning the JSON body, `Value::Null` for an empty one and `None` for
+    /// 404.
+    async fn send(&self, request: RequestBuilder) -> Result<Option<Value>, DeliveryError> {
+        let response = request
+            .bearer_auth(&self.token)
+            .header("Accept", SCIM_JSON)
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = response.error_for_status()?.text().await?;
+        Ok(Some(match body.trim().is_empty() {
+            true => Value::Null,
+            false => serde_json::from_str(&body)?,
+        }))
+    }
+
+    async fn send_json(
+        &self,
+        request: RequestBuilder,
+        body: Value,
+    ) -> Result<Option<Value>, DeliveryError> {
+        self.send(
+            request
+                .header("Content-Type", SCIM_JSON)
+                .body(body.to_string()),
+        )
+        .await
+    }
+
+    /// The id of the resource whose `attribute` equals `value`.
+    async fn find(
+        &self,
+        resource: &str,
+        attribute: &str,
+        value: &str,
+    ) -> Result<Option<String>, DeliveryError> {
+        let filter = format!(
+            "{} eq \"{}\"",
+            attribute,
+            value.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+        let request = self
+            .client
+            .get(format!("{}/{}", self.url, resource))
+            .query(&[("filter", filter)]);
+        Ok(self
+            .send(request)
+            .await?
+            .and_then(|body| body["Resources"][0]["id"].as_str().map(str::to_owned)))
+    }
+
+    /// Creates or replaces a user, returning its id.
+    pub(crate) async fn put_user(&self, user: &User) -> Result<String, DeliveryError> {
+        let mut body = json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+            "userName": user.username,
+            "name": {
+                "formatted": user.name,
+                "givenName": user.first_name,
+                "familyName": user.last_name,
+            },
+            "displayName": user.name,
+            "emails": [],
+            "active": !user.disabled,
+        });
+        if !user.email.is_empty() {
+            body["emails"] = json!([{ "value": user.email, "primary": true, "type": "work" }]);
+        }
+        match self.find("Users", "userName", &user.username).await? {
+            Some(id) => {
+                let url = format!("{}/Users/{}", self.url, id);
+                self.send_json(self.client.put(url), body).await?;
+                Ok(id)
+            }
+            None => {
+                let url = format!("{}/Users", self.url);
+                self.send_json(self.client.post(url), body)
+                    .await?
+                    .and_then(|body| body["id"].as_str().map(str::to_owned))
+                    .ok_or_else(|| "created user without id".into())
+            }
+        }
+    }
+
+    pub(crate) async fn delete_user(&self, username: &str) -> Result<(), DeliveryError> {
+        if let Some(id) = self.find("Users", "userName", username).await? {
+            let url = format!("{}/Users/{}", self.url, id);
+            self.send(self.client.delete(url)).await?;
+        }
+        Ok(())
+    }
+
+    /// Adds users to and removes users from a group. Missing users are created before they
+    /// are added, a missing group is created with the added users.
+    pub(crate) async fn change_members(
+        &self,
+        group_name: &str,
+        added: &[User],
+        removed: &[String],
+    ) -> Result<(), DeliveryError> {
+        let mut added_ids = Vec::new();
+        for user in added {
+            added_ids.push(self.put_user(user).await?);
+        }
+        let group_id = match self.find("Groups", "displayName", group_name).await? {
+            Some(group_id) => group_id,
+            None if added_ids.is_empty() => return Ok(()),
+            None => {
+                let body = json!({
+                    "schemas": ["urn:ietf:params:scim:schemas:core:2.0:Group"],
+                    "displayName": group_name,
+                    "members": added_ids
+                        .iter()
+                        .map(|id| json!({ "value": id }))
+                        .collect::<Vec<Value>>(),
+                });
+                let url = format!("{}/Groups", self.url);
+                self.send_json(self.client.post(url), body).await?;
+                return Ok(());
+            }
+        };
+        let mut operations = Vec::new();
+        if !added_ids.is_empty() {
+            operations.push(json!({
+                "op": "add",
+                "path": "members",
+                "value": added_ids
+                    .iter()
+                    .map(|id| json!({ "value": id }))
+                    .collect::<Vec<Value>>(),
+            }));
+        }
+        for username in removed {
+            if let Some(id) = self.find("Users", "userName", username).await? {
+                operations.push(json!({
+                    "op": "remove",
+                    "path": format!("members[value eq \"{}\"]", id),
+                }));
+            }
+        }
+        if !operations.is_empty() {
+            self.patch_group(&group_id, operations).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn rename_group(
+        &self,
+        previous_name: &str,
+        name: &str,
+    ) -> Result<(), DeliveryError> {
+        if let Some(group_id) = self.find("Groups", "displayName", previous_name).await? {
+            let operations = vec![json!({ "op": "replace", "path": "displayName", "value": name })];
+            self.patch_group(&group_id, operations).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn delete_group(&self, name: &str) -> Result<(), DeliveryError> {
+        if let Some(group_id) = self.find("Groups", "displayName", name).await? {
+            let url = format!("{}/Groups/{}", self.url, group_id);
+            self.send(self.client.delete(url)).await?;
+        }
+        Ok(())
+    }
+
+    async fn patch_group(
+        &self,
+        group_id: &str,
+        operations: Vec<Value>,
+    ) -> Result<(), DeliveryError> {
+        let body = json!({
+            "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+            "Operations": operations,
+        });
+        let url = format!("{}/Groups/{}", self.url, group_id);
+        self.send_json(self.client.patch(url), body).await?;
+        Ok(())
+    }
+}
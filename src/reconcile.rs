@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use rocket::{Orbit, Rocket};
+use rocket_db_pools::Database;
+use sqlx::pool::PoolConnection;
+use sqlx::Postgres;
+
+use crate::config::AppConfig;
+use crate::db::{DBGroup, DB};
+use crate::directory::pool::LdapPool;
+use crate::directory::{Directory, DirectoryCache, LdapDirectory};
+use crate::error::Error;
+
+/// Differences between the legitima groups in Postgres and the groups in LDAP.
+pub(crate) struct Drift {
+    /// legitima groups linked to a DN that does not exist in LDAP.
+    pub(crate) dangling: Vec<DBGroup>,
+    /// LDAP groups no legitima group is linked to.
+    pub(crate) orphaned: Vec<String>,
+}
+
+pub(crate) async fn detect_drift(
+    directory: &dyn Directory,
+    connection: &mut PoolConnection<Postgres>,
+) -> Result<Drift, Error> {
+    let ldap_dns: Vec<String> = directory
+        .list_groups()
+        .await?
+        .into_iter()
+        .map(|group| group.dn)
+        .collect();
+    let db_groups = DBGroup::list_all(connection).await?;
+
+    // DNs are compared case-insensitively, like LDAP compares the usual naming attributes.
+    let ldap_keys: HashSet<String> = ldap_dns.iter().map(|dn| dn.to_lowercase()).collect();
+    let db_keys: HashSet<String> = db_groups
+        .iter()
+        .map(|group| group.ldap_dn.to_lowercase())
+        .collect();
+    Ok(Drift {
+        dangling: db_groups
+            .into_iter()
+            .filter(|group| !ldap_keys.contains(&group.ldap_dn.to_lowercase()))
+            .collect(),
+        orphaned: ldap_dns
+            .into_iter()
+            .filter(|dn| !db_keys.contains(&dn.to_lowercase()))
+            .collect(),
+    })
+}
+
+/// Periodically logs drift, so it is noticed without anyone opening the reconciliation page.
+pub(crate) fn spawn_drift_check(rocket: &Rocket<Orbit>) {
+    let app_config = rocket.state::<AppConfig>().unwrap().clone();
+    if app_config.reconcile_interval == 0 {
+        return;
+    }
+    let pool = rocket.state::<LdapPool>().unwrap().clone();
+    let db = match DB::fetch(rocket) {
+        Some(db) => sqlx::PgPool::clone(db),
+        None => return,
+    };
+    rocket::tokio::spawn(async move {
+        let cache = DirectoryCache::default();
+        let directory = LdapDirectory::new(&pool, &app_config, &cache);
+        let mut interval =
+            rocket::tokio::time::interval(Duration::from_secs(app_config.reconcile_interval));
+        loop {
+            interval.tick().await;
+            cache.invalidate_groups();
+            let drift = match db.acquire().await {
+                Ok(mut connection) => detect_drift(&directory, &mut connection).await,
+                Err(e) => Err(Error::DB(e)),
+            };
+            match drift {
+                Ok(drift) => {
+                    for group in drift.dangling {
+                        warn!(
+                            "Group {} is linked to {}, which does not exist in LDAP",
+                            group.name, group.ldap_dn
+                        );
+                    }
+                    if !drift.orphaned.is_empty() {
+                        info!(
+                            "{} LDAP group(s) are not linked to a legitima group",
+                            drift.orphaned.len()
+                        );
+                    }
+                }
+                Err(e) => warn!("Group reconciliation failed: {}", e),
+            }
+        }
+    });
+}
@@ -5,10 +5,11 @@ use rocket::{Build, Rocket};
 use rocket_db_pools::Database;
 use rocket_dyn_templates::Template;
 
-use crate::config::{AppConfig, HydraConfig, WebauthnStaticConfig};
+use crate::config::{ApiConfig, AppConfig, HydraConfig, WebauthnStaticConfig};
 use crate::db::DB;
+use crate::directory::DirectoryCache;
 use crate::sessions::SessionStorage;
-use crate::{db, DBLdapConn};
+use crate::{db, directory};
 
 #[get("/")]
 fn base_redirect() -> Redirect {
@@ -27,7 +28,9 @@ pub(crate) fn build() -> Rocket<Build> {
                 crate::controllers::errors::bad_request,
                 crate::controllers::errors::forbidden,
                 crate::controllers::errors::not_found,
-                crate::controllers::errors::internal_server_error
+                crate::controllers::errors::conflict,
+                crate::controllers::errors::internal_server_error,
+                crate::controllers::errors::service_unavailable
             ],
         )
         .mount("/", routes![base_redirect])
@@ -38,6 +41,7 @@ pub(crate) fn build() -> Rocket<Build> {
                 crate::controllers::auth::login::login,
                 crate::controllers::auth::login::submit,
                 crate::controllers::auth::login::two_factor,
+                crate::controllers::auth::login::two_factor_enroll,
                 crate::controllers::auth::login::totp_2fa,
                 crate::controllers::auth::login::webauthn_2fa_challenge_login,
                 crate::controllers::auth::login::webauthn_2fa_login
@@ -50,7 +54,10 @@ pub(crate) fn build() -> Rocket<Build> {
                 crate::controllers::oidc::login::index,
                 crate::controllers::oidc::consent::index,
                 crate::controllers::oidc::consent::approve,
-                crate::controllers::oidc::consent::reject
+                crate::controllers::oidc::consent::reject,
+                crate::controllers::oidc::device::index,
+                crate::controllers::oidc::device::submit,
+                crate::controllers::oidc::device::done
             ],
         )
         .mount(
@@ -63,11 +70,18 @@ pub(crate) fn build() -> Rocket<Build> {
                 crate::controllers::selfservice::security::get_security,
                 crate::controllers::selfservice::security::auth_get_security,
                 crate::controllers::selfservice::security::auth_credential_delete,
+                crate::controllers::selfservice::security::auth_change_password,
                 crate::controllers::selfservice::security::auth_webauthn_challenge_register,
                 crate::controllers::selfservice::security::auth_webauthn_register,
                 crate::controllers::selfservice::security::auth_totp_setup_step1,
                 crate::controllers::selfservice::security::auth_totp_setup_step2,
                 crate::controllers::selfservice::security::auth_totp_setup_step3,
+                crate::controllers::selfservice::groups::list_groups,
+                crate::controllers::selfservice::groups::auth_list_groups,
+                crate::controllers::selfservice::groups::auth_edit_group,
+                crate::controllers::selfservice::groups::auth_edit_group_memberform,
+                crate::controllers::selfservice::activity::get_activity,
+                crate::controllers::selfservice::activity::auth_get_activity,
             ],
         )
         .mount(
@@ -77,20 +91,110 @@ pub(crate) fn build() -> Rocket<Build> {
                 crate::controllers::admin::groups::auth_list_groups,
                 crate::controllers::admin::groups::auth_edit_group,
                 crate::controllers::admin::groups::auth_edit_group_memberform,
+                crate::controllers::admin::groups::auth_change_group_member,
+                crate::controllers::admin::groups::auth_edit_group_2fa_policy_form,
+                crate::controllers::admin::groups::auth_edit_group_general_form,
+                crate::controllers::admin::groups::auth_delete_group_form,
+                crate::controllers::admin::groups::auth_add_group_owner,
+                crate::controllers::admin::groups::auth_delete_group_owner,
                 crate::controllers::admin::groups::auth_add_ldap_legitima,
                 crate::controllers::admin::groups::auth_add_ldap_legitima_form,
                 crate::controllers::admin::groups::auth_add_legitima,
                 crate::controllers::admin::groups::auth_add_legitima_form,
+                crate::controllers::admin::reconcile::reconcile,
+                crate::controllers::admin::reconcile::auth_reconcile,
+                crate::controllers::admin::reconcile::auth_import_group,
+                crate::controllers::admin::reconcile::auth_recreate_group,
+                crate::controllers::admin::clients::list_clients,
+                crate::controllers::admin::clients::auth_list_clients,
+                crate::controllers::admin::clients::auth_edit_client_form,
+                crate::controllers::admin::security::security_overview,
+                crate::controllers::admin::security::auth_security_overview,
+                crate::controllers::admin::audit::audit_log,
+                crate::controllers::admin::service_accounts::list_service_accounts,
+                crate::controllers::admin::service_accounts::auth_list_service_accounts,
+                crate::controllers::admin::service_accounts::auth_add_service_account,
+                crate::controllers::admin::service_accounts::auth_delete_service_account,
+                crate::controllers::admin::service_accounts::auth_add_api_key,
+                crate::controllers::admin::service_accounts::auth_rotate_api_key,
+                crate::controllers::admin::service_accounts::auth_revoke_api_key,
+                crate::controllers::admin::audit::auth_audit_log,
+                crate::controllers::admin::provisioning::delivery_log,
+                crate::controllers::admin::provisioning::auth_delivery_log,
+                crate::controllers::admin::provisioning::auth_retry_delivery,
+            ],
+        )
+        .register("/api", catchers![crate::controllers::api::api_error])
+        .mount(
+            "/api/v1",
+            routes![
+                crate::controllers::api::openapi,
+                crate::controllers::api::me::get_me,
+                crate::controllers::api::me::put_name,
+                crate::controllers::api::me::put_email,
+                crate::controllers::api::me::get_groups,
+                crate::controllers::api::me::get_credentials,
+                crate::controllers::api::me::delete_credential,
+                crate::controllers::api::me::get_sessions,
+                crate::controllers::api::me::delete_sessions,
+                crate::controllers::api::users::list_users,
+                crate::controllers::api::users::get_user,
+                crate::controllers::api::users::delete_sessions,
+                crate::controllers::api::groups::list_groups,
+                crate::controllers::api::groups::create_group,
+                crate::controllers::api::groups::get_group,
+                crate::controllers::api::groups::delete_group,
+                crate::controllers::api::groups::get_members,
+                crate::controllers::api::groups::change_members,
+            ],
+        )
+        .register("/scim", catchers![crate::controllers::scim::scim_error])
+        .mount(
+            "/scim/v2",
+            routes![
+                crate::controllers::scim::service_provider_config,
+                crate::controllers::scim::users::list_users,
+                crate::controllers::scim::users::get_user,
+                crate::controllers::scim::users::create_user,
+                crate::controllers::scim::users::replace_user,
+                crate::controllers::scim::users::patch_user,
+                crate::controllers::scim::users::delete_user,
+                crate::controllers::scim::groups::list_groups,
+                crate::controllers::scim::groups::get_group,
+                crate::controllers::scim::groups::create_group,
+                crate::controllers::scim::groups::replace_group,
+                crate::controllers::scim::groups::patch_group,
+                crate::controllers::scim::groups::delete_group,
             ],
         )
         .mount("/static", FileServer::from(static_root_path))
         .attach(Template::fairing())
-        .attach(DBLdapConn::fairing())
+        .attach(AdHoc::try_on_ignite("LDAP Pool", directory::pool::init))
+        .attach(AdHoc::try_on_ignite(
+            "Audit Sinks",
+            crate::audit::sink::init,
+        ))
+        .attach(AdHoc::try_on_ignite("Mail", crate::notifications::init))
+        .attach(AdHoc::try_on_ignite(
+            "Provisioning",
+            crate::provisioning::init,
+        ))
+        .attach(AdHoc::on_liftoff("LDAP Health Check", |rocket| {
+            Box::pin(async move { directory::pool::spawn_health_check(rocket) })
+        }))
         .attach(DB::init())
         .attach(SessionStorage::init())
         .attach(AdHoc::try_on_ignite("SQLx Migrations", db::run_migrations))
         .attach(crate::config::ad_hoc_config::<HydraConfig>("hydra"))
         .attach(crate::config::ad_hoc_config::<AppConfig>("app"))
+        .attach(crate::config::ad_hoc_config::<ApiConfig>("api"))
+        .manage(DirectoryCache::default())
+        .attach(AdHoc::on_liftoff("Group Reconciliation", |rocket| {
+            Box::pin(async move { crate::reconcile::spawn_drift_check(rocket) })
+        }))
+        .attach(AdHoc::on_liftoff("Provisioning Worker", |rocket| {
+            Box::pin(async move { crate::provisioning::spawn_worker(rocket) })
+        }))
         .attach(crate::config::ad_hoc_config::<WebauthnStaticConfig>(
             "webauthn",
         ))
@@ -0,0 +1,229 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use rocket_db_pools::Connection;
+use sha2::{Digest, Sha256};
+
+use crate::db::{DBApiKey, DB};
+
+/// Keys start with this prefix, which tells them apart from access tokens and makes them easy
+/// to spot for secret scanners.
+pub(crate) const API_KEY_PREFIX: &str = "lgt_";
+
+/// What a key of a service account may access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ApiScope {
+    UsersRead,
+    /// Includes `users:read`.
+    UsersWrite,
+    GroupsRead,
+    /// Includes `groups:read` and the members of all groups.
+    GroupsWrite,
+    /// Reading and changing the members of one group.
+    GroupMembers(i32),
+}
+
+impl ApiScope {
+    /// The scopes not limited to a single group.
+    pub(crate) const GLOBAL: [ApiScope; 4] = [
+        ApiScope::UsersRead,
+        ApiScope::UsersWrite,
+        ApiScope::GroupsRead,
+        ApiScope::GroupsWrite,
+    ];
+
+    /// Whether a key granted this scope may do what `scope` allows.
+    pub(crate) fn includes(&self, scope: ApiScope) -> bool {
+        match (self, scope) {
+            (granted, scope) if *granted == scope => true,
+            (ApiScope::UsersWrite, ApiScope::UsersRead) => true,
+            (ApiScope::GroupsWrite, ApiScope::GroupsRead | ApiScope::GroupMembers(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiScope::UsersRead => f.write_str("users:read"),
+            ApiScope::UsersWrite => f.write_str("users:write"),
+            ApiScope::GroupsRead => f.write_str("groups:read"),
+            ApiScope::GroupsWrite => f.write_str("groups:write"),
+            ApiScope::GroupMembers(group_id) => write!(f, "group:{}:members", group_id),
+        }
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "users:read" => Ok(ApiScope::UsersRead),
+            "users:write" => Ok(ApiScope::UsersWrite),
+            "groups:read" => Ok(ApiScope::GroupsRead),
+            "groups:write" => Ok(ApiScope::GroupsWrite),
+            _ => s
+                .strip_prefix("group:")
+                .and_then(|s| s.strip_suffix(":members"))
+                .and_then(|group_id| group_id.parse().ok())
+                .map(ApiScope::GroupMembers)
+                .ok_or(()),
+        }
+    }
+}
+
+/// Parses the space-separated scopes stored with a key, skipping unknown ones.
+pub(crate) fn parse_scopes(scopes: &str) -> Vec<ApiScope> {
+    scopes
+        .split_whitespace()
+        .filter_map(|scope| scope.parse().ok())
+        .collect()
+}
+
+pub(crate) fn format_scopes(scopes: &[ApiScope]) -> String {
+    scopes
+        .iter()
+        .map(ApiScope::to_string)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Returns a new key, which is only ever shown once, and the digest to store.
+pub(crate) fn generate_api_key() -> (String, String) {
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    const API_KEY_LEN: usize = 40;
+    let mut rng = rand::thread_rng();
+
+    let key: String = API_KEY_PREFIX
+        .chars()
+        .chain((0..API_KEY_LEN).map(|_| {
+            let idx = rng.gen_range(0..CHARSET.len());
+            CHARSET[idx] as char
+        }))
+        .collect();
+    let key_hash = hash_api_key(&key);
+    (key, key_hash)
+}
+
+/// Keys are long random strings, so an unsalted digest is enough to keep them from being
+/// usable when the database leaks.
+pub(crate) fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// A service account authenticated by the key in the `Authorization: Bearer` header. Requests
+/// without a key are forwarded, requests with an unknown or revoked key fail with 401.
+pub(crate) struct ServiceAccount {
+    /// Name audit events are recorded with, `service:<name>`.
+    actor: String,
+    scopes: Vec<ApiScope>,
+}
+
+impl ServiceAccount {
+    pub(crate) fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    pub(crate) fn allows(&self, scope: ApiScope) -> bool {
+        self.scopes.iter().any(|granted| granted.includes(scope))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ServiceAccount {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<ServiceAccount, Self::Error> {
+        let key = match request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .map(str::trim)
+        {
+            Some(key) if key.starts_with(API_KEY_PREFIX) => key,
+            _ => return Outcome::Forward(()),
+        };
+        let mut db = match request.guard::<Connection<DB>>().await {
+            Outcome::Success(db) => db,
+            _ => return Outcome::Failure((Status::ServiceUnavailable, ())),
+        };
+
+        let api_key = match DBApiKey::find_active_by_key_hash(&hash_api_key(key), &mut *db).await {
+            Ok(Some(api_key)) => api_key,
+            Ok(None) => return Outcome::Failure((Status::Unauthorized, ())),
+            Err(_) => return Outcome::Failure((Status::ServiceUnavailable, ())),
+        };
+        if let Err(e) = DBApiKey::touch(api_key.id, &mut *db).await {
+            warn!("Failed to record the use of API key {}: {}", api_key.id, e);
+        }
+        Outcome::Success(ServiceAccount {
+            actor: format!("service:{}", api_key.service_account_name),
+            scopes: parse_scopes(&api_key.scopes),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_round_trip() {
+        for scope in ApiScope::GLOBAL
+            .into_iter()
+            .chain([ApiScope::GroupMembers(42)])
+        {
+            assert_eq!(scope.to_string().parse(), Ok(scope));
+        }
+        assert_eq!("group:7:members".parse(), Ok(ApiScope::GroupMembers(7)));
+    }
+
+    #[test]
+    fn unknown_scopes_are_rejected() {
+        for scope in [
+            "",
+            "users",
+            "users:delete",
+            "group:x:members",
+            "group:7",
+            "group::members",
+            "group:7:members:extra",
+        ] {
+            assert_eq!(scope.parse::<ApiScope>(), Err(()), "{}", scope);
+        }
+        assert_eq!(
+            parse_scopes("users:read  bogus group:3:members"),
+            vec![ApiScope::UsersRead, ApiScope::GroupMembers(3)]
+        );
+    }
+
+    #[test]
+    fn write_scopes_include_read_scopes() {
+        assert!(ApiScope::UsersWrite.includes(ApiScope::UsersRead));
+        assert!(!ApiScope::UsersRead.includes(ApiScope::UsersWrite));
+        assert!(ApiScope::GroupsWrite.includes(ApiScope::GroupsRead));
+        assert!(ApiScope::GroupsWrite.includes(ApiScope::GroupMembers(1)));
+        assert!(!ApiScope::GroupsRead.includes(ApiScope::GroupMembers(1)));
+        assert!(!ApiScope::UsersWrite.includes(ApiScope::GroupsRead));
+    }
+
+    #[test]
+    fn group_member_scopes_are_limited_to_their_group() {
+        assert!(ApiScope::GroupMembers(1).includes(ApiScope::GroupMembers(1)));
+        assert!(!ApiScope::GroupMembers(1).includes(ApiScope::GroupMembers(2)));
+        assert!(!ApiScope::GroupMembers(1).includes(ApiScope::GroupsRead));
+    }
+
+    #[test]
+    fn generated_keys_match_their_hash() {
+        let (key, key_hash) = generate_api_key();
+        assert!(key.starts_with(API_KEY_PREFIX));
+        assert_eq!(hash_api_key(&key), key_hash);
+    }
+}
@@ -1,21 +1,30 @@
-use crate::config::AppConfig;
+use crate::audit::{AuditContext, AuditEventType};
+use crate::config::{AcrConfig, AppConfig, HydraConfig};
+use crate::db::{DBGroup, DBGroupOwner, DB};
+use crate::directory::{Directory, LdapDirectory};
 use crate::error::Error;
-use crate::ldap::get_user_groups;
-use crate::DBLdapConn;
 use hmac::{Hmac, Mac};
+use ory_hydra_client::apis::configuration::Configuration;
 use rand::Rng;
 use rocket::form::validate::Contains;
-use rocket::http::{Cookie, CookieJar, SameSite};
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
 use rocket::outcome::try_outcome;
 use rocket::request::{FromRequest, Outcome};
-use rocket::Request;
-use rocket_db_pools::deadpool_redis::redis::AsyncCommands;
+use rocket::{Request, State};
+use rocket_db_pools::deadpool_redis::redis::{self, AsyncCommands};
 use rocket_db_pools::{deadpool_redis, Connection, Database};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sha2::Sha256;
+use sqlx::pool::PoolConnection;
+use sqlx::Postgres;
 
 type HmacSha256 = Hmac<Sha256>;
 
+/// Seconds a session is kept after it was last used. The index of a user's sessions is kept
+/// as long, so it expires together with the last of them.
+const SESSION_TTL: usize = 30 * 24 * 60 * 60;
+
 #[derive(Database)]
 #[database("session_storage")]
 pub(crate) struct SessionStorage(deadpool_redis::Pool);
@@ -81,19 +90,72 @@ impl Session {
         }
     }
 
+    pub fn authenticated_since(&self) -> Option<chrono::Duration> {
+        chrono::DateTime::parse_from_rfc3339(&self.auth_timestamp)
+            .ok()
+            .map(|auth_timestamp| chrono::Utc::now().signed_duration_since(auth_timestamp))
+    }
+
     async fn save(&self, mut session_storage: Connection<SessionStorage>) -> Result<(), Error> {
         let conn = &mut *session_storage;
         let session_string = serde_json::to_string(self)?;
-        conn.set(self.id.clone(), session_string).await?;
+        conn.set_ex(self.id.clone(), session_string, SESSION_TTL)
+            .await?;
         Ok(())
     }
 
+    pub fn has_second_factor(&self) -> bool {
+        self.completed_auth_steps
+            .iter()
+            .any(|step| step == "totp" || step == "webauthn")
+    }
+
+    /// Maps the completed authentication steps to authentication method references (RFC 8176).
+    pub fn amr(&self) -> Vec<String> {
+        let mut amr: Vec<String> = self
+            .completed_auth_steps
+            .iter()
+            .filter_map(|step| match &**step {
+                "password" => Some("pwd".to_owned()),
+                "totp" => Some("otp".to_owned()),
+                "webauthn" => Some("hwk".to_owned()),
+                _ => None,
+            })
+            .collect();
+        if self.has_second_factor() {
+            amr.push("mfa".to_owned());
+        }
+        amr
+    }
+
+    pub fn acr(&self, acr_config: &AcrConfig) -> String {
+        if self.has_second_factor() {
+            acr_config.multi_factor.clone()
+        } else {
+            acr_config.single_factor.clone()
+        }
+    }
+
+    pub async fn require_step(
+        mut self,
+        step: &str,
+        session_storage: Connection<SessionStorage>,
+    ) -> Result<(), Error> {
+        if !self.missing_auth_steps.iter().any(|x| *x == step) {
+            self.missing_auth_steps.push(step.to_owned());
+        }
+        self.fully_authenticated = false;
+        self.save(session_storage).await
+    }
+
+    /// Marks `step` as done, recording the `method` that was used to complete it.
     pub async fn finish_step(
         mut self,
         step: &str,
+        method: &str,
         session_storage: Connection<SessionStorage>,
     ) -> Result<(), Error> {
-        self.completed_auth_steps.push(step.to_owned());
+        self.completed_auth_steps.push(method.to_owned());
         self.missing_auth_steps.remove(
             self.missing_auth_steps
                 .iter()
@@ -114,7 +176,9 @@ pub(crate) async fn create_session(
 ) -> Result<(), Error> {
     let conn = &mut *session_storage;
     let session_string = serde_json::to_string(session)?;
-    conn.set(&session.id, session_string).await?;
+    conn.set_ex(&session.id, session_string, SESSION_TTL)
+        .await?;
+    index_session(conn, &session.username, &session.id).await?;
 
     let mut mac = HmacSha256::new_from_slice(b"my secret and secure key").unwrap();
     mac.update(session.id.as_bytes());
@@ -128,6 +192,19 @@ pub(crate) async fn create_session(
     Ok(())
 }
 
+pub(crate) async fn delete_session(
+    mut session_storage: Connection<SessionStorage>,
+    session: Session,
+    cookies: &CookieJar<'_>,
+) -> Result<(), Error> {
+    let conn = &mut *session_storage;
+    conn.del::<_, ()>(&session.id).await?;
+    conn.srem::<_, _, ()>(user_sessions_key(&session.username), &session.id)
+        .await?;
+    cookies.remove(Cookie::named("legitima_session"));
+    Ok(())
+}
+
 async fn validate_session(
     mut session_storage: Connection<SessionStorage>,
     cookie_value: String,
@@ -161,7 +238,141 @@ async fn validate_session(
 
     let conn = &mut *session_storage;
     let session_data: String = conn.get(session_id).await?;
-    Ok(Some(serde_json::from_str::<Session>(&session_data[..])?))
+    let session = serde_json::from_str::<Session>(&session_data[..])?;
+    // Every use extends the session and the index. Sessions created before the index existed
+    // are added to it here, so they can be revoked from their first use on.
+    let key = user_sessions_key(&session.username);
+    redis::pipe()
+        .sadd(&key, session_id)
+        .ignore()
+        .expire(&key, SESSION_TTL)
+        .ignore()
+        .expire(session_id, SESSION_TTL)
+        .ignore()
+        .query_async::<_, ()>(conn)
+        .await?;
+    Ok(Some(session))
+}
+
+/// Key of the set of session ids of `username`, which allows revoking all of them.
+fn user_sessions_key(username: &str) -> String {
+    format!("user_sessions:{}", username)
+}
+
+/// Adds a new session to the index of `username`, dropping the ids of expired sessions.
+async fn index_session(
+    conn: &mut deadpool_redis::Connection,
+    username: &str,
+    session_id: &str,
+) -> Result<(), Error> {
+    let key = user_sessions_key(username);
+    let session_ids: Vec<String> = conn.smembers(&key).await?;
+    for indexed_id in session_ids {
+        let exists: bool = conn.exists(&indexed_id).await?;
+        if !exists {
+            conn.srem::<_, _, ()>(&key, &indexed_id).await?;
+        }
+    }
+    conn.sadd::<_, _, ()>(&key, session_id).await?;
+    conn.expire::<_, ()>(&key, SESSION_TTL).await?;
+    Ok(())
+}
+
+/// Revokes the consent given to `client_id` in Hydra or, without it, every consent and login
+/// session of the user. Hydra revokes the access and refresh tokens issued with a consent
+/// along with it.
+pub(crate) async fn revoke_sessions(
+    hydra_config: &HydraConfig,
+    username: &str,
+    client_id: Option<&str>,
+) -> Result<(), Error> {
+    let hydra_configuration: &Configuration = &hydra_config.as_hydra_configuration();
+    ory_hydra_client::apis::o_auth2_api::revoke_o_auth2_consent_sessions(
+        hydra_configuration,
+        username,
+        client_id,
+        Some(client_id.is_none()),
+    )
+    .await?;
+    if client_id.is_none() {
+        ory_hydra_client::apis::o_auth2_api::revoke_o_auth2_login_sessions(
+            hydra_configuration,
+            Some(username),
+            None,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Cuts users off after changes to their account, such as being disabled or losing a
+/// credential: revokes everything in Hydra and deletes their legitima sessions. The session of
+/// the request is kept, so users changing their own account stay logged in here.
+pub(crate) struct SessionRevoker<'r> {
+    hydra_config: &'r HydraConfig,
+    session_storage: Connection<SessionStorage>,
+    session: Option<Session>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SessionRevoker<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<SessionRevoker<'r>, Self::Error> {
+        let hydra_config = try_outcome!(request.guard::<&State<HydraConfig>>().await);
+        let session_storage = match request.guard::<Connection<SessionStorage>>().await {
+            Outcome::Success(session_storage) => session_storage,
+            _ => return Outcome::Failure((Status::ServiceUnavailable, ())),
+        };
+        Outcome::Success(SessionRevoker {
+            hydra_config: hydra_config.inner(),
+            session_storage,
+            session: request.guard::<Session>().await.succeeded(),
+        })
+    }
+}
+
+impl SessionRevoker<'_> {
+    /// Revokes all consent and login sessions of `username` in Hydra and deletes their
+    /// legitima sessions except the one of the request.
+    pub(crate) async fn revoke_all(&mut self, username: &str) -> Result<(), Error> {
+        revoke_sessions(self.hydra_config, username, None).await?;
+        let keep = self
+            .session
+            .as_ref()
+            .filter(|session| session.username == username)
+            .map(|session| session.id.as_str());
+        let conn = &mut *self.session_storage;
+        let key = user_sessions_key(username);
+        let session_ids: Vec<String> = conn.smembers(&key).await?;
+        for session_id in session_ids
+            .iter()
+            .filter(|session_id| Some(session_id.as_str()) != keep)
+        {
+            conn.del::<_, ()>(session_id).await?;
+            conn.srem::<_, _, ()>(&key, session_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Revokes all sessions of `username` because of `reason`, recorded with the revocation.
+    pub(crate) async fn cut_off(
+        &mut self,
+        username: &str,
+        reason: AuditEventType,
+        audit: &AuditContext,
+        connection: &mut PoolConnection<Postgres>,
+    ) -> Result<(), Error> {
+        self.revoke_all(username).await?;
+        audit
+            .record(
+                AuditEventType::SessionsRevoked,
+                username,
+                json!({ "client_id": null, "reason": reason.as_str() }),
+                connection,
+            )
+            .await
+    }
 }
 
 pub(crate) struct User(String);
@@ -195,14 +406,14 @@ impl<'r> FromRequest<'r> for AdminUser {
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<AdminUser, Self::Error> {
         let user = try_outcome!(request.guard::<User>().await);
-        let r = request.guard::<DBLdapConn>().await;
-        let ldap_conn = match r {
-            Outcome::Success(conn) => conn,
+        let r = request.guard::<LdapDirectory<'r>>().await;
+        let directory = match r {
+            Outcome::Success(directory) => directory,
             _ => return Outcome::Forward(()),
         };
         let app_config = request.rocket().state::<AppConfig>().unwrap();
 
-        let user_groups = match get_user_groups(app_config, &ldap_conn, &user.0).await {
+        let user_groups = match directory.get_user_groups(&user.0).await {
             Ok(groups) => groups,
             Err(_) => return Outcome::Forward(()),
         };
@@ -212,3 +423,67 @@ impl<'r> FromRequest<'r> for AdminUser {
         }
     }
 }
+
+/// A user owning at least one legitima group, either through `group_owner` or the owner
+/// attribute of the LDAP group. Owners may only manage the members of their own groups.
+pub(crate) struct GroupOwner {
+    group_ids: Vec<i32>,
+}
+
+impl GroupOwner {
+    pub(crate) fn group_ids(&self) -> &[i32] {
+        &self.group_ids
+    }
+
+    pub(crate) fn owns(&self, group_id: i32) -> bool {
+        self.group_ids.contains(&group_id)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for GroupOwner {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<GroupOwner, Self::Error> {
+        let user = try_outcome!(request.guard::<User>().await);
+        let directory = match request.guard::<LdapDirectory<'r>>().await {
+            Outcome::Success(directory) => directory,
+            _ => return Outcome::Forward(()),
+        };
+        let mut db = match request.guard::<Connection<DB>>().await {
+            Outcome::Success(db) => db,
+            _ => return Outcome::Forward(()),
+        };
+
+        let group_ids = match owned_group_ids(&user.0, &directory, &mut *db).await {
+            Ok(group_ids) => group_ids,
+            Err(_) => return Outcome::Forward(()),
+        };
+        match group_ids.is_empty() {
+            true => Outcome::Forward(()),
+            false => Outcome::Success(GroupOwner { group_ids }),
+        }
+    }
+}
+
+/// IDs of the legitima groups `username` owns, either through `group_owner` or the owner
+/// attribute of the LDAP group.
+pub(crate) async fn owned_group_ids(
+    username: &str,
+    directory: &dyn Directory,
+    connection: &mut PoolConnection<Postgres>,
+) -> Result<Vec<i32>, Error> {
+    let mut group_ids =
+        DBGroupOwner::list_group_ids_by_username(username, &mut *connection).await?;
+    let owned_group_dns = directory.get_owned_groups(username).await?;
+    if !owned_group_dns.is_empty() {
+        group_ids.extend(
+            DBGroup::list_all(&mut *connection)
+                .await?
+                .into_iter()
+                .filter(|group| owned_group_dns.contains(&group.ldap_dn))
+                .filter_map(|group| group.id),
+        );
+    }
+    Ok(group_ids)
+}